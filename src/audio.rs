@@ -0,0 +1,331 @@
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+#[cfg(feature = "audio")]
+use std::collections::VecDeque;
+#[cfg(feature = "audio")]
+use std::sync::{mpsc, Arc, Mutex};
+#[cfg(feature = "audio")]
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+
+// wav file parameters
+const WAV_HEADER_SIZE: u32 = 44;
+const WAV_CHANNELS: u16 = 2;
+const WAV_BITS_PER_SAMPLE: u16 = 16;
+
+// playback buffer parameters
+#[cfg(feature = "audio")]
+const PLAYBACK_LATENCY_IN_MS: usize = 100;
+// the resampling ratio is adjusted by at most 0.5% which can't be heard
+#[cfg(feature = "audio")]
+const MAX_RATE_DELTA: f64 = 0.005;
+
+// interleaved stereo samples waiting to be played
+#[cfg(feature = "audio")]
+type SampleBuffer = Arc<Mutex<VecDeque<f32>>>;
+
+// an audio sink receives interleaved stereo samples at the emulated sample rate,
+// it is sent along with the emulator to other threads
+pub trait AudioSink: Send {
+    fn push_samples(&mut self, samples: &[f32]) -> std::io::Result<()>;
+}
+
+// 16 bits pcm stereo wav file writer
+pub struct WavSink {
+    file: BufWriter<File>,
+    data_size: u32,
+}
+
+impl WavSink {
+    pub fn new<P: AsRef<Path>>(path: P, sample_rate: u32) -> std::io::Result<WavSink> {
+        let mut file = BufWriter::new(File::create(path)?);
+
+        // chunk sizes are updated when the file is closed
+        let block_align = WAV_CHANNELS * WAV_BITS_PER_SAMPLE / 8;
+        file.write_all(b"RIFF")?;
+        file.write_all(&(WAV_HEADER_SIZE - 8).to_le_bytes())?;
+        file.write_all(b"WAVE")?;
+        file.write_all(b"fmt ")?;
+        file.write_all(&16u32.to_le_bytes())?;
+        file.write_all(&1u16.to_le_bytes())?; // pcm format
+        file.write_all(&WAV_CHANNELS.to_le_bytes())?;
+        file.write_all(&sample_rate.to_le_bytes())?;
+        file.write_all(&(sample_rate * block_align as u32).to_le_bytes())?;
+        file.write_all(&block_align.to_le_bytes())?;
+        file.write_all(&WAV_BITS_PER_SAMPLE.to_le_bytes())?;
+        file.write_all(b"data")?;
+        file.write_all(&0u32.to_le_bytes())?;
+
+        Ok(WavSink {
+            file,
+            data_size: 0,
+        })
+    }
+
+    // write the chunk sizes in the header
+    pub fn finish(&mut self) -> std::io::Result<()> {
+        self.file.seek(SeekFrom::Start(4))?;
+        self.file.write_all(&(WAV_HEADER_SIZE - 8 + self.data_size).to_le_bytes())?;
+        self.file.seek(SeekFrom::Start(WAV_HEADER_SIZE as u64 - 4))?;
+        self.file.write_all(&self.data_size.to_le_bytes())?;
+        self.file.seek(SeekFrom::End(0))?;
+        self.file.flush()
+    }
+}
+
+impl AudioSink for WavSink {
+    fn push_samples(&mut self, samples: &[f32]) -> std::io::Result<()> {
+        for sample in samples {
+            let value = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+            self.file.write_all(&value.to_le_bytes())?;
+        }
+        self.data_size += (samples.len() * 2) as u32;
+
+        Ok(())
+    }
+}
+
+impl Drop for WavSink {
+    fn drop(&mut self) {
+        if let Err(message) = self.finish() {
+            println!("Cannot close wav file with error message: {}", message);
+        }
+    }
+}
+
+// linear interpolation between stereo frames
+pub struct Resampler {
+    // position of the next output frame between the previous and the current input frame
+    position: f64,
+    previous: [f32; 2],
+}
+
+impl Resampler {
+    pub fn new() -> Resampler {
+        Resampler {
+            position: 0.0,
+            previous: [0.0; 2],
+        }
+    }
+
+    // ratio is the number of output frames produced for each input frame
+    pub fn process(&mut self, input: &[f32], ratio: f64, output: &mut Vec<f32>) {
+        let step = 1.0 / ratio;
+
+        for frame in input.chunks_exact(2) {
+            while self.position < 1.0 {
+                let t = self.position as f32;
+                output.push(self.previous[0] + (frame[0] - self.previous[0]) * t);
+                output.push(self.previous[1] + (frame[1] - self.previous[1]) * t);
+                self.position += step;
+            }
+
+            self.position -= 1.0;
+            self.previous = [frame[0], frame[1]];
+        }
+    }
+}
+
+impl Default for Resampler {
+    fn default() -> Self {
+        Resampler::new()
+    }
+}
+
+// compute the resampling ratio which brings the buffer back to half full:
+// the emulator produces slightly more samples when the buffer drains and
+// slightly less when it fills up, instead of dropping or repeating samples
+pub fn get_dynamic_ratio(base_ratio: f64, buffer_level: usize, buffer_capacity: usize, max_delta: f64) -> f64 {
+    let fill = (buffer_level as f64 / buffer_capacity as f64).min(1.0);
+
+    base_ratio * (1.0 + max_delta * (1.0 - 2.0 * fill))
+}
+
+// live playback on the default output device of the host
+#[cfg(feature = "audio")]
+pub struct PlaybackSink {
+    // the stream thread stops once this sender is dropped
+    _stream_owner: mpsc::Sender<()>,
+    buffer: SampleBuffer,
+    capacity: usize,
+    base_ratio: f64,
+    resampler: Resampler,
+    resampled: Vec<f32>,
+}
+
+#[cfg(feature = "audio")]
+impl PlaybackSink {
+    pub fn new(sample_rate: u32) -> Result<PlaybackSink, String> {
+        // a cpal stream can't leave the thread which has opened it, it lives in its own
+        // thread so that the sink can be sent to another thread with the emulator
+        let (open_sender, open_receiver) = mpsc::channel();
+        let (stream_owner, stop_receiver) = mpsc::channel::<()>();
+        std::thread::spawn(move || {
+            match open_stream() {
+                Ok((stream, host_sample_rate, capacity, buffer)) => {
+                    let _ = open_sender.send(Ok((host_sample_rate, capacity, buffer)));
+                    // returns once the sink is dropped
+                    let _ = stop_receiver.recv();
+                    drop(stream);
+                }
+                Err(message) => {
+                    let _ = open_sender.send(Err(message));
+                }
+            }
+        });
+        let (host_sample_rate, capacity, buffer) = open_receiver.recv().map_err(|error| error.to_string())??;
+
+        Ok(PlaybackSink {
+            _stream_owner: stream_owner,
+            buffer,
+            capacity,
+            base_ratio: host_sample_rate as f64 / sample_rate as f64,
+            resampler: Resampler::new(),
+            resampled: Vec::new(),
+        })
+    }
+}
+
+// open and start the stream of the default output device, returns the stream, its sample rate,
+// the capacity of its buffer and the buffer it reads
+#[cfg(feature = "audio")]
+fn open_stream() -> Result<(cpal::Stream, u32, usize, SampleBuffer), String> {
+    let host = cpal::default_host();
+    let device = host.default_output_device().ok_or("no audio output device")?;
+    let config = device.default_output_config().map_err(|error| error.to_string())?;
+
+    let host_sample_rate = config.sample_rate().0;
+    let capacity = 2 * host_sample_rate as usize * PLAYBACK_LATENCY_IN_MS * 2 / 1000;
+    let buffer = Arc::new(Mutex::new(VecDeque::with_capacity(capacity)));
+
+    let stream = match config.sample_format() {
+        cpal::SampleFormat::F32 => build_stream::<f32>(&device, &config.config(), &buffer),
+        cpal::SampleFormat::I16 => build_stream::<i16>(&device, &config.config(), &buffer),
+        cpal::SampleFormat::U16 => build_stream::<u16>(&device, &config.config(), &buffer),
+        format => return Err(format!("unsupported sample format {}", format)),
+    }?;
+    stream.play().map_err(|error| error.to_string())?;
+
+    Ok((stream, host_sample_rate, capacity, buffer))
+}
+
+#[cfg(feature = "audio")]
+fn build_stream<T>(device: &cpal::Device, config: &cpal::StreamConfig, buffer: &SampleBuffer) -> Result<cpal::Stream, String>
+where
+    T: cpal::SizedSample + cpal::FromSample<f32>,
+{
+    let channels = config.channels as usize;
+    let buffer = Arc::clone(buffer);
+    let mut last_frame = [0.0; 2];
+
+    device.build_output_stream(
+        config,
+        move |data: &mut [T], _: &cpal::OutputCallbackInfo| {
+            let mut buffer = buffer.lock().unwrap();
+            for frame in data.chunks_mut(channels) {
+                // on underrun, hold the last frame to avoid clicks
+                if buffer.len() >= 2 {
+                    last_frame = [buffer.pop_front().unwrap(), buffer.pop_front().unwrap()];
+                }
+
+                for (channel, sample) in frame.iter_mut().enumerate() {
+                    let value = match (channels, channel) {
+                        (1, _) => (last_frame[0] + last_frame[1]) / 2.0,
+                        (_, 0) | (_, 1) => last_frame[channel],
+                        _ => 0.0,
+                    };
+                    *sample = T::from_sample(value);
+                }
+            }
+        },
+        |error| println!("Audio stream error: {}", error),
+        None,
+    )
+    .map_err(|error| error.to_string())
+}
+
+#[cfg(feature = "audio")]
+impl AudioSink for PlaybackSink {
+    fn push_samples(&mut self, samples: &[f32]) -> std::io::Result<()> {
+        let level = self.buffer.lock().unwrap().len();
+        let ratio = get_dynamic_ratio(self.base_ratio, level, self.capacity, MAX_RATE_DELTA);
+
+        self.resampled.clear();
+        self.resampler.process(samples, ratio, &mut self.resampled);
+
+        let mut buffer = self.buffer.lock().unwrap();
+        buffer.extend(self.resampled.iter());
+        // on overrun, drop the oldest frames to bound the latency
+        while buffer.len() > self.capacity {
+            buffer.pop_front();
+            buffer.pop_front();
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod audio_tests {
+    use super::*;
+
+    #[test]
+    fn test_wav_sink() {
+        let path = std::env::temp_dir().join("qoboy_test_wav_sink.wav");
+
+        {
+            let mut sink = WavSink::new(&path, 48000).unwrap();
+            sink.push_samples(&[0.0, 1.0, -1.0, 0.5]).unwrap();
+        }
+
+        let data = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(data.len(), 44 + 8);
+        assert_eq!(&data[0..4], b"RIFF");
+        assert_eq!(u32::from_le_bytes([data[4], data[5], data[6], data[7]]), 36 + 8);
+        assert_eq!(&data[8..16], b"WAVEfmt ");
+        assert_eq!(u32::from_le_bytes([data[24], data[25], data[26], data[27]]), 48000);
+        assert_eq!(&data[36..40], b"data");
+        assert_eq!(u32::from_le_bytes([data[40], data[41], data[42], data[43]]), 8);
+        assert_eq!(i16::from_le_bytes([data[46], data[47]]), i16::MAX);
+        assert_eq!(i16::from_le_bytes([data[48], data[49]]), -i16::MAX);
+    }
+
+    #[test]
+    fn test_resampler() {
+        let input: Vec<f32> = (0..1000).flat_map(|i| [i as f32, -(i as f32)]).collect();
+
+        // same rate, the output is the input delayed by one frame
+        let mut resampler = Resampler::new();
+        let mut output = Vec::new();
+        resampler.process(&input, 1.0, &mut output);
+        assert_eq!(output.len(), input.len());
+        assert_eq!(output[2..], input[..input.len() - 2]);
+
+        // upsampling interpolates between input frames
+        let mut resampler = Resampler::new();
+        let mut output = Vec::new();
+        resampler.process(&input, 2.0, &mut output);
+        assert_eq!(output.len(), 2 * input.len());
+        assert_eq!(output[6..12], [0.5, -0.5, 1.0, -1.0, 1.5, -1.5]);
+
+        // the output length follows the ratio
+        let mut resampler = Resampler::new();
+        let mut output = Vec::new();
+        resampler.process(&input, 44100.0 / 48000.0, &mut output);
+        assert!((output.len() as i32 / 2 - 919).abs() <= 1);
+    }
+
+    #[test]
+    fn test_dynamic_ratio() {
+        // the ratio is not changed when the buffer is half full
+        assert_eq!(get_dynamic_ratio(1.0, 50, 100, 0.005), 1.0);
+        // more samples are produced when the buffer is empty
+        assert_eq!(get_dynamic_ratio(1.0, 0, 100, 0.005), 1.005);
+        // less samples are produced when the buffer is full
+        assert_eq!(get_dynamic_ratio(1.0, 100, 100, 0.005), 0.995);
+        assert_eq!(get_dynamic_ratio(1.0, 500, 100, 0.005), 0.995);
+    }
+}
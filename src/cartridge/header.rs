@@ -0,0 +1,293 @@
+use crate::cartridge::{MbcType, RomSize, RamSize, get_mbc_type, get_rom_size, get_ram_size};
+use crate::cartridge::{CARTRIDGE_TYPE_OFFSET, CARTRIDGE_ROM_SIZE_OFFSET, CARTRIDGE_RAM_SIZE_OFFSET, CARTRIDGE_HEADER_CHECKSUM_OFFSET};
+use crate::error::EmulatorError;
+
+pub const CARTRIDGE_LOGO_OFFSET: u16 = 0x104;
+pub const CARTRIDGE_LOGO_SIZE: usize = 48;
+pub const CARTRIDGE_TITLE_OFFSET: u16 = 0x134;
+pub const CARTRIDGE_MANUFACTURER_CODE_OFFSET: u16 = 0x13F;
+pub const CARTRIDGE_CGB_FLAG_OFFSET: u16 = 0x143;
+pub const CARTRIDGE_NEW_LICENSEE_CODE_OFFSET: u16 = 0x144;
+pub const CARTRIDGE_SGB_FLAG_OFFSET: u16 = 0x146;
+pub const CARTRIDGE_DESTINATION_OFFSET: u16 = 0x14A;
+pub const CARTRIDGE_OLD_LICENSEE_CODE_OFFSET: u16 = 0x14B;
+pub const CARTRIDGE_VERSION_OFFSET: u16 = 0x14C;
+pub const CARTRIDGE_GLOBAL_CHECKSUM_OFFSET: u16 = 0x14E;
+pub const CARTRIDGE_HEADER_END: u16 = 0x150;
+
+// logo checked by the boot rom, each game of a multicart has its own copy
+pub const NINTENDO_LOGO: [u8; CARTRIDGE_LOGO_SIZE] = [
+    0xCE, 0xED, 0x66, 0x66, 0xCC, 0x0D, 0x00, 0x0B, 0x03, 0x73, 0x00, 0x83, 0x00, 0x0C, 0x00, 0x0D,
+    0x00, 0x08, 0x11, 0x1F, 0x88, 0x89, 0x00, 0x0E, 0xDC, 0xCC, 0x6E, 0xE6, 0xDD, 0xDD, 0xD9, 0x99,
+    0xBB, 0xBB, 0x67, 0x63, 0x6E, 0x0E, 0xEC, 0xCC, 0xDD, 0xDC, 0x99, 0x9F, 0xBB, 0xB9, 0x33, 0x3E,
+];
+
+// the new licensee code is used when the old one holds this value
+const USE_NEW_LICENSEE_CODE: u8 = 0x33;
+
+/// Color gameboy support announced by the cartridge.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum CgbSupport {
+    /// Game made for the original gameboy.
+    None,
+    /// Game with color enhancements which also runs on the original gameboy.
+    Enhanced,
+    /// Game which only runs on the color gameboy.
+    Required,
+}
+
+/// Market the cartridge has been sold on.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Destination {
+    Japan,
+    Overseas,
+    Unknown(u8),
+}
+
+/// Content of the cartridge header, from 0x0104 to 0x014F.
+#[derive(Clone, PartialEq, Debug)]
+pub struct CartridgeHeader {
+    pub logo: [u8; CARTRIDGE_LOGO_SIZE],
+    pub title: String,
+    /// Four letters code of the newer cartridges.
+    pub manufacturer_code: Option<String>,
+    pub cgb_flag: u8,
+    pub sgb_support: bool,
+    pub old_licensee_code: u8,
+    pub new_licensee_code: String,
+    pub destination: Destination,
+    pub version: u8,
+    pub cartridge_type: u8,
+    pub rom_size_code: u8,
+    pub ram_size_code: u8,
+    pub header_checksum: u8,
+    pub global_checksum: u16,
+    // computed from the rom content
+    rom_length: usize,
+    expected_header_checksum: u8,
+    expected_global_checksum: u16,
+}
+
+impl CartridgeHeader {
+    pub fn parse(rom: &[u8]) -> Result<CartridgeHeader, EmulatorError> {
+        if rom.len() < CARTRIDGE_HEADER_END as usize {
+            return Err(EmulatorError::RomTooSmall(rom.len()));
+        }
+
+        let mut logo = [0; CARTRIDGE_LOGO_SIZE];
+        logo.copy_from_slice(&rom[CARTRIDGE_LOGO_OFFSET as usize..CARTRIDGE_LOGO_OFFSET as usize + CARTRIDGE_LOGO_SIZE]);
+
+        // the end of the title area was reused by the color gameboy for its flag, then for a
+        // manufacturer code: the code is only detected as 4 uppercase letters or digits
+        let cgb_flag = rom[CARTRIDGE_CGB_FLAG_OFFSET as usize];
+        let manufacturer_code = &rom[CARTRIDGE_MANUFACTURER_CODE_OFFSET as usize..CARTRIDGE_CGB_FLAG_OFFSET as usize];
+        let has_manufacturer_code = cgb_flag & 0x80 != 0
+            && manufacturer_code.iter().all(|data| data.is_ascii_uppercase() || data.is_ascii_digit());
+        let title_end = if has_manufacturer_code {
+            CARTRIDGE_MANUFACTURER_CODE_OFFSET
+        } else if cgb_flag & 0x80 != 0 {
+            CARTRIDGE_CGB_FLAG_OFFSET
+        } else {
+            CARTRIDGE_NEW_LICENSEE_CODE_OFFSET
+        };
+
+        let header_checksum_area = &rom[CARTRIDGE_TITLE_OFFSET as usize..CARTRIDGE_HEADER_CHECKSUM_OFFSET as usize];
+        let global_checksum_offset = CARTRIDGE_GLOBAL_CHECKSUM_OFFSET as usize;
+
+        Ok(CartridgeHeader {
+            logo,
+            title: get_text(&rom[CARTRIDGE_TITLE_OFFSET as usize..title_end as usize]),
+            manufacturer_code: if has_manufacturer_code { Some(get_text(manufacturer_code)) } else { None },
+            cgb_flag,
+            sgb_support: rom[CARTRIDGE_SGB_FLAG_OFFSET as usize] == 0x03,
+            old_licensee_code: rom[CARTRIDGE_OLD_LICENSEE_CODE_OFFSET as usize],
+            new_licensee_code: get_text(&rom[CARTRIDGE_NEW_LICENSEE_CODE_OFFSET as usize..CARTRIDGE_SGB_FLAG_OFFSET as usize]),
+            destination: match rom[CARTRIDGE_DESTINATION_OFFSET as usize] {
+                0x00 => Destination::Japan,
+                0x01 => Destination::Overseas,
+                code => Destination::Unknown(code),
+            },
+            version: rom[CARTRIDGE_VERSION_OFFSET as usize],
+            cartridge_type: rom[CARTRIDGE_TYPE_OFFSET as usize],
+            rom_size_code: rom[CARTRIDGE_ROM_SIZE_OFFSET as usize],
+            ram_size_code: rom[CARTRIDGE_RAM_SIZE_OFFSET as usize],
+            header_checksum: rom[CARTRIDGE_HEADER_CHECKSUM_OFFSET as usize],
+            global_checksum: u16::from_be_bytes([rom[global_checksum_offset], rom[global_checksum_offset + 1]]),
+            rom_length: rom.len(),
+            expected_header_checksum: header_checksum_area.iter().fold(0, |checksum: u8, &data| checksum.wrapping_sub(data).wrapping_sub(1)),
+            // sum of all the bytes of the rom but the global checksum itself
+            expected_global_checksum: rom.iter()
+                .enumerate()
+                .filter(|(offset, _)| *offset != global_checksum_offset && *offset != global_checksum_offset + 1)
+                .fold(0, |checksum: u16, (_, &data)| checksum.wrapping_add(data as u16)),
+        })
+    }
+
+    pub fn get_cgb_support(&self) -> CgbSupport {
+        match self.cgb_flag {
+            0xC0 => CgbSupport::Required,
+            0x80 => CgbSupport::Enhanced,
+            _ => CgbSupport::None,
+        }
+    }
+
+    // two letters code of the newer cartridges, hexadecimal value of the older ones
+    pub fn get_licensee_code(&self) -> String {
+        if self.old_licensee_code == USE_NEW_LICENSEE_CODE {
+            self.new_licensee_code.clone()
+        } else {
+            format!("{:02X}", self.old_licensee_code)
+        }
+    }
+
+    pub fn get_mbc_type(&self) -> Result<MbcType, EmulatorError> {
+        get_mbc_type(self.cartridge_type)
+    }
+
+    pub fn get_rom_size(&self) -> Result<RomSize, EmulatorError> {
+        get_rom_size(self.rom_size_code)
+    }
+
+    pub fn get_ram_size(&self) -> Result<RamSize, EmulatorError> {
+        get_ram_size(self.ram_size_code)
+    }
+
+    /// The original boot rom locks up on a cartridge with an invalid header checksum.
+    pub fn is_header_checksum_valid(&self) -> bool {
+        self.header_checksum == self.expected_header_checksum
+    }
+
+    /// The global checksum is not verified by the gameboy, homebrews often leave it empty.
+    pub fn is_global_checksum_valid(&self) -> bool {
+        self.global_checksum == self.expected_global_checksum
+    }
+
+    pub fn get_expected_header_checksum(&self) -> u8 {
+        self.expected_header_checksum
+    }
+
+    pub fn get_expected_global_checksum(&self) -> u16 {
+        self.expected_global_checksum
+    }
+
+    /// Inconsistencies between the header and the rom content which can prevent the game
+    /// from running, the global checksum is left out.
+    pub fn get_warnings(&self) -> Vec<String> {
+        let mut warnings = Vec::new();
+
+        if let Ok(rom_size) = self.get_rom_size() {
+            if self.rom_length != rom_size as usize {
+                warnings.push(format!("rom file is {} bytes long but the header announces {} bytes", self.rom_length, rom_size as usize));
+            }
+        }
+
+        if !self.is_header_checksum_valid() {
+            warnings.push(format!("header checksum is {:#04x} instead of {:#04x}", self.header_checksum, self.expected_header_checksum));
+        }
+
+        warnings
+    }
+}
+
+// header texts are ascii, padded with zeros
+fn get_text(bytes: &[u8]) -> String {
+    bytes.iter()
+        .take_while(|&&data| data != 0x00)
+        .map(|&data| if data.is_ascii_graphic() || data == b' ' { data as char } else { '?' })
+        .collect::<String>()
+        .trim_end()
+        .to_string()
+}
+
+#[cfg(test)]
+mod header_tests {
+    use super::*;
+
+    fn build_rom(title: &[u8]) -> Vec<u8> {
+        let mut rom = vec![0x00; 0x8000];
+        rom[CARTRIDGE_TITLE_OFFSET as usize..CARTRIDGE_TITLE_OFFSET as usize + title.len()].copy_from_slice(title);
+        rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x03;
+        rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
+        rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x02;
+        rom[CARTRIDGE_DESTINATION_OFFSET as usize] = 0x01;
+        rom[CARTRIDGE_OLD_LICENSEE_CODE_OFFSET as usize] = 0x01;
+        rom[CARTRIDGE_VERSION_OFFSET as usize] = 0x02;
+        rom
+    }
+
+    // write the checksums expected by the header
+    fn sign_rom(rom: &mut [u8]) {
+        let header = CartridgeHeader::parse(rom).unwrap();
+        rom[CARTRIDGE_HEADER_CHECKSUM_OFFSET as usize] = header.get_expected_header_checksum();
+
+        let header = CartridgeHeader::parse(rom).unwrap();
+        let global_checksum = header.get_expected_global_checksum().to_be_bytes();
+        rom[CARTRIDGE_GLOBAL_CHECKSUM_OFFSET as usize..CARTRIDGE_HEADER_END as usize].copy_from_slice(&global_checksum);
+    }
+
+    #[test]
+    fn test_parse() {
+        let mut rom = build_rom(b"QOBOY TEST");
+        rom[CARTRIDGE_LOGO_OFFSET as usize] = 0xCE;
+        sign_rom(&mut rom);
+
+        let header = CartridgeHeader::parse(&rom).unwrap();
+        assert_eq!(header.logo[0], 0xCE);
+        assert_eq!(header.title, "QOBOY TEST");
+        assert_eq!(header.manufacturer_code, None);
+        assert_eq!(header.get_cgb_support(), CgbSupport::None);
+        assert!(!header.sgb_support);
+        assert_eq!(header.get_licensee_code(), "01");
+        assert_eq!(header.destination, Destination::Overseas);
+        assert_eq!(header.version, 0x02);
+        assert_eq!(header.get_mbc_type(), Ok(MbcType::MBC_1_RAM_BAT));
+        assert!(header.is_header_checksum_valid());
+        assert!(header.is_global_checksum_valid());
+        assert!(header.get_warnings().is_empty());
+
+        // the global checksum covers the whole rom, it is not reported as a warning
+        rom[0x2000] = 0x01;
+        let header = CartridgeHeader::parse(&rom).unwrap();
+        assert!(!header.is_global_checksum_valid());
+        assert!(header.get_warnings().is_empty());
+    }
+
+    #[test]
+    fn test_parse_cgb() {
+        let mut rom = build_rom(b"COLORGAMEABQOBY\xC0");
+        rom[CARTRIDGE_NEW_LICENSEE_CODE_OFFSET as usize..CARTRIDGE_SGB_FLAG_OFFSET as usize].copy_from_slice(b"01");
+        rom[CARTRIDGE_SGB_FLAG_OFFSET as usize] = 0x03;
+        rom[CARTRIDGE_OLD_LICENSEE_CODE_OFFSET as usize] = 0x33;
+
+        let header = CartridgeHeader::parse(&rom).unwrap();
+        assert_eq!(header.title, "COLORGAMEAB");
+        assert_eq!(header.manufacturer_code, Some(String::from("QOBY")));
+        assert_eq!(header.get_cgb_support(), CgbSupport::Required);
+        assert!(header.sgb_support);
+        assert_eq!(header.get_licensee_code(), "01");
+
+        // a lower case end of title is not a manufacturer code
+        let rom = build_rom(b"QOBOYCOLOR game\x80");
+        let header = CartridgeHeader::parse(&rom).unwrap();
+        assert_eq!(header.title, "QOBOYCOLOR game");
+        assert_eq!(header.manufacturer_code, None);
+        assert_eq!(header.get_cgb_support(), CgbSupport::Enhanced);
+    }
+
+    #[test]
+    fn test_warnings() {
+        let mut rom = build_rom(b"QOBOY TEST");
+        sign_rom(&mut rom);
+        rom.truncate(0x4000);
+
+        let header = CartridgeHeader::parse(&rom).unwrap();
+        assert_eq!(header.get_warnings(), vec![String::from("rom file is 16384 bytes long but the header announces 32768 bytes")]);
+
+        rom[CARTRIDGE_HEADER_CHECKSUM_OFFSET as usize] = 0x42;
+        let header = CartridgeHeader::parse(&rom).unwrap();
+        assert!(!header.is_header_checksum_valid());
+        assert_eq!(header.get_warnings()[1], format!("header checksum is 0x42 instead of {:#04x}", header.get_expected_header_checksum()));
+
+        assert_eq!(CartridgeHeader::parse(&rom[..0x14F]), Err(EmulatorError::RomTooSmall(0x14F)));
+    }
+}
@@ -0,0 +1,213 @@
+use crate::cartridge::{MbcType, RomSize, RamSize, Mbc};
+use crate::savestate::{SaveState, StateWriter, StateReader};
+
+const RAM_IR_SELECT_SPACE_START: u16 = 0x0000;
+const RAM_IR_SELECT_SPACE_END: u16 = 0x1FFF;
+
+const ROM_BANK_NB_SPACE_START: u16 = 0x2000;
+const ROM_BANK_NB_SPACE_END: u16 = 0x3FFF;
+
+const RAM_BANK_NB_SPACE_START: u16 = 0x4000;
+const RAM_BANK_NB_SPACE_END: u16 = 0x5FFF;
+
+const UNUSED_SPACE_START: u16 = 0x6000;
+const UNUSED_SPACE_END: u16 = 0x7FFF;
+
+// any other value maps the ram
+const SELECT_IR_FLAG: u8 = 0x0E;
+
+// the ir receiver never sees any light, there is no other gameboy in front of it
+const IR_NO_LIGHT: u8 = 0xC0;
+
+const GB_ADDR_BIT_MASK: usize = 0x3FFF;
+const RAM_ADDR_BIT_MASK: usize = 0x1FFF;
+const ROM_BANK_BIT_OFFSET: usize = 14;
+const RAM_BANK_BIT_OFFSET: usize = 13;
+
+pub struct Huc1 {
+    // config
+    battery: bool,
+    // internal registers
+    ir_mode: bool,
+    rom_bank_number: u8,
+    ram_bank_number: u8,
+    // infrared led, lit by the game
+    ir_led: bool,
+    // memory
+    rom_bank: Vec<u8>,
+    ram_bank: Vec<u8>,
+}
+
+impl Huc1 {
+    pub fn new(mbc_type: MbcType, rom_size: RomSize, ram_size: RamSize, rom: &[u8]) -> Huc1 {
+        let mut rom_bank: Vec<u8> = vec![0xFF; rom_size as usize];
+        let ram_bank: Vec<u8> = vec![0xFF; ram_size as usize];
+
+        // copy all rom data
+        let size = rom.len().min(rom_bank.len());
+        rom_bank[..size].copy_from_slice(&rom[..size]);
+
+        Huc1 {
+            // config
+            battery: mbc_type.has_battery(),
+            // internal registers
+            ir_mode: false,
+            rom_bank_number: 1,
+            ram_bank_number: 0,
+            ir_led: false,
+            // memory
+            rom_bank,
+            ram_bank,
+        }
+    }
+
+    // the bank number wraps around the number of banks of the ram
+    fn get_ram_address(&self, address: usize) -> usize {
+        let gb_addr = ((self.ram_bank_number as usize) << RAM_BANK_BIT_OFFSET)
+                            | (address & RAM_ADDR_BIT_MASK);
+        gb_addr % self.ram_bank.len()
+    }
+}
+
+impl Mbc for Huc1 {
+    fn read_bank_0 (&self, address: usize) -> u8 {
+        let gb_addr = address & GB_ADDR_BIT_MASK;
+        self.rom_bank[gb_addr]
+    }
+
+    fn read_bank_n (&self, address: usize) -> u8 {
+        let gb_addr = ((self.rom_bank_number as usize) << ROM_BANK_BIT_OFFSET)
+                            | (address & GB_ADDR_BIT_MASK);
+        self.rom_bank[gb_addr % self.rom_bank.len()]
+    }
+
+    fn read_ram (&self, address: usize) -> u8 {
+        if self.ir_mode {
+            IR_NO_LIGHT
+        } else if !self.ram_bank.is_empty() {
+            // the ram doesn't need to be enabled
+            self.ram_bank[self.get_ram_address(address)]
+        } else {
+            0xFF
+        }
+    }
+
+    fn write_bank_0 (&mut self, address: usize, data: u8) {
+        match address as u16 {
+            RAM_IR_SELECT_SPACE_START..=RAM_IR_SELECT_SPACE_END => {
+                self.ir_mode = (data & 0x0F) == SELECT_IR_FLAG;
+            },
+            ROM_BANK_NB_SPACE_START..=ROM_BANK_NB_SPACE_END => {
+                self.rom_bank_number = if data & 0x3F != 0 {
+                    data & 0x3F
+                } else {
+                    // if register is set to 0, set it to 1
+                    1
+                };
+            },
+            _ => panic!("huc 1 bank 0 address {:x} doesn't exists.", address),
+        }
+    }
+
+    fn write_bank_n (&mut self, address: usize, data: u8) {
+        match address as u16 {
+            RAM_BANK_NB_SPACE_START..=RAM_BANK_NB_SPACE_END => {
+                self.ram_bank_number = data & 0x03;
+            },
+            UNUSED_SPACE_START..=UNUSED_SPACE_END => {/* do nothing here */},
+            _ => panic!("huc 1 bank n address {:x} doesn't exists.", address),
+        }
+    }
+
+    fn write_ram (&mut self, address: usize, data: u8) -> bool {
+        if self.ir_mode {
+            self.ir_led = (data & 0x01) != 0;
+            false
+        } else if !self.ram_bank.is_empty() {
+            let gb_addr = self.get_ram_address(address);
+            self.ram_bank[gb_addr] = data;
+            true
+        } else {
+            false
+        }
+    }
+
+    // not used for this mbc, doesn't do anything
+    fn run (&mut self, _: u8) {}
+
+    fn has_battery (&self) -> bool {
+        self.battery
+    }
+
+    fn dump_ram (&self) -> Vec<u8> {
+        self.ram_bank.clone()
+    }
+
+    fn load_ram (&mut self, data: &[u8]) {
+        let size = data.len().min(self.ram_bank.len());
+        self.ram_bank[..size].copy_from_slice(&data[..size]);
+    }
+}
+
+impl SaveState for Huc1 {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bool(self.ir_mode);
+        writer.write_u8(self.rom_bank_number);
+        writer.write_u8(self.ram_bank_number);
+        writer.write_bool(self.ir_led);
+        writer.write_bytes(&self.ram_bank);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> std::io::Result<()> {
+        self.ir_mode = reader.read_bool()?;
+        self.rom_bank_number = reader.read_u8()?;
+        self.ram_bank_number = reader.read_u8()?;
+        self.ir_led = reader.read_bool()?;
+        reader.read_bytes_into(&mut self.ram_bank)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod huc1_tests {
+    use super::*;
+    use crate::cartridge::build_bank_numbered_rom;
+
+    fn build_huc1() -> Huc1 {
+        let rom = build_bank_numbered_rom(RomSize::SIZE_1_MB);
+        Huc1::new(MbcType::HUC1, RomSize::SIZE_1_MB, RamSize::SIZE_32_KB, &rom)
+    }
+
+    #[test]
+    fn test_banks() {
+        let mut mbc = build_huc1();
+        assert!(mbc.has_battery());
+        mbc.write_bank_0(0x2000, 0x3F);
+        assert_eq!(mbc.read_bank_n(0x4000), 0x3F);
+        mbc.write_bank_0(0x2000, 0x40);
+        assert_eq!(mbc.read_bank_n(0x4000), 0x01);
+
+        for bank in 0..4 {
+            mbc.write_bank_n(0x4000, bank);
+            mbc.write_ram(0xA000, bank + 0x10);
+        }
+        mbc.write_bank_n(0x4000, 0x02);
+        assert_eq!(mbc.read_ram(0xA000), 0x12);
+    }
+
+    #[test]
+    fn test_ir_mode() {
+        let mut mbc = build_huc1();
+        mbc.write_ram(0xA000, 0x12);
+
+        // the ir register hides the ram
+        mbc.write_bank_0(0x0000, 0x0E);
+        assert_eq!(mbc.read_ram(0xA000), IR_NO_LIGHT);
+        mbc.write_ram(0xA000, 0x01);
+        assert!(mbc.ir_led);
+
+        mbc.write_bank_0(0x0000, 0x00);
+        assert_eq!(mbc.read_ram(0xA000), 0x12);
+    }
+}
@@ -0,0 +1,427 @@
+use crate::cartridge::{MbcType, RomSize, RamSize, Mbc, get_unix_time};
+use crate::emulator::ONE_SECOND_IN_CYCLES;
+use crate::savestate::{SaveState, StateWriter, StateReader};
+
+const MODE_SPACE_START: u16 = 0x0000;
+const MODE_SPACE_END: u16 = 0x1FFF;
+
+const ROM_BANK_NB_SPACE_START: u16 = 0x2000;
+const ROM_BANK_NB_SPACE_END: u16 = 0x3FFF;
+
+const RAM_BANK_NB_SPACE_START: u16 = 0x4000;
+const RAM_BANK_NB_SPACE_END: u16 = 0x5FFF;
+
+const UNUSED_SPACE_START: u16 = 0x6000;
+const UNUSED_SPACE_END: u16 = 0x7FFF;
+
+// the mode register selects what is mapped in the ram space
+const MODE_RAM_READ: u8 = 0x00;
+const MODE_RAM_READ_WRITE: u8 = 0x0A;
+const MODE_RTC_COMMAND: u8 = 0x0B;
+const MODE_RTC_RESPONSE: u8 = 0x0C;
+const MODE_RTC_SEMAPHORE: u8 = 0x0D;
+const MODE_IR: u8 = 0x0E;
+
+// commands sent to the rtc, with a 4 bits argument
+const RTC_READ: u8 = 0x01;
+const RTC_WRITE: u8 = 0x03;
+const RTC_SET_ADDRESS_LOW: u8 = 0x04;
+const RTC_SET_ADDRESS_HIGH: u8 = 0x05;
+const RTC_EXTENDED: u8 = 0x06;
+
+// arguments of the extended command
+const RTC_LATCH_CLOCK: u8 = 0x00;
+const RTC_SET_CLOCK: u8 = 0x01;
+const RTC_GET_STATUS: u8 = 0x02;
+
+// the rtc is always ready for the next command
+const RTC_READY: u8 = 0x01;
+
+// the ir receiver never sees any light, there is no other gameboy in front of it
+const IR_NO_LIGHT: u8 = 0xC0;
+
+// the clock counts minutes in the day and days, 12 bits each, stored as nibbles
+// at the beginning of the rtc memory
+const MINUTES_PER_DAY: u64 = 1440;
+const RTC_DAY_COUNT: u64 = 4096;
+const RTC_MEMORY_SIZE: usize = 256;
+const RTC_CLOCK_NIBBLES: usize = 6;
+
+// clock appended to the ram in the save file: u64 unix timestamp, u16 minutes,
+// u16 days, u16 alarm minutes, u16 alarm days and u8 alarm enable, little endian
+const RTC_FOOTER_SIZE: usize = 17;
+
+const GB_ADDR_BIT_MASK: usize = 0x3FFF;
+const RAM_ADDR_BIT_MASK: usize = 0x1FFF;
+const ROM_BANK_BIT_OFFSET: usize = 14;
+const RAM_BANK_BIT_OFFSET: usize = 13;
+
+pub struct Huc3 {
+    // config
+    battery: bool,
+    // internal registers
+    mode: u8,
+    rom_bank_number: u8,
+    ram_bank_number: u8,
+    // memory
+    rom_bank: Vec<u8>,
+    ram_bank: Vec<u8>,
+    // rtc
+    rtc_cycles: usize,
+    rtc_sec: u8,
+    rtc_minutes: u16,
+    rtc_days: u16,
+    rtc_memory: [u8; RTC_MEMORY_SIZE],
+    rtc_address: u8,
+    rtc_command: u8,
+    rtc_response: u8,
+}
+
+impl Huc3 {
+    pub fn new(mbc_type: MbcType, rom_size: RomSize, ram_size: RamSize, rom: &[u8]) -> Huc3 {
+        let mut rom_bank: Vec<u8> = vec![0xFF; rom_size as usize];
+        let ram_bank: Vec<u8> = vec![0xFF; ram_size as usize];
+
+        // copy all rom data
+        let size = rom.len().min(rom_bank.len());
+        rom_bank[..size].copy_from_slice(&rom[..size]);
+
+        Huc3 {
+            // config
+            battery: mbc_type.has_battery(),
+            // internal registers
+            mode: MODE_RAM_READ,
+            rom_bank_number: 1,
+            ram_bank_number: 0,
+            // memory
+            rom_bank,
+            ram_bank,
+            // rtc
+            rtc_cycles: 0,
+            rtc_sec: 0,
+            rtc_minutes: 0,
+            rtc_days: 0,
+            rtc_memory: [0; RTC_MEMORY_SIZE],
+            rtc_address: 0,
+            rtc_command: 0,
+            rtc_response: 0,
+        }
+    }
+
+    // the bank number wraps around the number of banks of the ram
+    fn get_ram_address(&self, address: usize) -> usize {
+        let gb_addr = ((self.ram_bank_number as usize) << RAM_BANK_BIT_OFFSET)
+                            | (address & RAM_ADDR_BIT_MASK);
+        gb_addr % self.ram_bank.len()
+    }
+
+    fn advance_clock(&mut self, seconds: u64) {
+        let seconds = self.rtc_sec as u64 + seconds;
+        self.rtc_sec = (seconds % 60) as u8;
+        let minutes = self.rtc_minutes as u64 + seconds / 60;
+        self.rtc_minutes = (minutes % MINUTES_PER_DAY) as u16;
+        let days = self.rtc_days as u64 + minutes / MINUTES_PER_DAY;
+        self.rtc_days = (days % RTC_DAY_COUNT) as u16;
+    }
+
+    // move the clock back, the day counter wraps around below day 0
+    fn rewind_clock(&mut self, seconds: u64) {
+        let time = (self.rtc_days as u64 * MINUTES_PER_DAY + self.rtc_minutes as u64) * 60 + self.rtc_sec as u64;
+        let period = RTC_DAY_COUNT * MINUTES_PER_DAY * 60;
+        let time = (time % period + period - seconds % period) % period;
+        self.rtc_sec = (time % 60) as u8;
+        self.rtc_minutes = (time / 60 % MINUTES_PER_DAY) as u16;
+        self.rtc_days = (time / 60 / MINUTES_PER_DAY) as u16;
+    }
+
+    fn run_command(&mut self, data: u8) {
+        let command = (data >> 4) & 0x07;
+        let argument = data & 0x0F;
+
+        match command {
+            RTC_READ => {
+                self.rtc_response = self.rtc_memory[self.rtc_address as usize];
+                self.rtc_address = self.rtc_address.wrapping_add(1);
+            },
+            RTC_WRITE => {
+                self.rtc_memory[self.rtc_address as usize] = argument;
+                self.rtc_address = self.rtc_address.wrapping_add(1);
+            },
+            RTC_SET_ADDRESS_LOW => self.rtc_address = (self.rtc_address & 0xF0) | argument,
+            RTC_SET_ADDRESS_HIGH => self.rtc_address = (self.rtc_address & 0x0F) | argument << 4,
+            RTC_EXTENDED => match argument {
+                RTC_LATCH_CLOCK => {
+                    let clock = (self.rtc_days as u32) << 12 | self.rtc_minutes as u32;
+                    for (index, nibble) in self.rtc_memory[..RTC_CLOCK_NIBBLES].iter_mut().enumerate() {
+                        *nibble = ((clock >> (index * 4)) & 0x0F) as u8;
+                    }
+                },
+                RTC_SET_CLOCK => {
+                    let clock = self.rtc_memory[..RTC_CLOCK_NIBBLES].iter()
+                        .enumerate()
+                        .fold(0, |clock, (index, &nibble)| clock | ((nibble as u32 & 0x0F) << (index * 4)));
+                    self.rtc_sec = 0;
+                    self.rtc_minutes = ((clock & 0xFFF) as u64 % MINUTES_PER_DAY) as u16;
+                    self.rtc_days = (clock >> 12) as u16;
+                },
+                RTC_GET_STATUS => self.rtc_response = RTC_READY,
+                // the alarm tone is not emulated
+                _ => {/* do nothing here */},
+            },
+            _ => {/* do nothing here */},
+        }
+
+        self.rtc_command = command;
+    }
+
+    fn get_rtc_footer(&self, timestamp: u64) -> Vec<u8> {
+        let mut footer = timestamp.to_le_bytes().to_vec();
+        footer.extend_from_slice(&self.rtc_minutes.to_le_bytes());
+        footer.extend_from_slice(&self.rtc_days.to_le_bytes());
+        // no alarm
+        footer.extend_from_slice(&[0; 5]);
+        footer
+    }
+
+    // restore the clock then let it run for the time elapsed since the save
+    fn load_rtc_footer(&mut self, footer: &[u8], timestamp: u64) {
+        let mut saved_timestamp = [0; 8];
+        saved_timestamp.copy_from_slice(&footer[0..8]);
+        self.rtc_minutes = u16::from_le_bytes([footer[8], footer[9]]) % MINUTES_PER_DAY as u16;
+        self.rtc_days = u16::from_le_bytes([footer[10], footer[11]]) % RTC_DAY_COUNT as u16;
+        self.rtc_sec = 0;
+
+        self.advance_clock(timestamp.saturating_sub(u64::from_le_bytes(saved_timestamp)));
+    }
+}
+
+impl Mbc for Huc3 {
+    fn read_bank_0 (&self, address: usize) -> u8 {
+        let gb_addr = address & GB_ADDR_BIT_MASK;
+        self.rom_bank[gb_addr]
+    }
+
+    fn read_bank_n (&self, address: usize) -> u8 {
+        let gb_addr = ((self.rom_bank_number as usize) << ROM_BANK_BIT_OFFSET)
+                            | (address & GB_ADDR_BIT_MASK);
+        self.rom_bank[gb_addr % self.rom_bank.len()]
+    }
+
+    fn read_ram (&self, address: usize) -> u8 {
+        match self.mode {
+            MODE_RAM_READ | MODE_RAM_READ_WRITE if !self.ram_bank.is_empty() => {
+                self.ram_bank[self.get_ram_address(address)]
+            },
+            MODE_RTC_RESPONSE => 0x80 | self.rtc_command << 4 | self.rtc_response,
+            MODE_RTC_SEMAPHORE => RTC_READY,
+            MODE_IR => IR_NO_LIGHT,
+            _ => 0xFF,
+        }
+    }
+
+    fn write_bank_0 (&mut self, address: usize, data: u8) {
+        match address as u16 {
+            MODE_SPACE_START..=MODE_SPACE_END => {
+                self.mode = data & 0x0F;
+            },
+            ROM_BANK_NB_SPACE_START..=ROM_BANK_NB_SPACE_END => {
+                self.rom_bank_number = if data & 0x7F != 0 {
+                    data & 0x7F
+                } else {
+                    // if register is set to 0, set it to 1
+                    1
+                };
+            },
+            _ => panic!("huc 3 bank 0 address {:x} doesn't exists.", address),
+        }
+    }
+
+    fn write_bank_n (&mut self, address: usize, data: u8) {
+        match address as u16 {
+            RAM_BANK_NB_SPACE_START..=RAM_BANK_NB_SPACE_END => {
+                self.ram_bank_number = data & 0x03;
+            },
+            UNUSED_SPACE_START..=UNUSED_SPACE_END => {/* do nothing here */},
+            _ => panic!("huc 3 bank n address {:x} doesn't exists.", address),
+        }
+    }
+
+    fn write_ram (&mut self, address: usize, data: u8) -> bool {
+        match self.mode {
+            MODE_RAM_READ_WRITE if !self.ram_bank.is_empty() => {
+                let gb_addr = self.get_ram_address(address);
+                self.ram_bank[gb_addr] = data;
+                true
+            },
+            // the commands can set the clock, which is stored in the save file
+            MODE_RTC_COMMAND => {
+                self.run_command(data);
+                true
+            },
+            // commands are run as soon as they are written, the semaphore is not needed
+            // and the ir led is not emulated
+            _ => false,
+        }
+    }
+
+    fn run (&mut self, cycles: u8) {
+        self.rtc_cycles += cycles as usize;
+
+        if self.rtc_cycles >= ONE_SECOND_IN_CYCLES {
+            let add_sec = self.rtc_cycles / ONE_SECOND_IN_CYCLES;
+            // update rtc cycles
+            self.rtc_cycles %= ONE_SECOND_IN_CYCLES;
+            self.advance_clock(add_sec as u64);
+        }
+    }
+
+    fn has_battery (&self) -> bool {
+        self.battery
+    }
+
+    fn dump_ram (&self) -> Vec<u8> {
+        let mut data = self.ram_bank.clone();
+        data.extend(self.get_rtc_footer(get_unix_time()));
+        data
+    }
+
+    fn load_ram (&mut self, data: &[u8]) {
+        let size = data.len().min(self.ram_bank.len());
+        self.ram_bank[..size].copy_from_slice(&data[..size]);
+
+        // save files without clock footer only restore the ram
+        let footer = &data[size..];
+        if footer.len() == RTC_FOOTER_SIZE {
+            self.load_rtc_footer(footer, get_unix_time());
+        }
+    }
+
+    fn advance_rtc (&mut self, seconds: i64) {
+        if seconds >= 0 {
+            self.advance_clock(seconds as u64);
+        } else {
+            self.rewind_clock(seconds.unsigned_abs());
+        }
+    }
+}
+
+impl SaveState for Huc3 {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(self.mode);
+        writer.write_u8(self.rom_bank_number);
+        writer.write_u8(self.ram_bank_number);
+        writer.write_bytes(&self.ram_bank);
+        // rtc
+        writer.write_u64(self.rtc_cycles as u64);
+        writer.write_u8(self.rtc_sec);
+        writer.write_u16(self.rtc_minutes);
+        writer.write_u16(self.rtc_days);
+        writer.write_bytes(&self.rtc_memory);
+        writer.write_u8(self.rtc_address);
+        writer.write_u8(self.rtc_command);
+        writer.write_u8(self.rtc_response);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> std::io::Result<()> {
+        self.mode = reader.read_u8()?;
+        self.rom_bank_number = reader.read_u8()?;
+        self.ram_bank_number = reader.read_u8()?;
+        reader.read_bytes_into(&mut self.ram_bank)?;
+        // rtc
+        self.rtc_cycles = reader.read_u64()? as usize;
+        self.rtc_sec = reader.read_u8()?;
+        self.rtc_minutes = reader.read_u16()?;
+        self.rtc_days = reader.read_u16()?;
+        reader.read_bytes_into(&mut self.rtc_memory)?;
+        self.rtc_address = reader.read_u8()?;
+        self.rtc_command = reader.read_u8()?;
+        self.rtc_response = reader.read_u8()?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod huc3_tests {
+    use super::*;
+
+    fn build_huc3() -> Huc3 {
+        let rom = vec![0xFF; RomSize::SIZE_1_MB as usize];
+        Huc3::new(MbcType::HUC3, RomSize::SIZE_1_MB, RamSize::SIZE_32_KB, &rom)
+    }
+
+    fn send_command(mbc: &mut Huc3, command: u8, argument: u8) -> u8 {
+        mbc.write_bank_0(0x0000, MODE_RTC_COMMAND);
+        mbc.write_ram(0xA000, command << 4 | argument);
+        mbc.write_bank_0(0x0000, MODE_RTC_RESPONSE);
+        mbc.read_ram(0xA000)
+    }
+
+    #[test]
+    fn test_ram_modes() {
+        let mut mbc = build_huc3();
+        assert!(mbc.has_battery());
+
+        // the ram is read only until the read write mode is selected
+        mbc.write_ram(0xA000, 0x12);
+        assert_eq!(mbc.read_ram(0xA000), 0xFF);
+        mbc.write_bank_0(0x0000, MODE_RAM_READ_WRITE);
+        mbc.write_bank_n(0x4000, 0x03);
+        mbc.write_ram(0xA000, 0x12);
+        mbc.write_bank_0(0x0000, MODE_RAM_READ);
+        assert_eq!(mbc.read_ram(0xA000), 0x12);
+        assert_eq!(mbc.dump_ram()[3 << 13], 0x12);
+
+        mbc.write_bank_0(0x0000, MODE_RTC_SEMAPHORE);
+        assert_eq!(mbc.read_ram(0xA000), RTC_READY);
+        mbc.write_bank_0(0x0000, MODE_IR);
+        assert_eq!(mbc.read_ram(0xA000), IR_NO_LIGHT);
+    }
+
+    #[test]
+    fn test_rtc_commands() {
+        let mut mbc = build_huc3();
+        assert_eq!(send_command(&mut mbc, RTC_EXTENDED, RTC_GET_STATUS), 0xE1);
+
+        // set the clock to day 2 at 23:59
+        send_command(&mut mbc, RTC_SET_ADDRESS_LOW, 0x00);
+        send_command(&mut mbc, RTC_SET_ADDRESS_HIGH, 0x00);
+        for nibble in [0xF, 0x9, 0x5, 0x2, 0x0, 0x0] {
+            send_command(&mut mbc, RTC_WRITE, nibble);
+        }
+        send_command(&mut mbc, RTC_EXTENDED, RTC_SET_CLOCK);
+        assert_eq!((mbc.rtc_minutes, mbc.rtc_days), (1439, 2));
+
+        // one minute later
+        for _ in 0..(60 * ONE_SECOND_IN_CYCLES / 128) {
+            mbc.run(128);
+        }
+        send_command(&mut mbc, RTC_EXTENDED, RTC_LATCH_CLOCK);
+        send_command(&mut mbc, RTC_SET_ADDRESS_LOW, 0x00);
+        let clock: Vec<u8> = (0..RTC_CLOCK_NIBBLES)
+            .map(|_| send_command(&mut mbc, RTC_READ, 0x00))
+            .collect();
+        assert_eq!(clock, [0x90, 0x90, 0x90, 0x93, 0x90, 0x90]);
+    }
+
+    #[test]
+    fn test_rtc_save_file() {
+        let mut mbc = build_huc3();
+        mbc.rtc_minutes = 1000;
+        mbc.rtc_days = 4095;
+
+        let data = mbc.dump_ram();
+        assert_eq!(data.len(), 0x8000 + RTC_FOOTER_SIZE);
+        assert_eq!(data[0x8008..0x800C], [0xE8, 0x03, 0xFF, 0x0F]);
+
+        // the day counter wraps around
+        let footer = mbc.get_rtc_footer(1000);
+        let mut mbc = build_huc3();
+        mbc.load_rtc_footer(&footer, 1000 + 24 * 3600 + 60);
+        assert_eq!((mbc.rtc_minutes, mbc.rtc_days), (1001, 0));
+
+        // and wraps around below day 0 when the clock is moved back
+        mbc.advance_rtc(-(2 * 24 * 3600 + 2 * 60));
+        assert_eq!((mbc.rtc_minutes, mbc.rtc_days), (999, 4094));
+    }
+}
@@ -1,4 +1,6 @@
 use crate::cartridge::{MbcType, RomSize, RamSize, Mbc};
+use crate::cartridge::header::{CARTRIDGE_LOGO_OFFSET, CARTRIDGE_LOGO_SIZE, NINTENDO_LOGO};
+use crate::savestate::{SaveState, StateWriter, StateReader};
 
 const RAM_ENABLE_SPACE_START: u16 = 0x0000;
 const RAM_ENABLE_SPACE_END: u16 = 0x1FFF;
@@ -17,6 +19,13 @@ const ENABLE_RAM_FLAG: u8 = 0x0A;
 const GB_ADDR_BIT_MASK: usize = 0x3FFF;
 const ROM_BANK_BIT_OFFSET: usize = 14;
 const RAM_BANK_BIT_OFFSET: usize = 19;
+// multicarts don't wire the 5th bit of the rom bank register, the secondary register
+// selects one of the 4 games of 256 KB
+const MULTICART_RAM_BANK_BIT_OFFSET: usize = 18;
+const MULTICART_GAME_SIZE: usize = 0x40000;
+
+const RAM_ADDR_BIT_MASK: usize = 0x1FFF;
+const RAM_ADDR_BANK_BIT_OFFSET: usize = 13;
 
 #[allow(non_camel_case_types)]
 enum RomBankMask {
@@ -30,6 +39,8 @@ enum RomBankMask {
 pub struct Mbc1 {
     // config
     rom_size: RomSize,
+    battery: bool,
+    multicart: bool,
     // internal registers
     ram_enable: bool,
     rom_bank_number: u8,
@@ -41,34 +52,80 @@ pub struct Mbc1 {
 }
 
 impl Mbc1 {
-    pub fn new(_: MbcType, rom_size: RomSize, ram_size: RamSize, rom: &[u8]) -> Mbc1 {
-        let mut rom_bank: Vec<u8> = vec![0xFF; rom_size.clone() as usize];
-        let ram_bank: Vec<u8> = vec![0xFF; ram_size.clone() as usize];
+    pub fn new(mbc_type: MbcType, rom_size: RomSize, ram_size: RamSize, rom: &[u8]) -> Mbc1 {
+        let mut rom_bank: Vec<u8> = vec![0xFF; rom_size as usize];
+        let ram_bank: Vec<u8> = vec![0xFF; ram_size as usize];
 
         // copy all rom data
-        for rom_index in 0..(rom_size as usize){
-            rom_bank[rom_index as usize] = rom[rom_index as usize];
-        }
+        let size = rom.len().min(rom_bank.len());
+        rom_bank[..size].copy_from_slice(&rom[..size]);
 
         Mbc1 {
             // config
-            rom_size: rom_size,
+            rom_size,
+            battery: mbc_type.has_battery(),
+            multicart: is_multicart(rom_size, &rom_bank),
             // internal registers
             ram_enable: false,
             rom_bank_number: 1,
             ram_bank_number: 0,
             banking_mode: false,
             // memory
-            rom_bank: rom_bank,
-            ram_bank: ram_bank,
+            rom_bank,
+            ram_bank,
         }
     }
+
+    // the secondary register drives the upper bits of the rom address
+    fn get_rom_address(&self, rom_bank_number: u8, address: usize) -> usize {
+        let ram_bank_bit_offset = if self.multicart {
+            MULTICART_RAM_BANK_BIT_OFFSET
+        } else {
+            RAM_BANK_BIT_OFFSET
+        };
+
+        let gb_addr = ((self.ram_bank_number as usize) << ram_bank_bit_offset)
+                            | ((rom_bank_number as usize) << ROM_BANK_BIT_OFFSET)
+                            | (address & GB_ADDR_BIT_MASK);
+        gb_addr % self.rom_bank.len()
+    }
+
+    // the secondary register selects the ram bank in mode 1 only, it wraps around the size of the ram chip
+    fn get_ram_address(&self, address: usize) -> usize {
+        let ram_bank_number = if self.banking_mode {
+            self.ram_bank_number as usize
+        } else {
+            0
+        };
+
+        let gb_addr = (ram_bank_number << RAM_ADDR_BANK_BIT_OFFSET)
+                            | (address & RAM_ADDR_BIT_MASK);
+        gb_addr % self.ram_bank.len()
+    }
+}
+
+// multicarts are 1 MB roms holding a game with its own header every 256 KB, the
+// menu is in the first one
+fn is_multicart(rom_size: RomSize, rom: &[u8]) -> bool {
+    if !matches!(rom_size, RomSize::SIZE_1_MB) {
+        return false;
+    }
+
+    let game_count = rom.chunks(MULTICART_GAME_SIZE)
+        .filter(|game| {
+            let logo_offset = CARTRIDGE_LOGO_OFFSET as usize;
+            game[logo_offset..logo_offset + CARTRIDGE_LOGO_SIZE] == NINTENDO_LOGO
+        })
+        .count();
+
+    // the menu and at least one game
+    game_count >= 2
 }
 
 impl Mbc for Mbc1 {
     fn read_bank_0 (&self, address: usize) -> u8 {
         if self.banking_mode {
-            let gb_addr = ((self.ram_bank_number as usize) << RAM_BANK_BIT_OFFSET) | (address & GB_ADDR_BIT_MASK);
+            let gb_addr = self.get_rom_address(0, address);
             self.rom_bank[gb_addr]
         } else {
             let gb_addr = address & GB_ADDR_BIT_MASK;
@@ -77,22 +134,13 @@ impl Mbc for Mbc1 {
     }
 
     fn read_bank_n (&self, address: usize) -> u8 {
-        let gb_addr = ((self.ram_bank_number as usize) << RAM_BANK_BIT_OFFSET) 
-                            | ((self.rom_bank_number as usize) << ROM_BANK_BIT_OFFSET)
-                            | (address & GB_ADDR_BIT_MASK);
+        let gb_addr = self.get_rom_address(self.rom_bank_number, address);
         self.rom_bank[gb_addr]
     }
 
     fn read_ram (&self, address: usize) -> u8 {
-        if self.ram_enable {
-            if self.banking_mode {
-                let gb_addr = address & 0x1FFF;
-                self.ram_bank[gb_addr]
-            } else {
-                let gb_addr = ((self.ram_bank_number as usize) << 13)
-                                    | (address & 0x1FFF);
-                self.ram_bank[gb_addr]
-            }
+        if self.ram_enable && !self.ram_bank.is_empty() {
+            self.ram_bank[self.get_ram_address(address)]
         } else {
             // RAM is disabled, returns 0xFF
             0xFF
@@ -108,6 +156,7 @@ impl Mbc for Mbc1 {
             },
             ROM_BANK_NB_SPACE_START..=ROM_BANK_NB_SPACE_END => {
                 let rom_bank_mask = match self.rom_size {
+                    _ if self.multicart => RomBankMask::MASK_4_BIT,
                     RomSize::SIZE_32_KB => RomBankMask::MASK_1_BIT,
                     RomSize::SIZE_64_KB => RomBankMask::MASK_2_BIT,
                     RomSize::SIZE_128_KB => RomBankMask::MASK_3_BIT,
@@ -115,7 +164,8 @@ impl Mbc for Mbc1 {
                     _ => RomBankMask::MASK_5_BIT,
                 };
 
-                self.rom_bank_number = if data != 0 {
+                // the 5 bits of the register are compared to 0, even when they are not all wired
+                self.rom_bank_number = if data & (RomBankMask::MASK_5_BIT as u8) != 0 {
                     data & (rom_bank_mask as u8)
                 } else {
                     // if register is set to 0, set it to 1 
@@ -138,21 +188,143 @@ impl Mbc for Mbc1 {
         }
     }
 
-    fn write_ram (&mut self, address: usize, data: u8) {
-        if self.ram_enable {
-            if self.banking_mode {
-                let gb_addr = address & 0x1FFF;
-                self.ram_bank[gb_addr] = data;
-            } else {
-                let gb_addr = ((self.ram_bank_number as usize) << 13)
-                                    | (address & 0x1FFF);
-                self.ram_bank[gb_addr] = data;
-            }
+    fn write_ram (&mut self, address: usize, data: u8) -> bool {
+        if self.ram_enable && !self.ram_bank.is_empty() {
+            let gb_addr = self.get_ram_address(address);
+            self.ram_bank[gb_addr] = data;
+            true
         } else {
             // do nothing when ram is disabled
+            false
         }
     }
 
     // not used for this mbc, doesn't do anything
     fn run (&mut self, _: u8) {}
-}
\ No newline at end of file
+
+    fn has_battery (&self) -> bool {
+        self.battery
+    }
+
+    fn dump_ram (&self) -> Vec<u8> {
+        self.ram_bank.clone()
+    }
+
+    fn load_ram (&mut self, data: &[u8]) {
+        let size = data.len().min(self.ram_bank.len());
+        self.ram_bank[..size].copy_from_slice(&data[..size]);
+    }
+}
+
+impl SaveState for Mbc1 {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bool(self.ram_enable);
+        writer.write_u8(self.rom_bank_number);
+        writer.write_u8(self.ram_bank_number);
+        writer.write_bool(self.banking_mode);
+        writer.write_bytes(&self.ram_bank);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> std::io::Result<()> {
+        self.ram_enable = reader.read_bool()?;
+        self.rom_bank_number = reader.read_u8()?;
+        self.ram_bank_number = reader.read_u8()?;
+        self.banking_mode = reader.read_bool()?;
+        reader.read_bytes_into(&mut self.ram_bank)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod mbc1_tests {
+    use super::*;
+    use crate::cartridge::build_bank_numbered_rom;
+
+    fn build_rom(games: usize) -> Vec<u8> {
+        let mut rom = build_bank_numbered_rom(RomSize::SIZE_1_MB);
+        for game in rom.chunks_mut(MULTICART_GAME_SIZE).take(games) {
+            let logo_offset = CARTRIDGE_LOGO_OFFSET as usize;
+            game[logo_offset..logo_offset + CARTRIDGE_LOGO_SIZE].copy_from_slice(&NINTENDO_LOGO);
+        }
+
+        rom
+    }
+
+    #[test]
+    fn test_multicart_detection() {
+        assert!(is_multicart(RomSize::SIZE_1_MB, &build_rom(4)));
+        assert!(is_multicart(RomSize::SIZE_1_MB, &build_rom(2)));
+        assert!(!is_multicart(RomSize::SIZE_1_MB, &build_rom(1)));
+        assert!(!is_multicart(RomSize::SIZE_2_MB, &vec![0xFF; RomSize::SIZE_2_MB as usize]));
+    }
+
+    #[test]
+    fn test_rom_banks() {
+        let mut mbc = Mbc1::new(MbcType::MBC_1, RomSize::SIZE_1_MB, RamSize::NO_RAM, &build_rom(1));
+        mbc.write_bank_0(0x2000, 0x13);
+        assert_eq!(mbc.read_bank_n(0x4000), 0x13);
+        mbc.write_bank_n(0x4000, 0x01);
+        assert_eq!(mbc.read_bank_n(0x4000), 0x33);
+        // bank 0x20 can't be selected in the first register
+        mbc.write_bank_0(0x2000, 0x20);
+        assert_eq!(mbc.read_bank_n(0x4000), 0x21);
+        // the secondary register maps the bank 0 area in mode 1
+        mbc.write_bank_n(0x6000, 0x01);
+        assert_eq!(mbc.read_bank_0(0x0000), 0x20);
+    }
+
+    #[test]
+    fn test_multicart_rom_banks() {
+        let mut mbc = Mbc1::new(MbcType::MBC_1, RomSize::SIZE_1_MB, RamSize::NO_RAM, &build_rom(4));
+        mbc.write_bank_0(0x2000, 0x13);
+        assert_eq!(mbc.read_bank_n(0x4000), 0x03);
+        mbc.write_bank_n(0x4000, 0x02);
+        assert_eq!(mbc.read_bank_n(0x4000), 0x23);
+        // with the unwired 5th bit set, the first bank of the game is mapped
+        mbc.write_bank_0(0x2000, 0x10);
+        assert_eq!(mbc.read_bank_n(0x4000), 0x20);
+
+        // the menu starts a game by mapping its first bank in the bank 0 area
+        mbc.write_bank_n(0x6000, 0x01);
+        mbc.write_bank_n(0x4000, 0x03);
+        assert_eq!(mbc.read_bank_0(0x0000), 0x30);
+        assert_eq!(mbc.read_bank_0(CARTRIDGE_LOGO_OFFSET as usize), NINTENDO_LOGO[0]);
+        mbc.write_bank_0(0x2000, 0x01);
+        assert_eq!(mbc.read_bank_n(0x4000), 0x31);
+    }
+
+    #[test]
+    fn test_ram_banks() {
+        let mut mbc = Mbc1::new(MbcType::MBC_1_RAM_BAT, RomSize::SIZE_512_KB, RamSize::SIZE_32_KB, &build_rom(1));
+        mbc.write_bank_0(0x0000, 0x0A);
+        mbc.write_ram(0xA000, 0x10);
+        // the secondary register selects the ram bank in mode 1 only
+        mbc.write_bank_n(0x4000, 0x02);
+        assert_eq!(mbc.read_ram(0xA000), 0x10);
+        mbc.write_bank_n(0x6000, 0x01);
+        mbc.write_ram(0xA000, 0x12);
+        assert_eq!(mbc.ram_bank[0x4000], 0x12);
+        mbc.write_bank_n(0x6000, 0x00);
+        assert_eq!(mbc.read_ram(0xA000), 0x10);
+    }
+
+    #[test]
+    fn test_small_ram() {
+        // 8 KB of ram are mirrored in every bank
+        let mut mbc = Mbc1::new(MbcType::MBC_1_RAM_BAT, RomSize::SIZE_1_MB, RamSize::SIZE_8_KB, &build_rom(1));
+        mbc.write_bank_0(0x0000, 0x0A);
+        mbc.write_ram(0xA000, 0x12);
+        mbc.write_bank_n(0x4000, 0x01);
+        mbc.write_bank_n(0x6000, 0x01);
+        assert_eq!(mbc.read_ram(0xA000), 0x12);
+        mbc.write_ram(0xBFFF, 0x34);
+        assert_eq!(mbc.ram_bank[0x1FFF], 0x34);
+
+        // without ram, reads return 0xFF and writes are ignored
+        let mut mbc = Mbc1::new(MbcType::MBC_1, RomSize::SIZE_1_MB, RamSize::NO_RAM, &build_rom(4));
+        mbc.write_bank_0(0x0000, 0x0A);
+        mbc.write_ram(0xA000, 0x12);
+        assert_eq!(mbc.read_ram(0xA000), 0xFF);
+    }
+}
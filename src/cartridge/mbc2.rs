@@ -0,0 +1,202 @@
+use crate::cartridge::{MbcType, RomSize, Mbc};
+use crate::savestate::{SaveState, StateWriter, StateReader};
+
+const REGISTER_SPACE_START: u16 = 0x0000;
+const REGISTER_SPACE_END: u16 = 0x3FFF;
+
+const UNUSED_SPACE_START: u16 = 0x4000;
+const UNUSED_SPACE_END: u16 = 0x7FFF;
+
+// bit 8 of the address selects the register written in bank 0
+const ROM_BANK_REGISTER_FLAG: usize = 0x0100;
+
+const ENABLE_RAM_FLAG: u8 = 0x0A;
+
+const GB_ADDR_BIT_MASK: usize = 0x3FFF;
+const ROM_BANK_BIT_OFFSET: usize = 14;
+
+// the ram is built in the mbc, 512 half-bytes mirrored over the whole ram space
+const RAM_SIZE: usize = 0x200;
+const RAM_ADDR_BIT_MASK: usize = 0x01FF;
+const RAM_DATA_MASK: u8 = 0x0F;
+
+pub struct Mbc2 {
+    // config
+    battery: bool,
+    // internal registers
+    ram_enable: bool,
+    rom_bank_number: u8,
+    // memory
+    rom_bank: Vec<u8>,
+    ram_bank: Vec<u8>,
+}
+
+impl Mbc2 {
+    pub fn new(mbc_type: MbcType, rom_size: RomSize, rom: &[u8]) -> Mbc2 {
+        let mut rom_bank: Vec<u8> = vec![0xFF; rom_size as usize];
+
+        // copy all rom data
+        let size = rom.len().min(rom_bank.len());
+        rom_bank[..size].copy_from_slice(&rom[..size]);
+
+        Mbc2 {
+            // config
+            battery: mbc_type.has_battery(),
+            // internal registers
+            ram_enable: false,
+            rom_bank_number: 1,
+            // memory
+            rom_bank,
+            ram_bank: vec![RAM_DATA_MASK; RAM_SIZE],
+        }
+    }
+}
+
+impl Mbc for Mbc2 {
+    fn read_bank_0 (&self, address: usize) -> u8 {
+        let gb_addr = address & GB_ADDR_BIT_MASK;
+        self.rom_bank[gb_addr]
+    }
+
+    fn read_bank_n (&self, address: usize) -> u8 {
+        let gb_addr = ((self.rom_bank_number as usize) << ROM_BANK_BIT_OFFSET)
+                            | (address & GB_ADDR_BIT_MASK);
+        self.rom_bank[gb_addr % self.rom_bank.len()]
+    }
+
+    fn read_ram (&self, address: usize) -> u8 {
+        if self.ram_enable {
+            // only the lower nibble is stored, the upper one reads as 1s
+            !RAM_DATA_MASK | self.ram_bank[address & RAM_ADDR_BIT_MASK]
+        } else {
+            // RAM is disabled, returns 0xFF
+            0xFF
+        }
+    }
+
+    fn write_bank_0 (&mut self, address: usize, data: u8) {
+        match address as u16 {
+            REGISTER_SPACE_START..=REGISTER_SPACE_END => {
+                if address & ROM_BANK_REGISTER_FLAG != 0 {
+                    self.rom_bank_number = if data & 0x0F != 0 {
+                        data & 0x0F
+                    } else {
+                        // if register is set to 0, set it to 1
+                        1
+                    };
+                } else {
+                    self.ram_enable = (data & 0x0F) == ENABLE_RAM_FLAG;
+                }
+            },
+            _ => panic!("mbc 2 bank 0 address {:x} doesn't exists.", address),
+        }
+    }
+
+    fn write_bank_n (&mut self, address: usize, _: u8) {
+        match address as u16 {
+            UNUSED_SPACE_START..=UNUSED_SPACE_END => {/* do nothing here */},
+            _ => panic!("mbc 2 bank n address {:x} doesn't exists.", address),
+        }
+    }
+
+    fn write_ram (&mut self, address: usize, data: u8) -> bool {
+        if self.ram_enable {
+            self.ram_bank[address & RAM_ADDR_BIT_MASK] = data & RAM_DATA_MASK;
+            true
+        } else {
+            // do nothing when ram is disabled
+            false
+        }
+    }
+
+    // not used for this mbc, doesn't do anything
+    fn run (&mut self, _: u8) {}
+
+    fn has_battery (&self) -> bool {
+        self.battery
+    }
+
+    fn dump_ram (&self) -> Vec<u8> {
+        self.ram_bank.clone()
+    }
+
+    fn load_ram (&mut self, data: &[u8]) {
+        for (ram_data, &save_data) in self.ram_bank.iter_mut().zip(data) {
+            *ram_data = save_data & RAM_DATA_MASK;
+        }
+    }
+}
+
+impl SaveState for Mbc2 {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bool(self.ram_enable);
+        writer.write_u8(self.rom_bank_number);
+        writer.write_bytes(&self.ram_bank);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> std::io::Result<()> {
+        self.ram_enable = reader.read_bool()?;
+        self.rom_bank_number = reader.read_u8()?;
+        reader.read_bytes_into(&mut self.ram_bank)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod mbc2_tests {
+    use super::*;
+    use crate::cartridge::build_bank_numbered_rom;
+
+    fn build_mbc2(mbc_type: MbcType) -> Mbc2 {
+        let rom = build_bank_numbered_rom(RomSize::SIZE_256_KB);
+        Mbc2::new(mbc_type, RomSize::SIZE_256_KB, &rom)
+    }
+
+    #[test]
+    fn test_registers() {
+        let mut mbc = build_mbc2(MbcType::MBC_2);
+        assert_eq!(mbc.read_bank_n(0x4000), 0x01);
+
+        // address bit 8 set, rom bank register
+        mbc.write_bank_0(0x2100, 0x0A);
+        assert_eq!(mbc.read_bank_n(0x4000), 0x0A);
+        assert_eq!(mbc.read_ram(0xA000), 0xFF);
+        mbc.write_bank_0(0x0100, 0x1F);
+        assert_eq!(mbc.read_bank_n(0x4000), 0x0F);
+        mbc.write_bank_0(0x3FFF, 0x10);
+        assert_eq!(mbc.read_bank_n(0x4000), 0x01);
+
+        // address bit 8 cleared, ram enable register
+        mbc.write_bank_0(0x3E00, 0x0A);
+        assert_eq!(mbc.read_bank_n(0x4000), 0x01);
+        mbc.write_ram(0xA000, 0x05);
+        assert_eq!(mbc.read_ram(0xA000), 0xF5);
+        mbc.write_bank_0(0x0000, 0x00);
+        assert_eq!(mbc.read_ram(0xA000), 0xFF);
+    }
+
+    #[test]
+    fn test_ram() {
+        let mut mbc = build_mbc2(MbcType::MBC_2_BAT);
+        assert!(mbc.has_battery());
+        mbc.write_bank_0(0x0000, 0x0A);
+
+        // half-bytes mirrored over the whole ram space
+        mbc.write_ram(0xA001, 0xA7);
+        assert_eq!(mbc.read_ram(0xA001), 0xF7);
+        assert_eq!(mbc.read_ram(0xA201), 0xF7);
+        assert_eq!(mbc.read_ram(0xBE01), 0xF7);
+        mbc.write_ram(0xB1FF, 0x03);
+        assert_eq!(mbc.read_ram(0xA1FF), 0xF3);
+
+        let ram = mbc.dump_ram();
+        assert_eq!(ram.len(), 512);
+        assert_eq!((ram[0x001], ram[0x1FF]), (0x07, 0x03));
+
+        let mut mbc = build_mbc2(MbcType::MBC_2_BAT);
+        mbc.load_ram(&[0xFF, 0x12]);
+        mbc.write_bank_0(0x0000, 0x0A);
+        assert_eq!((mbc.read_ram(0xA000), mbc.read_ram(0xA001)), (0xFF, 0xF2));
+    }
+}
@@ -1,5 +1,6 @@
-use crate::cartridge::{MbcType, RomSize, RamSize, Mbc};
+use crate::cartridge::{MbcType, RomSize, RamSize, Mbc, get_unix_time};
 use crate::emulator::ONE_SECOND_IN_CYCLES;
+use crate::savestate::{SaveState, StateWriter, StateReader};
 
 const RAM_ENABLE_SPACE_START: u16 = 0x0000;
 const RAM_ENABLE_SPACE_END: u16 = 0x1FFF;
@@ -16,23 +17,32 @@ const LATCH_CLOCK_SPACE_END: u16 = 0x7FFF;
 const ENABLE_RAM_FLAG: u8 = 0x0A;
 
 const GB_ADDR_BIT_MASK: usize = 0x3FFF;
+const RAM_ADDR_BIT_MASK: usize = 0x1FFF;
 const ROM_BANK_BIT_OFFSET: usize = 14;
 const RAM_BANK_BIT_OFFSET: usize = 13;
 
-#[allow(non_camel_case_types)]
-enum RomBankMask {
-    MASK_1_BIT = 0x01,
-    MASK_2_BIT = 0x03,
-    MASK_3_BIT = 0x07,
-    MASK_4_BIT = 0x0F,
-    MASK_5_BIT = 0x1F,
-    MASK_6_BIT = 0x3F,
-    MASK_7_BIT = 0x7F,
-}
+// mbc30 has a wider rom bank register and twice as many ram banks
+const MBC3_ROM_BANK_MASK: u8 = 0x7F;
+const MBC30_ROM_BANK_MASK: u8 = 0xFF;
+const MBC3_LAST_RAM_BANK: u8 = 0x03;
+const MBC30_LAST_RAM_BANK: u8 = 0x07;
+
+// the day counter has 9 bits
+const RTC_DAY_COUNT: u64 = 512;
+const SECONDS_PER_DAY: u64 = 86400;
+
+// clock registers and host time appended to the ram in the save file: 10 little endian
+// u32 for the current then latched registers, followed by a u64 unix timestamp. Some
+// emulators write a 32 bits timestamp instead.
+const RTC_FOOTER_SIZE: usize = 48;
+const RTC_SHORT_FOOTER_SIZE: usize = 44;
 
 pub struct Mbc3 {
     // config
-    rom_size: RomSize,
+    rom_bank_mask: u8,
+    last_ram_bank: u8,
+    battery: bool,
+    rtc: bool,
     // internal registers
     ram_enable: bool,
     rom_bank_number: u8,
@@ -55,21 +65,35 @@ pub struct Mbc3 {
     rtc_min_latch: u8,
     rtc_hours_latch: u8,
     rtc_day_latch: u8,
+    rtc_control_latch: u8,
 }
 
 impl Mbc3 {
-    pub fn new(_: MbcType, rom_size: RomSize, ram_size: RamSize, rom: &[u8]) -> Mbc3 {
-        let mut rom_bank: Vec<u8> = vec![0xFF; rom_size.clone() as usize];
-        let ram_bank: Vec<u8> = vec![0xFF; ram_size.clone() as usize];
+    pub fn new(mbc_type: MbcType, rom_size: RomSize, ram_size: RamSize, rom: &[u8]) -> Mbc3 {
+        let mut rom_bank: Vec<u8> = vec![0xFF; rom_size as usize];
+        let ram_bank: Vec<u8> = vec![0xFF; ram_size as usize];
 
         // copy all rom data
-        for rom_index in 0..(rom_size as usize){
-            rom_bank[rom_index as usize] = rom[rom_index as usize];
-        }
+        let size = rom.len().min(rom_bank.len());
+        rom_bank[..size].copy_from_slice(&rom[..size]);
+
+        // mbc30 shares the cartridge types of mbc3, only its rom or ram size gives it away
+        let mbc30 = matches!(rom_size, RomSize::SIZE_4_MB | RomSize::SIZE_8_MB)
+            || matches!(ram_size, RamSize::SIZE_64_KB | RamSize::SIZE_128_KB);
+        let (register_mask, last_ram_bank) = if mbc30 {
+            (MBC30_ROM_BANK_MASK, MBC30_LAST_RAM_BANK)
+        } else {
+            (MBC3_ROM_BANK_MASK, MBC3_LAST_RAM_BANK)
+        };
+        // the bank number wraps around the number of banks of the rom
+        let rom_bank_count = (rom_size as usize) >> ROM_BANK_BIT_OFFSET;
 
         Mbc3 {
             // config
-            rom_size: rom_size,
+            rom_bank_mask: register_mask & (rom_bank_count - 1) as u8,
+            last_ram_bank,
+            battery: mbc_type.has_battery(),
+            rtc: matches!(mbc_type, MbcType::MBC_3_TIM_BAT | MbcType::MBC_3_TIM_RAM_BAT),
             // internal registers
             ram_enable: false,
             rom_bank_number: 1,
@@ -92,7 +116,99 @@ impl Mbc3 {
             rtc_min_latch: 0,
             rtc_hours_latch: 0,
             rtc_day_latch: 0,
+            rtc_control_latch: 0,
+        }
+    }
+
+    fn get_rtc_control(&self) -> u8 {
+        (self.rtc_day_hi as u8)
+            | (self.rtc_halt as u8) << 6
+            | (self.rtc_overflow as u8) << 7
+    }
+
+    fn set_rtc_control(&mut self, data: u8) {
+        self.rtc_day_hi = (data & 0x01) != 0;
+        self.rtc_halt = (data & 0x40) != 0;
+        self.rtc_overflow = (data & 0x80) != 0;
+    }
+
+    // move the clock forward, the overflow flag is set when the day counter wraps around
+    fn advance_clock(&mut self, seconds: u64) {
+        if self.rtc_halt {
+            return;
+        }
+
+        let seconds = self.rtc_sec as u64 + seconds;
+        self.rtc_sec = (seconds % 60) as u8;
+        let minutes = self.rtc_min as u64 + seconds / 60;
+        self.rtc_min = (minutes % 60) as u8;
+        let hours = self.rtc_hours as u64 + minutes / 60;
+        self.rtc_hours = (hours % 24) as u8;
+        let days = ((self.rtc_day_hi as u64) << 8 | self.rtc_day_lo as u64) + hours / 24;
+        if days >= RTC_DAY_COUNT {
+            self.rtc_overflow = true;
         }
+        let days = days % RTC_DAY_COUNT;
+        self.rtc_day_lo = days as u8;
+        self.rtc_day_hi = days >= 0x100;
+    }
+
+    // move the clock back, the day counter wraps around below day 0 without overflow
+    fn rewind_clock(&mut self, seconds: u64) {
+        if self.rtc_halt {
+            return;
+        }
+
+        let days = (self.rtc_day_hi as u64) << 8 | self.rtc_day_lo as u64;
+        let time = ((days * 24 + self.rtc_hours as u64) * 60 + self.rtc_min as u64) * 60 + self.rtc_sec as u64;
+        let period = RTC_DAY_COUNT * SECONDS_PER_DAY;
+        let time = (time % period + period - seconds % period) % period;
+        self.rtc_sec = (time % 60) as u8;
+        self.rtc_min = (time / 60 % 60) as u8;
+        self.rtc_hours = (time / 3600 % 24) as u8;
+        let days = time / SECONDS_PER_DAY;
+        self.rtc_day_lo = days as u8;
+        self.rtc_day_hi = days >= 0x100;
+    }
+
+    fn get_rtc_footer(&self, timestamp: u64) -> Vec<u8> {
+        let registers = [
+            self.rtc_sec, self.rtc_min, self.rtc_hours, self.rtc_day_lo, self.get_rtc_control(),
+            self.rtc_sec_latch, self.rtc_min_latch, self.rtc_hours_latch, self.rtc_day_latch, self.rtc_control_latch,
+        ];
+
+        let mut footer: Vec<u8> = registers.iter()
+            .flat_map(|&register| (register as u32).to_le_bytes())
+            .collect();
+        footer.extend_from_slice(&timestamp.to_le_bytes());
+        footer
+    }
+
+    // restore the clock then let it run for the time elapsed since the save
+    fn load_rtc_footer(&mut self, footer: &[u8], timestamp: u64) {
+        let register = |index: usize| footer[index * 4];
+
+        self.rtc_sec = register(0);
+        self.rtc_min = register(1);
+        self.rtc_hours = register(2);
+        self.rtc_day_lo = register(3);
+        self.set_rtc_control(register(4));
+        self.rtc_sec_latch = register(5);
+        self.rtc_min_latch = register(6);
+        self.rtc_hours_latch = register(7);
+        self.rtc_day_latch = register(8);
+        self.rtc_control_latch = register(9);
+
+        let mut saved_timestamp = [0; 8];
+        saved_timestamp[..footer.len() - 40].copy_from_slice(&footer[40..]);
+        self.advance_clock(timestamp.saturating_sub(u64::from_le_bytes(saved_timestamp)));
+    }
+
+    // the bank number wraps around the number of banks of the ram
+    fn get_ram_address(&self, address: usize) -> usize {
+        let gb_addr = ((self.ram_bank_number as usize) << RAM_BANK_BIT_OFFSET)
+                            | (address & RAM_ADDR_BIT_MASK);
+        gb_addr % self.ram_bank.len()
     }
 }
 
@@ -112,19 +228,13 @@ impl Mbc for Mbc3 {
         if self.ram_enable {
             match self.ram_bank_number {
                 // here we access the ram banks
-                0x00..=0x03 => {
-                    let gb_addr = ((self.ram_bank_number as usize) << RAM_BANK_BIT_OFFSET)
-                                | (address & 0x1FFF);
-                    self.ram_bank[gb_addr]
-                }
+                0x00..=0x07 if !self.ram_bank.is_empty() => self.ram_bank[self.get_ram_address(address)],
                 // here we access rtc registers
                 0x08 => self.rtc_sec_latch,
                 0x09 => self.rtc_min_latch,
                 0x0A => self.rtc_hours_latch,
                 0x0B => self.rtc_day_latch,
-                0x0C => (self.rtc_day_hi as u8)
-                        | (self.rtc_halt as u8) << 6
-                        | (self.rtc_overflow as u8) << 7,
+                0x0C => self.get_rtc_control(),
                 _ => 0xFF,
             }
         } else {
@@ -141,18 +251,8 @@ impl Mbc for Mbc3 {
                 }
             },
             ROM_BANK_NB_SPACE_START..=ROM_BANK_NB_SPACE_END => {
-                let rom_bank_mask = match self.rom_size {
-                    RomSize::SIZE_32_KB => RomBankMask::MASK_1_BIT,
-                    RomSize::SIZE_64_KB => RomBankMask::MASK_2_BIT,
-                    RomSize::SIZE_128_KB => RomBankMask::MASK_3_BIT,
-                    RomSize::SIZE_256_KB => RomBankMask::MASK_4_BIT,
-                    RomSize::SIZE_512_KB => RomBankMask::MASK_5_BIT,
-                    RomSize::SIZE_1_MB => RomBankMask::MASK_6_BIT,
-                    _ => RomBankMask::MASK_7_BIT,
-                };
-
                 self.rom_bank_number = if data != 0 {
-                    data & (rom_bank_mask as u8)
+                    data & self.rom_bank_mask
                 } else {
                     // if register is set to 0, set it to 1 
                     1
@@ -166,8 +266,9 @@ impl Mbc for Mbc3 {
         match address as u16 {
             RAM_BANK_NB_SPACE_START..=RAM_BANK_NB_SPACE_END => {
                 match data {
-                    0x00..=0x03 => self.ram_bank_number = data & 0x03,
-                    0x08..=0x0C => self.ram_bank_number = data,
+                    _ if data <= self.last_ram_bank => self.ram_bank_number = data,
+                    // rtc registers are only mapped when the cartridge has a clock
+                    0x08..=0x0C if self.rtc => self.ram_bank_number = data,
                     _ => {/* do nothing here */},
                 }
             },
@@ -185,63 +286,42 @@ impl Mbc for Mbc3 {
         }
     }
 
-    fn write_ram (&mut self, address: usize, data: u8) {
+    fn write_ram (&mut self, address: usize, data: u8) -> bool {
         if self.ram_enable {
             match self.ram_bank_number {
                 // here we access the ram banks
-                0x00..=0x03 => {
-                    let gb_addr = ((self.ram_bank_number as usize) << RAM_BANK_BIT_OFFSET)
-                                | (address & 0x1FFF);
+                0x00..=0x07 if !self.ram_bank.is_empty() => {
+                    let gb_addr = self.get_ram_address(address);
                     self.ram_bank[gb_addr] = data;
                 }
                 // here we access rtc registers
-                0x08 => { self.rtc_sec = data }
-                0x09 => { self.rtc_min = data }
-                0x0A => { self.rtc_hours = data }
+                0x08 => { self.rtc_sec = data & 0x3F }
+                0x09 => { self.rtc_min = data & 0x3F }
+                0x0A => { self.rtc_hours = data & 0x1F }
                 0x0B => { self.rtc_day_lo = data }
-                0x0C => { 
-                    self.rtc_day_hi = (data & 0x01) != 0;
-                    self.rtc_halt = (data & 0x40) != 0;
-                    self.rtc_overflow = (data & 0x80) != 0;
-                }
-                _ => {/* do nothing here */}
+                0x0C => { self.set_rtc_control(data) }
+                _ => return false,
             }
+            true
         } else {
             // do nothing when ram is disabled
+            false
         }
     }
 
     fn run (&mut self, cycles: u8) {
+        if !self.rtc {
+            return;
+        }
+
         if !self.rtc_halt {
             self.rtc_cycles += cycles as usize;
 
-            if self.rtc_cycles > ONE_SECOND_IN_CYCLES {
-                let add_sec = (self.rtc_cycles / ONE_SECOND_IN_CYCLES) as u8;
+            if self.rtc_cycles >= ONE_SECOND_IN_CYCLES {
+                let add_sec = self.rtc_cycles / ONE_SECOND_IN_CYCLES;
                 // update rtc cycles
-                self.rtc_cycles = self.rtc_cycles % ONE_SECOND_IN_CYCLES;
-                // update rtc seconds
-                self.rtc_sec +=  add_sec;
-                if self.rtc_sec > 60 {
-                    self.rtc_sec = 0;
-                    self.rtc_min += 1;
-                };
-                // update rtc minutes
-                if self.rtc_min > 60 {
-                    self.rtc_min = 0;
-                    self.rtc_hours += 1;
-                }
-                // update rtc hours
-                if self.rtc_hours >= 24 {
-                    self.rtc_hours = 0;
-                    // check if day has overflowed
-                    if self.rtc_day_hi && self.rtc_day_lo == 0xFF {
-                        self.rtc_overflow = true;
-                    }
-                    // update day value
-                    let (new_value, overflow) = self.rtc_day_lo.overflowing_add(1);
-                    self.rtc_day_lo = new_value;
-                    if overflow {self.rtc_day_hi = overflow};
-                }
+                self.rtc_cycles %= ONE_SECOND_IN_CYCLES;
+                self.advance_clock(add_sec as u64);
             }
         }
 
@@ -251,8 +331,250 @@ impl Mbc for Mbc3 {
             self.rtc_min_latch = self.rtc_min;
             self.rtc_hours_latch = self.rtc_hours;
             self.rtc_day_latch = self.rtc_day_lo;
+            self.rtc_control_latch = self.get_rtc_control();
             // reset latch
             self.latch_rtc_enable = false;
         }
     }
-}
\ No newline at end of file
+
+    fn has_battery (&self) -> bool {
+        self.battery
+    }
+
+    fn dump_ram (&self) -> Vec<u8> {
+        let mut data = self.ram_bank.clone();
+        if self.rtc {
+            data.extend(self.get_rtc_footer(get_unix_time()));
+        }
+        data
+    }
+
+    fn load_ram (&mut self, data: &[u8]) {
+        let size = data.len().min(self.ram_bank.len());
+        self.ram_bank[..size].copy_from_slice(&data[..size]);
+
+        // save files without clock footer only restore the ram
+        let footer = &data[size..];
+        if self.rtc && (footer.len() == RTC_FOOTER_SIZE || footer.len() == RTC_SHORT_FOOTER_SIZE) {
+            self.load_rtc_footer(footer, get_unix_time());
+        }
+    }
+
+    fn advance_rtc (&mut self, seconds: i64) {
+        if self.rtc {
+            if seconds >= 0 {
+                self.advance_clock(seconds as u64);
+            } else {
+                self.rewind_clock(seconds.unsigned_abs());
+            }
+        }
+    }
+}
+
+impl SaveState for Mbc3 {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bool(self.ram_enable);
+        writer.write_u8(self.rom_bank_number);
+        writer.write_u8(self.ram_bank_number);
+        writer.write_bytes(&self.ram_bank);
+        // rtc
+        writer.write_bool(self.latch_rtc_flag);
+        writer.write_bool(self.latch_rtc_enable);
+        writer.write_u64(self.rtc_cycles as u64);
+        writer.write_u8(self.rtc_sec);
+        writer.write_u8(self.rtc_min);
+        writer.write_u8(self.rtc_hours);
+        writer.write_u8(self.rtc_day_lo);
+        writer.write_bool(self.rtc_day_hi);
+        writer.write_bool(self.rtc_halt);
+        writer.write_bool(self.rtc_overflow);
+        writer.write_u8(self.rtc_sec_latch);
+        writer.write_u8(self.rtc_min_latch);
+        writer.write_u8(self.rtc_hours_latch);
+        writer.write_u8(self.rtc_day_latch);
+        writer.write_u8(self.rtc_control_latch);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> std::io::Result<()> {
+        self.ram_enable = reader.read_bool()?;
+        self.rom_bank_number = reader.read_u8()?;
+        self.ram_bank_number = reader.read_u8()?;
+        reader.read_bytes_into(&mut self.ram_bank)?;
+        // rtc
+        self.latch_rtc_flag = reader.read_bool()?;
+        self.latch_rtc_enable = reader.read_bool()?;
+        self.rtc_cycles = reader.read_u64()? as usize;
+        self.rtc_sec = reader.read_u8()?;
+        self.rtc_min = reader.read_u8()?;
+        self.rtc_hours = reader.read_u8()?;
+        self.rtc_day_lo = reader.read_u8()?;
+        self.rtc_day_hi = reader.read_bool()?;
+        self.rtc_halt = reader.read_bool()?;
+        self.rtc_overflow = reader.read_bool()?;
+        self.rtc_sec_latch = reader.read_u8()?;
+        self.rtc_min_latch = reader.read_u8()?;
+        self.rtc_hours_latch = reader.read_u8()?;
+        self.rtc_day_latch = reader.read_u8()?;
+        self.rtc_control_latch = reader.read_u8()?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod mbc3_tests {
+    use super::*;
+    use crate::cartridge::build_bank_numbered_rom;
+
+    fn build_mbc3(mbc_type: MbcType, rom_size: RomSize, ram_size: RamSize) -> Mbc3 {
+        let rom = build_bank_numbered_rom(rom_size);
+        Mbc3::new(mbc_type, rom_size, ram_size, &rom)
+    }
+
+    #[test]
+    fn test_rom_banks() {
+        let mut mbc = build_mbc3(MbcType::MBC_3, RomSize::SIZE_2_MB, RamSize::NO_RAM);
+        mbc.write_bank_0(0x2000, 0x00);
+        assert_eq!(mbc.read_bank_n(0x4000), 0x01);
+        mbc.write_bank_0(0x2000, 0xFF);
+        assert_eq!(mbc.read_bank_n(0x4000), 0x7F);
+
+        // the bank number is masked by the rom size
+        let mut mbc = build_mbc3(MbcType::MBC_3, RomSize::SIZE_256_KB, RamSize::NO_RAM);
+        mbc.write_bank_0(0x2000, 0x13);
+        assert_eq!(mbc.read_bank_n(0x4000), 0x03);
+
+        // mbc30 uses the 8 bits of the register
+        let mut mbc = build_mbc3(MbcType::MBC_3_RAM_BAT, RomSize::SIZE_4_MB, RamSize::SIZE_32_KB);
+        mbc.write_bank_0(0x2000, 0xFF);
+        assert_eq!(mbc.read_bank_n(0x4000), 0xFF);
+    }
+
+    #[test]
+    fn test_ram_banks() {
+        let mut mbc = build_mbc3(MbcType::MBC_3_RAM, RomSize::SIZE_32_KB, RamSize::SIZE_32_KB);
+        assert!(!mbc.has_battery());
+        mbc.write_bank_0(0x0000, 0x0A);
+        for bank in 0..4 {
+            mbc.write_bank_n(0x4000, bank);
+            mbc.write_ram(0xA000, bank + 0x10);
+        }
+        // banks 4 to 7 are only available on mbc30
+        mbc.write_bank_n(0x4000, 0x04);
+        assert_eq!(mbc.read_ram(0xA000), 0x13);
+
+        let mut mbc = build_mbc3(MbcType::MBC_3_RAM_BAT, RomSize::SIZE_32_KB, RamSize::SIZE_64_KB);
+        mbc.write_bank_0(0x0000, 0x0A);
+        for bank in 0..8 {
+            mbc.write_bank_n(0x4000, bank);
+            mbc.write_ram(0xA000, bank + 0x10);
+        }
+        assert_eq!(mbc.dump_ram()[7 << 13], 0x17);
+
+        // no ram at all
+        let mut mbc = build_mbc3(MbcType::MBC_3, RomSize::SIZE_32_KB, RamSize::NO_RAM);
+        mbc.write_bank_0(0x0000, 0x0A);
+        mbc.write_ram(0xA000, 0x12);
+        assert_eq!(mbc.read_ram(0xA000), 0xFF);
+    }
+
+    #[test]
+    fn test_rtc_only_with_timer() {
+        let mut mbc = build_mbc3(MbcType::MBC_3_TIM_BAT, RomSize::SIZE_32_KB, RamSize::NO_RAM);
+        assert!(mbc.has_battery());
+        mbc.write_bank_0(0x0000, 0x0A);
+        mbc.write_bank_n(0x4000, 0x08);
+        mbc.write_ram(0xA000, 0x12);
+        mbc.write_bank_n(0x6000, 0x00);
+        mbc.write_bank_n(0x6000, 0x01);
+        mbc.run(4);
+        assert_eq!(mbc.read_ram(0xA000), 0x12);
+
+        // registers are not mapped without the timer
+        let mut mbc = build_mbc3(MbcType::MBC_3_RAM_BAT, RomSize::SIZE_32_KB, RamSize::SIZE_8_KB);
+        mbc.write_bank_0(0x0000, 0x0A);
+        mbc.write_ram(0xA000, 0x34);
+        // the ram bank stays selected
+        mbc.write_bank_n(0x4000, 0x08);
+        assert_eq!(mbc.read_ram(0xA000), 0x34);
+    }
+
+    #[test]
+    fn test_rtc_advance() {
+        let mut mbc = build_mbc3(MbcType::MBC_3_TIM_RAM_BAT, RomSize::SIZE_32_KB, RamSize::SIZE_8_KB);
+        mbc.rtc_sec = 59;
+        mbc.rtc_min = 59;
+        mbc.rtc_hours = 23;
+        mbc.rtc_day_lo = 0xFF;
+
+        // one second carries over to the day counter
+        for _ in 0..(ONE_SECOND_IN_CYCLES / 128) {
+            mbc.run(128);
+        }
+        assert_eq!((mbc.rtc_sec, mbc.rtc_min, mbc.rtc_hours, mbc.rtc_day_lo, mbc.rtc_day_hi), (0, 0, 0, 0x00, true));
+        assert!(!mbc.rtc_overflow);
+
+        // the 9 bits day counter wraps around
+        mbc.advance_rtc(256 * 24 * 3600 + 3 * 3600 + 2 * 60 + 1);
+        assert_eq!((mbc.rtc_sec, mbc.rtc_min, mbc.rtc_hours, mbc.rtc_day_lo, mbc.rtc_day_hi), (1, 2, 3, 0x00, false));
+        assert!(mbc.rtc_overflow);
+
+        // moving back wraps around below day 0
+        mbc.advance_rtc(-(3 * 3600 + 2 * 60 + 2));
+        assert_eq!((mbc.rtc_sec, mbc.rtc_min, mbc.rtc_hours, mbc.rtc_day_lo, mbc.rtc_day_hi), (59, 59, 23, 0xFF, true));
+        mbc.advance_rtc(-(256 * 24 * 3600));
+        assert_eq!((mbc.rtc_sec, mbc.rtc_min, mbc.rtc_hours, mbc.rtc_day_lo, mbc.rtc_day_hi), (59, 59, 23, 0xFF, false));
+
+        // a halted clock doesn't move
+        mbc.write_bank_0(0x0000, 0x0A);
+        mbc.write_bank_n(0x4000, 0x0C);
+        mbc.write_ram(0xA000, 0x40);
+        mbc.advance_rtc(3600);
+        assert_eq!(mbc.rtc_hours, 23);
+        mbc.advance_rtc(-3600);
+        assert_eq!(mbc.rtc_hours, 23);
+    }
+
+    #[test]
+    fn test_rtc_save_file() {
+        let mut mbc = build_mbc3(MbcType::MBC_3_TIM_RAM_BAT, RomSize::SIZE_32_KB, RamSize::SIZE_8_KB);
+        mbc.write_bank_0(0x0000, 0x0A);
+        mbc.write_ram(0xA000, 0x12);
+        mbc.rtc_sec = 10;
+        mbc.rtc_min = 20;
+        mbc.rtc_hours = 22;
+        mbc.rtc_day_lo = 0xFF;
+        mbc.rtc_day_hi = true;
+        mbc.rtc_hours_latch = 5;
+        mbc.rtc_control_latch = 0x40;
+
+        let data = mbc.dump_ram();
+        assert_eq!(data.len(), 0x2000 + RTC_FOOTER_SIZE);
+        assert_eq!(data[0x2000..0x2014], [10, 0, 0, 0, 20, 0, 0, 0, 22, 0, 0, 0, 0xFF, 0, 0, 0, 0x01, 0, 0, 0]);
+        assert_eq!(data[0x201C], 5);
+        assert_eq!(data[0x2024], 0x40);
+
+        // two hours later, the day counter overflows
+        let mut footer = mbc.get_rtc_footer(1000);
+        let mut mbc = build_mbc3(MbcType::MBC_3_TIM_RAM_BAT, RomSize::SIZE_32_KB, RamSize::SIZE_8_KB);
+        mbc.load_rtc_footer(&footer, 1000 + 2 * 3600 + 50);
+        assert_eq!((mbc.rtc_sec, mbc.rtc_min, mbc.rtc_hours, mbc.rtc_day_lo, mbc.rtc_day_hi), (0, 21, 0, 0x00, false));
+        assert!(mbc.rtc_overflow);
+        assert_eq!(mbc.rtc_hours_latch, 5);
+        assert_eq!(mbc.rtc_control_latch, 0x40);
+
+        // short footer with a 32 bits timestamp
+        footer.truncate(RTC_SHORT_FOOTER_SIZE);
+        let mut mbc = build_mbc3(MbcType::MBC_3_TIM_RAM_BAT, RomSize::SIZE_32_KB, RamSize::SIZE_8_KB);
+        mbc.load_rtc_footer(&footer, 1000 + 60);
+        assert_eq!((mbc.rtc_sec, mbc.rtc_min, mbc.rtc_hours), (10, 21, 22));
+
+        // the footer is ignored without clock
+        let mut data = vec![0x34; 0x2000];
+        data.extend(footer);
+        let mut mbc = build_mbc3(MbcType::MBC_3_RAM_BAT, RomSize::SIZE_32_KB, RamSize::SIZE_8_KB);
+        mbc.load_ram(&data);
+        assert_eq!(mbc.dump_ram(), vec![0x34; 0x2000]);
+        assert_eq!(mbc.rtc_sec, 0);
+    }
+}
@@ -0,0 +1,278 @@
+use crate::cartridge::{MbcType, RomSize, RamSize, Mbc};
+use crate::savestate::{SaveState, StateWriter, StateReader};
+
+const RAM_ENABLE_SPACE_START: u16 = 0x0000;
+const RAM_ENABLE_SPACE_END: u16 = 0x1FFF;
+
+const ROM_BANK_NB_LOW_SPACE_START: u16 = 0x2000;
+const ROM_BANK_NB_LOW_SPACE_END: u16 = 0x2FFF;
+
+const ROM_BANK_NB_HIGH_SPACE_START: u16 = 0x3000;
+const ROM_BANK_NB_HIGH_SPACE_END: u16 = 0x3FFF;
+
+const RAM_BANK_NB_SPACE_START: u16 = 0x4000;
+const RAM_BANK_NB_SPACE_END: u16 = 0x5FFF;
+
+const UNUSED_SPACE_START: u16 = 0x6000;
+const UNUSED_SPACE_END: u16 = 0x7FFF;
+
+const ENABLE_RAM_FLAG: u8 = 0x0A;
+
+const GB_ADDR_BIT_MASK: usize = 0x3FFF;
+const RAM_ADDR_BIT_MASK: usize = 0x1FFF;
+const ROM_BANK_BIT_OFFSET: usize = 14;
+const RAM_BANK_BIT_OFFSET: usize = 13;
+
+// on rumble cartridges the bit 3 of the ram bank register drives the motor
+const RUMBLE_MOTOR_BIT: u8 = 0x08;
+
+pub struct Mbc5 {
+    // config
+    battery: bool,
+    rumble: bool,
+    // internal registers
+    ram_enable: bool,
+    rom_bank_number: u16,
+    ram_bank_number: u8,
+    // rumble motor
+    motor_on: bool,
+    motor_changed: bool,
+    // memory
+    rom_bank: Vec<u8>,
+    ram_bank: Vec<u8>,
+}
+
+impl Mbc5 {
+    pub fn new(mbc_type: MbcType, rom_size: RomSize, ram_size: RamSize, rom: &[u8]) -> Mbc5 {
+        let mut rom_bank: Vec<u8> = vec![0xFF; rom_size as usize];
+        let ram_bank: Vec<u8> = vec![0xFF; ram_size as usize];
+
+        // copy all rom data
+        let size = rom.len().min(rom_bank.len());
+        rom_bank[..size].copy_from_slice(&rom[..size]);
+
+        Mbc5 {
+            // config
+            battery: mbc_type.has_battery(),
+            rumble: matches!(mbc_type,
+                MbcType::MBC_5_RUMBLE
+                | MbcType::MBC_5_RUMBLE_RAM
+                | MbcType::MBC_5_RUMBLE_RAM_BAT),
+            // internal registers
+            ram_enable: false,
+            rom_bank_number: 1,
+            ram_bank_number: 0,
+            // rumble motor
+            motor_on: false,
+            motor_changed: false,
+            // memory
+            rom_bank,
+            ram_bank,
+        }
+    }
+
+    // the bank number wraps around the number of banks of the rom / ram chip
+    fn get_ram_address(&self, address: usize) -> usize {
+        let gb_addr = ((self.ram_bank_number as usize) << RAM_BANK_BIT_OFFSET)
+                            | (address & RAM_ADDR_BIT_MASK);
+        gb_addr % self.ram_bank.len()
+    }
+}
+
+impl Mbc for Mbc5 {
+    fn read_bank_0 (&self, address: usize) -> u8 {
+        let gb_addr = address & GB_ADDR_BIT_MASK;
+        self.rom_bank[gb_addr]
+    }
+
+    fn read_bank_n (&self, address: usize) -> u8 {
+        // unlike mbc 1 and 3, bank 0 can be mapped here
+        let gb_addr = ((self.rom_bank_number as usize) << ROM_BANK_BIT_OFFSET)
+                            | (address & GB_ADDR_BIT_MASK);
+        self.rom_bank[gb_addr % self.rom_bank.len()]
+    }
+
+    fn read_ram (&self, address: usize) -> u8 {
+        if self.ram_enable && !self.ram_bank.is_empty() {
+            self.ram_bank[self.get_ram_address(address)]
+        } else {
+            // RAM is disabled, returns 0xFF
+            0xFF
+        }
+    }
+
+    fn write_bank_0 (&mut self, address: usize, data: u8) {
+        match address as u16 {
+            RAM_ENABLE_SPACE_START..=RAM_ENABLE_SPACE_END => {
+                self.ram_enable = (data & 0x0F) == ENABLE_RAM_FLAG;
+            },
+            ROM_BANK_NB_LOW_SPACE_START..=ROM_BANK_NB_LOW_SPACE_END => {
+                self.rom_bank_number = (self.rom_bank_number & 0x100) | data as u16;
+            },
+            ROM_BANK_NB_HIGH_SPACE_START..=ROM_BANK_NB_HIGH_SPACE_END => {
+                self.rom_bank_number = (self.rom_bank_number & 0x0FF) | ((data as u16 & 0x01) << 8);
+            },
+            _ => panic!("mbc 5 bank 0 address {:x} doesn't exists.", address),
+        }
+    }
+
+    fn write_bank_n (&mut self, address: usize, data: u8) {
+        match address as u16 {
+            RAM_BANK_NB_SPACE_START..=RAM_BANK_NB_SPACE_END => {
+                if self.rumble {
+                    let motor_on = (data & RUMBLE_MOTOR_BIT) != 0;
+                    self.motor_changed |= motor_on != self.motor_on;
+                    self.motor_on = motor_on;
+                    self.ram_bank_number = data & 0x07;
+                } else {
+                    self.ram_bank_number = data & 0x0F;
+                }
+            },
+            UNUSED_SPACE_START..=UNUSED_SPACE_END => {/* do nothing here */},
+            _ => panic!("mbc 5 bank n address {:x} doesn't exists.", address),
+        }
+    }
+
+    fn write_ram (&mut self, address: usize, data: u8) -> bool {
+        if self.ram_enable && !self.ram_bank.is_empty() {
+            let gb_addr = self.get_ram_address(address);
+            self.ram_bank[gb_addr] = data;
+            true
+        } else {
+            // do nothing when ram is disabled
+            false
+        }
+    }
+
+    // not used for this mbc, doesn't do anything
+    fn run (&mut self, _: u8) {}
+
+    fn has_battery (&self) -> bool {
+        self.battery
+    }
+
+    fn dump_ram (&self) -> Vec<u8> {
+        self.ram_bank.clone()
+    }
+
+    fn load_ram (&mut self, data: &[u8]) {
+        let size = data.len().min(self.ram_bank.len());
+        self.ram_bank[..size].copy_from_slice(&data[..size]);
+    }
+
+    fn take_rumble (&mut self) -> Option<bool> {
+        if self.motor_changed {
+            self.motor_changed = false;
+            Some(self.motor_on)
+        } else {
+            None
+        }
+    }
+}
+
+impl SaveState for Mbc5 {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bool(self.ram_enable);
+        writer.write_u16(self.rom_bank_number);
+        writer.write_u8(self.ram_bank_number);
+        writer.write_bool(self.motor_on);
+        writer.write_bytes(&self.ram_bank);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> std::io::Result<()> {
+        self.ram_enable = reader.read_bool()?;
+        self.rom_bank_number = reader.read_u16()?;
+        self.ram_bank_number = reader.read_u8()?;
+        // the frontend is told about the restored motor state
+        let motor_on = reader.read_bool()?;
+        self.motor_changed = motor_on != self.motor_on;
+        self.motor_on = motor_on;
+        reader.read_bytes_into(&mut self.ram_bank)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod mbc5_tests {
+    use super::*;
+    use crate::cartridge::build_bank_numbered_rom;
+
+    fn build_mbc5(mbc_type: MbcType, rom_size: RomSize, ram_size: RamSize) -> Mbc5 {
+        let rom = build_bank_numbered_rom(rom_size);
+        Mbc5::new(mbc_type, rom_size, ram_size, &rom)
+    }
+
+    #[test]
+    fn test_rom_banks() {
+        let mut mbc = build_mbc5(MbcType::MBC_5, RomSize::SIZE_8_MB, RamSize::NO_RAM);
+        assert_eq!(mbc.read_bank_n(0x4000), 0x01);
+
+        // bank 0 can be selected
+        mbc.write_bank_0(0x2000, 0x00);
+        assert_eq!(mbc.read_bank_n(0x4000), 0x00);
+
+        // 9th bit of the bank number
+        mbc.write_bank_0(0x2000, 0x42);
+        mbc.write_bank_0(0x3000, 0x01);
+        assert_eq!((mbc.read_bank_n(0x4000), mbc.read_bank_n(0x4001)), (0x42, 0x01));
+        mbc.write_bank_0(0x2FFF, 0x05);
+        assert_eq!((mbc.read_bank_n(0x4000), mbc.read_bank_n(0x4001)), (0x05, 0x01));
+        mbc.write_bank_0(0x3FFF, 0xFE);
+        assert_eq!((mbc.read_bank_n(0x4000), mbc.read_bank_n(0x4001)), (0x05, 0x00));
+
+        // the bank number wraps around smaller roms
+        let mut mbc = build_mbc5(MbcType::MBC_5, RomSize::SIZE_64_KB, RamSize::NO_RAM);
+        mbc.write_bank_0(0x2000, 0x06);
+        assert_eq!(mbc.read_bank_n(0x4000), 0x02);
+        assert_eq!(mbc.read_bank_0(0x0000), 0x00);
+    }
+
+    #[test]
+    fn test_ram_banks() {
+        let mut mbc = build_mbc5(MbcType::MBC_5_RAM_BAT, RomSize::SIZE_32_KB, RamSize::SIZE_128_KB);
+        assert!(mbc.has_battery());
+
+        // ram is disabled at startup
+        mbc.write_ram(0xA000, 0x12);
+        assert_eq!(mbc.read_ram(0xA000), 0xFF);
+
+        mbc.write_bank_0(0x0000, 0x0A);
+        for bank in 0..16 {
+            mbc.write_bank_n(0x4000, bank);
+            mbc.write_ram(0xA000, bank + 0x10);
+        }
+        for bank in 0..16 {
+            mbc.write_bank_n(0x4000, bank);
+            assert_eq!(mbc.read_ram(0xA000), bank + 0x10);
+        }
+        assert_eq!(mbc.dump_ram()[15 << 13], 0x1F);
+
+        // any value other than 0x0A disables the ram
+        mbc.write_bank_0(0x0000, 0x00);
+        assert_eq!(mbc.read_ram(0xA000), 0xFF);
+    }
+
+    #[test]
+    fn test_rumble() {
+        let mut mbc = build_mbc5(MbcType::MBC_5_RUMBLE_RAM, RomSize::SIZE_32_KB, RamSize::SIZE_32_KB);
+        assert!(!mbc.has_battery());
+        assert_eq!(mbc.take_rumble(), None);
+
+        mbc.write_bank_0(0x0000, 0x0A);
+        mbc.write_bank_n(0x4000, 0x09);
+        mbc.write_ram(0xA000, 0x34);
+        assert_eq!(mbc.take_rumble(), Some(true));
+        assert_eq!(mbc.take_rumble(), None);
+
+        // the motor bit doesn't select a ram bank
+        mbc.write_bank_n(0x4000, 0x01);
+        assert_eq!(mbc.read_ram(0xA000), 0x34);
+        assert_eq!(mbc.take_rumble(), Some(false));
+
+        // no motor on other cartridges
+        let mut mbc = build_mbc5(MbcType::MBC_5_RAM, RomSize::SIZE_32_KB, RamSize::SIZE_128_KB);
+        mbc.write_bank_n(0x4000, 0x08);
+        assert_eq!(mbc.take_rumble(), None);
+    }
+}
@@ -0,0 +1,461 @@
+use crate::cartridge::{MbcType, RomSize, Mbc};
+use crate::savestate::{SaveState, StateWriter, StateReader};
+
+const RAM_ENABLE_SPACE_START: u16 = 0x0000;
+const RAM_ENABLE_SPACE_END: u16 = 0x1FFF;
+
+const ROM_BANK_NB_SPACE_START: u16 = 0x2000;
+const ROM_BANK_NB_SPACE_END: u16 = 0x3FFF;
+
+const RAM_ENABLE_2_SPACE_START: u16 = 0x4000;
+const RAM_ENABLE_2_SPACE_END: u16 = 0x5FFF;
+
+const UNUSED_SPACE_START: u16 = 0x6000;
+const UNUSED_SPACE_END: u16 = 0x7FFF;
+
+// the registers are only mapped once both enable registers are set
+const ENABLE_RAM_FLAG: u8 = 0x0A;
+const ENABLE_RAM_2_FLAG: u8 = 0x40;
+
+// registers are repeated every 16 bytes in 0xA000 - 0xAFFF
+const REGISTER_SPACE_MASK: usize = 0xF000;
+const REGISTER_SPACE_START: usize = 0xA000;
+const REGISTER_INDEX_BIT_OFFSET: usize = 4;
+
+const ACCELEROMETER_ERASE_REGISTER: usize = 0x0;
+const ACCELEROMETER_LATCH_REGISTER: usize = 0x1;
+const ACCELEROMETER_X_LOW_REGISTER: usize = 0x2;
+const ACCELEROMETER_X_HIGH_REGISTER: usize = 0x3;
+const ACCELEROMETER_Y_LOW_REGISTER: usize = 0x4;
+const ACCELEROMETER_Y_HIGH_REGISTER: usize = 0x5;
+const UNKNOWN_ZERO_REGISTER: usize = 0x6;
+const EEPROM_REGISTER: usize = 0x8;
+
+const ACCELEROMETER_ERASE_FLAG: u8 = 0x55;
+const ACCELEROMETER_LATCH_FLAG: u8 = 0xAA;
+
+// accelerometer values when the cartridge lies flat, and for a 1 g tilt
+const ACCELEROMETER_ERASED: u16 = 0x8000;
+const ACCELEROMETER_CENTER: f32 = 33232.0; // 0x81D0
+const ACCELEROMETER_ONE_G: f32 = 112.0; // 0x70
+
+// eeprom register pins
+const EEPROM_CS: u8 = 0x80;
+const EEPROM_CLK: u8 = 0x40;
+const EEPROM_DI: u8 = 0x02;
+const EEPROM_DO: u8 = 0x01;
+
+// 93LC56 serial eeprom, 128 words of 16 bits. Commands are a start bit, a 2 bits
+// opcode and an 8 bits address, write commands are followed by the 16 bits data.
+const EEPROM_SIZE: usize = 0x100;
+const EEPROM_WORD_MASK: u16 = 0x7F;
+const EEPROM_COMMAND_BITS: u8 = 11;
+const EEPROM_WRITE_COMMAND_BITS: u8 = 27;
+const EEPROM_WORD_BITS: u8 = 16;
+
+const EEPROM_EXTENDED: u16 = 0x0;
+const EEPROM_WRITE: u16 = 0x1;
+const EEPROM_READ: u16 = 0x2;
+const EEPROM_ERASE: u16 = 0x3;
+
+// extended commands use the 2 upper bits of the address
+const EEPROM_WRITE_DISABLE: u16 = 0x0;
+const EEPROM_WRITE_ALL: u16 = 0x1;
+const EEPROM_ERASE_ALL: u16 = 0x2;
+const EEPROM_WRITE_ENABLE: u16 = 0x3;
+
+const GB_ADDR_BIT_MASK: usize = 0x3FFF;
+const ROM_BANK_BIT_OFFSET: usize = 14;
+
+pub struct Mbc7 {
+    // config
+    battery: bool,
+    // internal registers
+    ram_enable: bool,
+    ram_enable_2: bool,
+    rom_bank_number: u8,
+    // memory
+    rom_bank: Vec<u8>,
+    // accelerometer, the tilt is given by the host in g
+    tilt_x: f32,
+    tilt_y: f32,
+    accelerometer_x: u16,
+    accelerometer_y: u16,
+    accelerometer_erased: bool,
+    // eeprom
+    eeprom: Vec<u8>,
+    eeprom_pins: u8,
+    eeprom_command: u32,
+    eeprom_command_bits: u8,
+    eeprom_read_data: u16,
+    eeprom_read_bits: u8,
+    eeprom_write_enable: bool,
+}
+
+impl Mbc7 {
+    pub fn new(mbc_type: MbcType, rom_size: RomSize, rom: &[u8]) -> Mbc7 {
+        let mut rom_bank: Vec<u8> = vec![0xFF; rom_size as usize];
+
+        // copy all rom data
+        let size = rom.len().min(rom_bank.len());
+        rom_bank[..size].copy_from_slice(&rom[..size]);
+
+        Mbc7 {
+            // config
+            battery: mbc_type.has_battery(),
+            // internal registers
+            ram_enable: false,
+            ram_enable_2: false,
+            rom_bank_number: 1,
+            // memory
+            rom_bank,
+            // accelerometer
+            tilt_x: 0.0,
+            tilt_y: 0.0,
+            accelerometer_x: ACCELEROMETER_ERASED,
+            accelerometer_y: ACCELEROMETER_ERASED,
+            accelerometer_erased: false,
+            // eeprom
+            eeprom: vec![0xFF; EEPROM_SIZE],
+            eeprom_pins: EEPROM_DO,
+            eeprom_command: 0,
+            eeprom_command_bits: 0,
+            eeprom_read_data: 0,
+            eeprom_read_bits: 0,
+            eeprom_write_enable: false,
+        }
+    }
+
+    fn get_accelerometer_value(tilt: f32) -> u16 {
+        (ACCELEROMETER_CENTER + tilt * ACCELEROMETER_ONE_G) as u16
+    }
+
+    // words are stored in little endian in the save file
+    fn read_word(&self, address: u16) -> u16 {
+        let index = ((address & EEPROM_WORD_MASK) as usize) * 2;
+        u16::from_le_bytes([self.eeprom[index], self.eeprom[index + 1]])
+    }
+
+    fn write_word(&mut self, address: u16, data: u16) {
+        let index = ((address & EEPROM_WORD_MASK) as usize) * 2;
+        self.eeprom[index..index + 2].copy_from_slice(&data.to_le_bytes());
+    }
+
+    fn write_eeprom_pins(&mut self, data: u8) {
+        let previous_pins = self.eeprom_pins;
+        self.eeprom_pins = (data & (EEPROM_CS | EEPROM_CLK | EEPROM_DI)) | (previous_pins & EEPROM_DO);
+
+        // the eeprom is reset when it is not selected
+        if data & EEPROM_CS == 0 {
+            self.eeprom_command = 0;
+            self.eeprom_command_bits = 0;
+            self.eeprom_read_bits = 0;
+            self.eeprom_pins |= EEPROM_DO;
+            return;
+        }
+
+        // bits are sampled on the rising edge of the clock
+        if previous_pins & EEPROM_CLK != 0 || data & EEPROM_CLK == 0 {
+            return;
+        }
+
+        // the word of a read command is shifted out first
+        if self.eeprom_read_bits > 0 {
+            self.eeprom_read_bits -= 1;
+            let data_out = (self.eeprom_read_data >> self.eeprom_read_bits) & 0x01 != 0;
+            self.set_data_out(data_out);
+            return;
+        }
+
+        let data_in = data & EEPROM_DI != 0;
+        // wait for the start bit
+        if self.eeprom_command_bits == 0 && !data_in {
+            return;
+        }
+
+        self.eeprom_command = (self.eeprom_command << 1) | data_in as u32;
+        self.eeprom_command_bits += 1;
+        self.run_eeprom_command();
+    }
+
+    fn set_data_out(&mut self, data_out: bool) {
+        if data_out {
+            self.eeprom_pins |= EEPROM_DO;
+        } else {
+            self.eeprom_pins &= !EEPROM_DO;
+        }
+    }
+
+    fn run_eeprom_command(&mut self) {
+        // opcode and address are followed by the data of write commands
+        let header = if self.eeprom_command_bits >= EEPROM_COMMAND_BITS {
+            (self.eeprom_command >> (self.eeprom_command_bits - EEPROM_COMMAND_BITS)) as u16
+        } else {
+            return;
+        };
+        let opcode = (header >> 8) & 0x03;
+        let address = header & 0xFF;
+        let extended_opcode = address >> 6;
+        let has_data = opcode == EEPROM_WRITE || (opcode == EEPROM_EXTENDED && extended_opcode == EEPROM_WRITE_ALL);
+
+        if has_data && self.eeprom_command_bits < EEPROM_WRITE_COMMAND_BITS {
+            return;
+        }
+        let data = (self.eeprom_command & 0xFFFF) as u16;
+
+        match opcode {
+            EEPROM_READ => {
+                // a dummy 0 comes before the word
+                self.eeprom_read_data = self.read_word(address);
+                self.eeprom_read_bits = EEPROM_WORD_BITS;
+                self.set_data_out(false);
+            },
+            EEPROM_WRITE if self.eeprom_write_enable => self.write_word(address, data),
+            EEPROM_ERASE if self.eeprom_write_enable => self.write_word(address, 0xFFFF),
+            EEPROM_EXTENDED => match extended_opcode {
+                EEPROM_WRITE_DISABLE => self.eeprom_write_enable = false,
+                EEPROM_WRITE_ENABLE => self.eeprom_write_enable = true,
+                EEPROM_WRITE_ALL if self.eeprom_write_enable => {
+                    for word in 0..=EEPROM_WORD_MASK {
+                        self.write_word(word, data);
+                    }
+                },
+                EEPROM_ERASE_ALL if self.eeprom_write_enable => self.eeprom.fill(0xFF),
+                _ => {/* do nothing here */},
+            },
+            _ => {/* do nothing here */},
+        }
+
+        // writes are immediate, the eeprom is always ready
+        if opcode != EEPROM_READ {
+            self.set_data_out(true);
+        }
+
+        self.eeprom_command = 0;
+        self.eeprom_command_bits = 0;
+    }
+}
+
+impl Mbc for Mbc7 {
+    fn read_bank_0 (&self, address: usize) -> u8 {
+        let gb_addr = address & GB_ADDR_BIT_MASK;
+        self.rom_bank[gb_addr]
+    }
+
+    fn read_bank_n (&self, address: usize) -> u8 {
+        let gb_addr = ((self.rom_bank_number as usize) << ROM_BANK_BIT_OFFSET)
+                            | (address & GB_ADDR_BIT_MASK);
+        self.rom_bank[gb_addr % self.rom_bank.len()]
+    }
+
+    fn read_ram (&self, address: usize) -> u8 {
+        if !self.ram_enable || !self.ram_enable_2 || address & REGISTER_SPACE_MASK != REGISTER_SPACE_START {
+            return 0xFF;
+        }
+
+        match (address >> REGISTER_INDEX_BIT_OFFSET) & 0x0F {
+            ACCELEROMETER_X_LOW_REGISTER => self.accelerometer_x as u8,
+            ACCELEROMETER_X_HIGH_REGISTER => (self.accelerometer_x >> 8) as u8,
+            ACCELEROMETER_Y_LOW_REGISTER => self.accelerometer_y as u8,
+            ACCELEROMETER_Y_HIGH_REGISTER => (self.accelerometer_y >> 8) as u8,
+            UNKNOWN_ZERO_REGISTER => 0x00,
+            EEPROM_REGISTER => self.eeprom_pins,
+            _ => 0xFF,
+        }
+    }
+
+    fn write_bank_0 (&mut self, address: usize, data: u8) {
+        match address as u16 {
+            RAM_ENABLE_SPACE_START..=RAM_ENABLE_SPACE_END => {
+                self.ram_enable = data == ENABLE_RAM_FLAG;
+            },
+            ROM_BANK_NB_SPACE_START..=ROM_BANK_NB_SPACE_END => {
+                self.rom_bank_number = data & 0x7F;
+            },
+            _ => panic!("mbc 7 bank 0 address {:x} doesn't exists.", address),
+        }
+    }
+
+    fn write_bank_n (&mut self, address: usize, data: u8) {
+        match address as u16 {
+            RAM_ENABLE_2_SPACE_START..=RAM_ENABLE_2_SPACE_END => {
+                self.ram_enable_2 = data == ENABLE_RAM_2_FLAG;
+            },
+            UNUSED_SPACE_START..=UNUSED_SPACE_END => {/* do nothing here */},
+            _ => panic!("mbc 7 bank n address {:x} doesn't exists.", address),
+        }
+    }
+
+    fn write_ram (&mut self, address: usize, data: u8) -> bool {
+        if !self.ram_enable || !self.ram_enable_2 || address & REGISTER_SPACE_MASK != REGISTER_SPACE_START {
+            return false;
+        }
+
+        match (address >> REGISTER_INDEX_BIT_OFFSET) & 0x0F {
+            ACCELEROMETER_ERASE_REGISTER if data == ACCELEROMETER_ERASE_FLAG => {
+                self.accelerometer_x = ACCELEROMETER_ERASED;
+                self.accelerometer_y = ACCELEROMETER_ERASED;
+                self.accelerometer_erased = true;
+            },
+            // a new value can only be latched after an erase
+            ACCELEROMETER_LATCH_REGISTER if data == ACCELEROMETER_LATCH_FLAG && self.accelerometer_erased => {
+                self.accelerometer_x = Mbc7::get_accelerometer_value(self.tilt_x);
+                self.accelerometer_y = Mbc7::get_accelerometer_value(self.tilt_y);
+                self.accelerometer_erased = false;
+            },
+            // only the eeprom is stored in the save file
+            EEPROM_REGISTER => {
+                self.write_eeprom_pins(data);
+                return true;
+            },
+            _ => {/* do nothing here */},
+        }
+
+        false
+    }
+
+    // not used for this mbc, doesn't do anything
+    fn run (&mut self, _: u8) {}
+
+    fn has_battery (&self) -> bool {
+        self.battery
+    }
+
+    // the eeprom is the save file
+    fn dump_ram (&self) -> Vec<u8> {
+        self.eeprom.clone()
+    }
+
+    fn load_ram (&mut self, data: &[u8]) {
+        let size = data.len().min(self.eeprom.len());
+        self.eeprom[..size].copy_from_slice(&data[..size]);
+    }
+
+    fn set_tilt (&mut self, x: f32, y: f32) {
+        self.tilt_x = x;
+        self.tilt_y = y;
+    }
+}
+
+impl SaveState for Mbc7 {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bool(self.ram_enable);
+        writer.write_bool(self.ram_enable_2);
+        writer.write_u8(self.rom_bank_number);
+        // accelerometer
+        writer.write_u16(self.accelerometer_x);
+        writer.write_u16(self.accelerometer_y);
+        writer.write_bool(self.accelerometer_erased);
+        // eeprom
+        writer.write_bytes(&self.eeprom);
+        writer.write_u8(self.eeprom_pins);
+        writer.write_u32(self.eeprom_command);
+        writer.write_u8(self.eeprom_command_bits);
+        writer.write_u16(self.eeprom_read_data);
+        writer.write_u8(self.eeprom_read_bits);
+        writer.write_bool(self.eeprom_write_enable);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> std::io::Result<()> {
+        self.ram_enable = reader.read_bool()?;
+        self.ram_enable_2 = reader.read_bool()?;
+        self.rom_bank_number = reader.read_u8()?;
+        // accelerometer
+        self.accelerometer_x = reader.read_u16()?;
+        self.accelerometer_y = reader.read_u16()?;
+        self.accelerometer_erased = reader.read_bool()?;
+        // eeprom
+        reader.read_bytes_into(&mut self.eeprom)?;
+        self.eeprom_pins = reader.read_u8()?;
+        self.eeprom_command = reader.read_u32()?;
+        self.eeprom_command_bits = reader.read_u8()?;
+        self.eeprom_read_data = reader.read_u16()?;
+        self.eeprom_read_bits = reader.read_u8()?;
+        self.eeprom_write_enable = reader.read_bool()?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod mbc7_tests {
+    use super::*;
+
+    fn build_mbc7() -> Mbc7 {
+        let rom = vec![0xFF; RomSize::SIZE_1_MB as usize];
+        let mut mbc = Mbc7::new(MbcType::MBC_7, RomSize::SIZE_1_MB, &rom);
+        mbc.write_bank_0(0x0000, ENABLE_RAM_FLAG);
+        mbc.write_bank_n(0x4000, ENABLE_RAM_2_FLAG);
+        mbc
+    }
+
+    // clock the bits in, most significant first, and return the bits clocked out
+    fn send_bits(mbc: &mut Mbc7, data: u32, bits: u8) -> u32 {
+        let mut data_out = 0;
+        for bit in (0..bits).rev() {
+            let data_in = if (data >> bit) & 0x01 != 0 { EEPROM_DI } else { 0 };
+            mbc.write_ram(0xA080, EEPROM_CS | data_in);
+            mbc.write_ram(0xA080, EEPROM_CS | EEPROM_CLK | data_in);
+            data_out = (data_out << 1) | (mbc.read_ram(0xA080) & EEPROM_DO) as u32;
+        }
+        data_out
+    }
+
+    fn send_command(mbc: &mut Mbc7, opcode: u16, address: u16) {
+        mbc.write_ram(0xA080, 0x00);
+        send_bits(mbc, (0x4 | opcode as u32) << 8 | address as u32, EEPROM_COMMAND_BITS);
+    }
+
+    #[test]
+    fn test_accelerometer() {
+        let mut mbc = build_mbc7();
+        mbc.set_tilt(1.0, -0.5);
+
+        // values are only latched after an erase
+        mbc.write_ram(0xA010, ACCELEROMETER_LATCH_FLAG);
+        assert_eq!((mbc.read_ram(0xA020), mbc.read_ram(0xA030)), (0x00, 0x80));
+
+        mbc.write_ram(0xA000, ACCELEROMETER_ERASE_FLAG);
+        mbc.write_ram(0xA010, ACCELEROMETER_LATCH_FLAG);
+        assert_eq!((mbc.read_ram(0xA020), mbc.read_ram(0xA030)), (0x40, 0x82));
+        assert_eq!((mbc.read_ram(0xA040), mbc.read_ram(0xA050)), (0x98, 0x81));
+        assert_eq!(mbc.read_ram(0xA060), 0x00);
+        assert_eq!(mbc.read_ram(0xA070), 0xFF);
+
+        // registers are hidden until both enable registers are set
+        mbc.write_bank_n(0x4000, 0x00);
+        assert_eq!(mbc.read_ram(0xA020), 0xFF);
+    }
+
+    #[test]
+    fn test_eeprom() {
+        let mut mbc = build_mbc7();
+        assert!(mbc.has_battery());
+
+        // writes are ignored until they are enabled
+        send_command(&mut mbc, EEPROM_WRITE, 0x05);
+        send_bits(&mut mbc, 0x1234, EEPROM_WORD_BITS);
+        assert_eq!(mbc.read_word(0x05), 0xFFFF);
+
+        send_command(&mut mbc, EEPROM_EXTENDED, EEPROM_WRITE_ENABLE << 6);
+        send_command(&mut mbc, EEPROM_WRITE, 0x05);
+        send_bits(&mut mbc, 0x1234, EEPROM_WORD_BITS);
+        assert_eq!(mbc.dump_ram()[0x0A..0x0C], [0x34, 0x12]);
+
+        // the word follows a dummy 0
+        send_command(&mut mbc, EEPROM_READ, 0x05);
+        assert_eq!(mbc.read_ram(0xA080) & EEPROM_DO, 0x00);
+        assert_eq!(send_bits(&mut mbc, 0, EEPROM_WORD_BITS), 0x1234);
+
+        send_command(&mut mbc, EEPROM_ERASE, 0x05);
+        assert_eq!(mbc.read_word(0x05), 0xFFFF);
+
+        send_command(&mut mbc, EEPROM_EXTENDED, EEPROM_WRITE_ALL << 6);
+        send_bits(&mut mbc, 0xABCD, EEPROM_WORD_BITS);
+        assert!(mbc.dump_ram().chunks(2).all(|word| word == [0xCD, 0xAB]));
+
+        send_command(&mut mbc, EEPROM_EXTENDED, EEPROM_WRITE_DISABLE << 6);
+        send_command(&mut mbc, EEPROM_EXTENDED, EEPROM_ERASE_ALL << 6);
+        assert_eq!(mbc.read_word(0x7F), 0xABCD);
+    }
+}
@@ -1,16 +1,37 @@
 mod rom;
 mod mbc1;
+mod mbc2;
 mod mbc3;
+mod mbc5;
+mod mbc7;
+mod huc1;
+mod huc3;
+mod header;
 
 use rom::Rom;
 use mbc1::Mbc1;
+use mbc2::Mbc2;
 use mbc3::Mbc3;
+use mbc5::Mbc5;
+use mbc7::Mbc7;
+use huc1::Huc1;
+use huc3::Huc3;
+pub use header::{CartridgeHeader, CgbSupport, Destination};
+
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::savestate::{SaveState, StateWriter, StateReader, invalid_state};
+use crate::error::EmulatorError;
 
 pub const CARTRIDGE_TYPE_OFFSET: u16 = 0x147;
 pub const CARTRIDGE_ROM_SIZE_OFFSET: u16 = 0x148;
 pub const CARTRIDGE_RAM_SIZE_OFFSET: u16 = 0x149;
+pub const CARTRIDGE_HEADER_CHECKSUM_OFFSET: u16 = 0x14D;
 
 #[allow(non_camel_case_types)]
+#[derive(Copy, Clone, PartialEq, Debug)]
 pub enum MbcType {
     ROM_ONLY,
     MBC_1,
@@ -78,6 +99,25 @@ impl std::fmt::Display for MbcType {
     }
 }
 
+impl MbcType {
+    // only cartridges with a battery keep their ram content when powered off
+    pub fn has_battery(&self) -> bool {
+        matches!(self,
+            MbcType::ROM_RAM_BAT
+            | MbcType::MMM01_RAM_BAT
+            | MbcType::MBC_1_RAM_BAT
+            | MbcType::MBC_2_BAT
+            | MbcType::MBC_3_TIM_BAT
+            | MbcType::MBC_3_TIM_RAM_BAT
+            | MbcType::MBC_3_RAM_BAT
+            | MbcType::MBC_5_RAM_BAT
+            | MbcType::MBC_5_RUMBLE_RAM_BAT
+            | MbcType::MBC_7
+            | MbcType::HUC3
+            | MbcType::HUC1)
+    }
+}
+
 #[allow(non_camel_case_types)]
 #[derive(Copy, Clone)]
 pub enum RomSize {
@@ -132,8 +172,8 @@ impl std::fmt::Display for RamSize {
     }
 }
 
-fn get_mbc_type(raw_data: u8) -> MbcType {
-    match raw_data {
+fn get_mbc_type(raw_data: u8) -> Result<MbcType, EmulatorError> {
+    let mbc_type = match raw_data {
         0x00 => MbcType::ROM_ONLY,
         0x01 => MbcType::MBC_1,
         0x02 => MbcType::MBC_1_RAM,
@@ -162,12 +202,14 @@ fn get_mbc_type(raw_data: u8) -> MbcType {
         0xFD => MbcType::TAMA_5,
         0xFE => MbcType::HUC3,
         0xFF => MbcType::HUC1,
-        _=> panic!("Catridge with mbc type {:x} is unknown", raw_data),
-    }
+        _=> return Err(EmulatorError::UnknownMbcType(raw_data)),
+    };
+
+    Ok(mbc_type)
 }
 
-fn get_rom_size(raw_data: u8) -> RomSize {
-    match raw_data {
+fn get_rom_size(raw_data: u8) -> Result<RomSize, EmulatorError> {
+    let rom_size = match raw_data {
         0x00 => RomSize::SIZE_32_KB,
         0x01 => RomSize::SIZE_64_KB,
         0x02 => RomSize::SIZE_128_KB,
@@ -177,22 +219,33 @@ fn get_rom_size(raw_data: u8) -> RomSize {
         0x06 => RomSize::SIZE_2_MB,
         0x07 => RomSize::SIZE_4_MB,
         0x08 => RomSize::SIZE_8_MB,
-        _=> panic!("Catridge with Rom size code {:x} is unknown", raw_data),
-    }
+        _=> return Err(EmulatorError::UnknownRomSize(raw_data)),
+    };
+
+    Ok(rom_size)
 }
 
-fn get_ram_size(raw_data: u8) -> RamSize {
-    match raw_data {
+fn get_ram_size(raw_data: u8) -> Result<RamSize, EmulatorError> {
+    let ram_size = match raw_data {
         0x00 => RamSize::NO_RAM,
         0x02 => RamSize::SIZE_8_KB,
         0x03 => RamSize::SIZE_32_KB,
         0x04 => RamSize::SIZE_128_KB,
         0x05 => RamSize::SIZE_64_KB,
-        _=> panic!("Catridge with Ram size code {:x} is unknown", raw_data),
-    }
+        _=> return Err(EmulatorError::UnknownRamSize(raw_data)),
+    };
+
+    Ok(ram_size)
 }
 
-pub trait Mbc {
+// host time saved with the cartridge clocks, in seconds
+fn get_unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs())
+}
+
+pub trait Mbc: SaveState + Send {
     fn read_bank_0 (&self, address: usize) -> u8;
 
     fn read_bank_n (&self, address: usize) -> u8;
@@ -203,33 +256,122 @@ pub trait Mbc {
 
     fn write_bank_n (&mut self, address: usize, data: u8);
 
-    fn write_ram (&mut self, address: usize, data: u8);
+    // returns false when the write has been ignored, for instance because the ram is disabled
+    fn write_ram (&mut self, address: usize, data: u8) -> bool;
 
     fn run(&mut self, cycles: u8);
+
+    fn has_battery(&self) -> bool;
+
+    fn dump_ram(&self) -> Vec<u8>;
+
+    fn load_ram(&mut self, data: &[u8]);
+
+    // new state of the rumble motor when it has changed, only rumble cartridges have one
+    fn take_rumble(&mut self) -> Option<bool> {
+        None
+    }
+
+    // move the real time clock forward, or back when negative, only cartridges with a clock have one
+    fn advance_rtc(&mut self, _: i64) {}
+
+    // tilt of the gameboy in g, only cartridges with an accelerometer use it
+    fn set_tilt(&mut self, _: f32, _: f32) {}
 }
 
 pub struct Cartridge {
-    mbc: Box<dyn Mbc>,    
+    mbc: Box<dyn Mbc>,
+    header: CartridgeHeader,
+    // used to check that a save state belongs to this rom
+    rom_checksum: u32,
+    // battery backed ram
+    save_path: Option<PathBuf>,
+    ram_dirty: bool,
 }
 
 impl Cartridge {
-    pub fn new(rom: &[u8]) -> Cartridge {
-        // find the mbctype in the rom data
-        let mbc_type = get_mbc_type(rom[CARTRIDGE_TYPE_OFFSET as usize]);
-        let rom_size = get_rom_size(rom[CARTRIDGE_ROM_SIZE_OFFSET as usize]);
-        let ram_size = get_ram_size(rom[CARTRIDGE_RAM_SIZE_OFFSET as usize]);
+    pub fn new(rom: &[u8]) -> Result<Cartridge, EmulatorError> {
+        let header = CartridgeHeader::parse(rom)?;
 
-        println!("Catridge with mbc type {}, rom size: {}, ram_size: {}", mbc_type, rom_size, ram_size);
+        // find the mbctype in the rom data
+        let mbc_type = header.get_mbc_type()?;
+        let rom_size = header.get_rom_size()?;
+        let ram_size = header.get_ram_size()?;
 
         // find the correct mbc structure for the cartridge interface
-        Cartridge {
-            mbc: match mbc_type {
-                MbcType::ROM_ONLY => Box::new(Rom::new(rom)),
-                MbcType::MBC_1 => Box::new(Mbc1::new(mbc_type, rom_size, ram_size, rom)),
-                MbcType::MBC_3_RAM_BAT => Box::new(Mbc3::new(mbc_type, rom_size, ram_size, rom)),
-                _ => panic!("Catridge with mbc type {} is not supported", mbc_type),
-            },
+        let mbc: Box<dyn Mbc> = match mbc_type {
+            MbcType::ROM_ONLY => Box::new(Rom::new(rom)),
+            MbcType::MBC_1
+            | MbcType::MBC_1_RAM
+            | MbcType::MBC_1_RAM_BAT => Box::new(Mbc1::new(mbc_type, rom_size, ram_size, rom)),
+            MbcType::MBC_2
+            | MbcType::MBC_2_BAT => Box::new(Mbc2::new(mbc_type, rom_size, rom)),
+            MbcType::MBC_3_TIM_BAT
+            | MbcType::MBC_3_TIM_RAM_BAT
+            | MbcType::MBC_3
+            | MbcType::MBC_3_RAM
+            | MbcType::MBC_3_RAM_BAT => Box::new(Mbc3::new(mbc_type, rom_size, ram_size, rom)),
+            MbcType::MBC_5
+            | MbcType::MBC_5_RAM
+            | MbcType::MBC_5_RAM_BAT
+            | MbcType::MBC_5_RUMBLE
+            | MbcType::MBC_5_RUMBLE_RAM
+            | MbcType::MBC_5_RUMBLE_RAM_BAT => Box::new(Mbc5::new(mbc_type, rom_size, ram_size, rom)),
+            MbcType::MBC_7 => Box::new(Mbc7::new(mbc_type, rom_size, rom)),
+            MbcType::HUC1 => Box::new(Huc1::new(mbc_type, rom_size, ram_size, rom)),
+            MbcType::HUC3 => Box::new(Huc3::new(mbc_type, rom_size, ram_size, rom)),
+            _ => return Err(EmulatorError::UnsupportedMbc(mbc_type)),
+        };
+
+        Ok(Cartridge {
+            mbc,
+            header,
+            rom_checksum: rom.iter().fold(0, |checksum: u32, &data| checksum.rotate_left(1) ^ data as u32),
+            save_path: None,
+            ram_dirty: false,
+        })
+    }
+
+    pub fn get_header(&self) -> &CartridgeHeader {
+        &self.header
+    }
+
+    // the save file is a raw dump of the cartridge ram, as done by most emulators
+    pub fn get_save_path(rom_path: &str) -> PathBuf {
+        Path::new(rom_path).with_extension("sav")
+    }
+
+    // returns true when the ram has been restored from an existing save file
+    pub fn attach_save_file(&mut self, save_path: PathBuf) -> std::io::Result<bool> {
+        if !self.mbc.has_battery() {
+            // nothing to persist for this cartridge
+            return Ok(false);
         }
+
+        // restore the ram content if a save file already exists
+        let loaded = save_path.exists();
+        if loaded {
+            let mut save_data = Vec::new();
+            File::open(&save_path)?.read_to_end(&mut save_data)?;
+            self.mbc.load_ram(&save_data);
+        }
+
+        self.save_path = Some(save_path);
+        self.ram_dirty = false;
+
+        Ok(loaded)
+    }
+
+    pub fn flush_save_file(&mut self) -> std::io::Result<()> {
+        if let Some(save_path) = &self.save_path {
+            // write the file only if the ram content has changed
+            if self.ram_dirty {
+                File::create(save_path)?.write_all(&self.mbc.dump_ram())?;
+                self.ram_dirty = false;
+            }
+        }
+
+        Ok(())
     }
 
     pub fn read_bank_0(&self, address: usize) -> u8 {
@@ -253,10 +395,147 @@ impl Cartridge {
     }
 
     pub fn write_ram(&mut self, address: usize, data: u8) {
-        self.mbc.write_ram(address, data);
+        // unchanged ram doesn't need to be written again
+        if self.mbc.write_ram(address, data) {
+            self.ram_dirty = true;
+        }
     }
 
     pub fn run(&mut self, cycles: u8) {
         self.mbc.run(cycles);
     }
+
+    pub fn take_rumble(&mut self) -> Option<bool> {
+        self.mbc.take_rumble()
+    }
+
+    pub fn advance_rtc(&mut self, seconds: i64) {
+        self.mbc.advance_rtc(seconds);
+    }
+
+    pub fn set_tilt(&mut self, x: f32, y: f32) {
+        self.mbc.set_tilt(x, y);
+    }
+}
+
+impl SaveState for Cartridge {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u32(self.rom_checksum);
+        self.mbc.save_state(writer);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> std::io::Result<()> {
+        if reader.read_u32()? != self.rom_checksum {
+            return Err(invalid_state("save state was made with another rom"));
+        }
+
+        self.mbc.load_state(reader)?;
+        // the restored ram shall be written in the save file
+        self.ram_dirty = true;
+
+        Ok(())
+    }
+}
+
+// test rom where each bank starts with its 16 bits bank number
+#[cfg(test)]
+fn build_bank_numbered_rom(rom_size: RomSize) -> Vec<u8> {
+    let mut rom = vec![0xFF; rom_size as usize];
+    for (bank, data) in rom.chunks_mut(0x4000).enumerate() {
+        data[0] = bank as u8;
+        data[1] = (bank >> 8) as u8;
+    }
+    rom
+}
+
+#[cfg(test)]
+mod cartridge_tests {
+    use super::*;
+
+    #[test]
+    fn test_battery_save_file() {
+        let mut rom = [0xFF; 0x8000];
+        rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x03;
+        rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
+        rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x02;
+        let save_path = std::env::temp_dir().join("qoboy_test_battery_save_file.sav");
+        let _ = std::fs::remove_file(&save_path);
+
+        // write some data in the cartridge ram then flush it
+        let mut cartridge = Cartridge::new(&rom).unwrap();
+        cartridge.attach_save_file(save_path.clone()).unwrap();
+        cartridge.write_bank_0(0x0000, 0x0A);
+        cartridge.write_ram(0xA000, 0x12);
+        cartridge.write_ram(0xBFFF, 0x34);
+        cartridge.flush_save_file().unwrap();
+        assert_eq!(std::fs::metadata(&save_path).unwrap().len(), 0x2000);
+
+        // data shall be restored in a new cartridge
+        let mut cartridge = Cartridge::new(&rom).unwrap();
+        assert!(cartridge.attach_save_file(save_path.clone()).unwrap());
+        cartridge.write_bank_0(0x0000, 0x0A);
+        assert_eq!(cartridge.read_ram(0xA000), 0x12);
+        assert_eq!(cartridge.read_ram(0xBFFF), 0x34);
+
+        std::fs::remove_file(&save_path).unwrap();
+    }
+
+    #[test]
+    fn test_no_battery_no_save_file() {
+        let mut rom = [0xFF; 0x8000];
+        rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x02;
+        rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
+        rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x02;
+        let save_path = std::env::temp_dir().join("qoboy_test_no_battery_no_save_file.sav");
+        let _ = std::fs::remove_file(&save_path);
+
+        let mut cartridge = Cartridge::new(&rom).unwrap();
+        cartridge.attach_save_file(save_path.clone()).unwrap();
+        cartridge.write_bank_0(0x0000, 0x0A);
+        cartridge.write_ram(0xA000, 0x12);
+        cartridge.flush_save_file().unwrap();
+        assert!(!save_path.exists());
+    }
+
+    #[test]
+    fn test_ignored_write_no_save_file() {
+        let mut rom = [0xFF; 0x8000];
+        rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x03;
+        rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
+        rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x02;
+        let save_path = std::env::temp_dir().join("qoboy_test_ignored_write_no_save_file.sav");
+        let _ = std::fs::remove_file(&save_path);
+
+        // the write is ignored while the ram is disabled, there is nothing to save
+        let mut cartridge = Cartridge::new(&rom).unwrap();
+        cartridge.attach_save_file(save_path.clone()).unwrap();
+        cartridge.write_ram(0xA000, 0x12);
+        cartridge.flush_save_file().unwrap();
+        assert!(!save_path.exists());
+    }
+
+    #[test]
+    fn test_invalid_header() {
+        let mut rom = [0xFF; 0x8000];
+        rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
+        rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
+        rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
+        assert!(Cartridge::new(&rom).is_ok());
+
+        assert_eq!(Cartridge::new(&rom[..0x100]).err(), Some(EmulatorError::RomTooSmall(0x100)));
+
+        rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x42;
+        assert_eq!(Cartridge::new(&rom).err(), Some(EmulatorError::UnknownRamSize(0x42)));
+
+        rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x42;
+        assert_eq!(Cartridge::new(&rom).err(), Some(EmulatorError::UnknownRomSize(0x42)));
+
+        rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x42;
+        assert_eq!(Cartridge::new(&rom).err(), Some(EmulatorError::UnknownMbcType(0x42)));
+
+        rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x20;
+        rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
+        rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
+        assert_eq!(Cartridge::new(&rom).err(), Some(EmulatorError::UnsupportedMbc(MbcType::MBC_6)));
+    }
 }
\ No newline at end of file
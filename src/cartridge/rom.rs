@@ -1,4 +1,5 @@
 use crate::cartridge::Mbc;
+use crate::savestate::{SaveState, StateWriter, StateReader};
 use crate::soc::peripheral::{ROM_BANK_0_SIZE, ROM_BANK_N_SIZE};
 
 pub struct Rom {
@@ -40,7 +41,29 @@ impl Mbc for Rom {
     }
 
     // not used for this mbc, doesn't do anything
-    fn write_ram (&mut self, _: usize, _: u8) {}
+    fn write_ram (&mut self, _: usize, _: u8) -> bool {
+        false
+    }
 
     fn run (&mut self, _: u8) {}
+
+    // no battery on this cartridge, nothing to save
+    fn has_battery (&self) -> bool {
+        false
+    }
+
+    fn dump_ram (&self) -> Vec<u8> {
+        Vec::new()
+    }
+
+    fn load_ram (&mut self, _: &[u8]) {}
+}
+
+// this mbc has no internal registers
+impl SaveState for Rom {
+    fn save_state(&self, _: &mut StateWriter) {}
+
+    fn load_state(&mut self, _: &mut StateReader) -> std::io::Result<()> {
+        Ok(())
+    }
 }
\ No newline at end of file
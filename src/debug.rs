@@ -1,7 +1,5 @@
-use crate::emulator::{Emulator, EmulatorState, ONE_FRAME_IN_NS, ONE_FRAME_IN_CYCLES};
-use crate::soc::peripheral::IoAccess;
-use std::time::Instant;
-
+use qoboy::{Emulator, RunResult, PixelColor};
+use qoboy::emulator::ONE_FRAME_IN_CYCLES;
 use std::io::{stdin, stdout, Write};
 use std::thread;
 use std::sync::{Arc, Mutex};
@@ -14,6 +12,10 @@ const SCALE_FACTOR: usize = 3;
 const TILE_SIZE: usize = 8;
 const WINDOW_DIMENSIONS: [usize; 2] = [(NB_TILE_X * TILE_SIZE * SCALE_FACTOR), (NB_TILE_Y * TILE_SIZE * SCALE_FACTOR)];
 
+// the tiles are stored at the beginning of VRAM, they are drawn with the default shades
+const VRAM_BEGIN: u16 = 0x8000;
+const TILE_COLORS: [PixelColor; 4] = [PixelColor::WHITE, PixelColor::LIGHT_GRAY, PixelColor::DARK_GRAY, PixelColor::BLACK];
+
 #[derive(Clone, Copy)]
 pub enum DebuggerCommand {
     HALT,
@@ -49,110 +51,101 @@ impl DebugCtx {
     }
 }
 
-pub fn run_debug_mode(emulator: &mut Emulator, dbg_ctx: &mut DebugCtx) {
-    match emulator.state {
-        EmulatorState::GetTime => {
-            emulator.frame_tick = Instant::now();
+// run the emulator until the end of the frame, a halted emulator only processes the debugger commands
+pub fn run_debug_frame(emulator: &mut Emulator, dbg_ctx: &mut DebugCtx) -> RunResult {
+    let mut frame = RunResult::default();
+
+    while !frame.vblank && frame.cycles < ONE_FRAME_IN_CYCLES {
+        match dbg_ctx.debugger_state {
+            DebuggerState::HALT => {
+                // display cpu internal registers
+                if dbg_ctx.display_cpu_reg {
+                    dbg_ctx.display_cpu_reg = false;
+                    let registers = emulator.get_cpu_registers();
+                    println!("instruction byte : {:#04x} / pc : {:#06x} / sp : {:#04x}", emulator.read_memory(registers.pc), registers.pc, registers.sp);
+                    println!("BC : {:#06x} / AF : {:#06x} / DE : {:#06x} / HL : {:#06x}", registers.bc, registers.af, registers.de, registers.hl);
+                }
 
-            emulator.state = EmulatorState::RunMachine;
-        }
-        EmulatorState::RunMachine => {
-            match dbg_ctx.debugger_state {
-                DebuggerState::HALT => {
-                    // display cpu internal registers
-                    if dbg_ctx.display_cpu_reg {
-                        dbg_ctx.display_cpu_reg = false;
-                        println!("instruction byte : {:#04x} / pc : {:#06x} / sp : {:#04x}", emulator.soc.peripheral.read(emulator.soc.cpu.pc), emulator.soc.cpu.pc, emulator.soc.cpu.sp);
-                        println!("BC : {:#06x} / AF : {:#06x} / DE : {:#06x} / HL : {:#06x}", emulator.soc.cpu.registers.read_bc(), emulator.soc.cpu.registers.read_af(), emulator.soc.cpu.registers.read_de(), emulator.soc.cpu.registers.read_hl());
-                    }
-
-                    // wait until a new debug command is entered
-                    let cmd = dbg_ctx.cmd.pop();
-                    if let Some(DebuggerCommand::RUN) = cmd {
-                        dbg_ctx.display_cpu_reg = true;
-                        dbg_ctx.debugger_state = DebuggerState::RUN;
-                    }
-
-                    if let Some(DebuggerCommand::STEP) = cmd {
-                        dbg_ctx.display_cpu_reg = true;
-                        dbg_ctx.debugger_state = DebuggerState::STEP;
-                    }
+                // wait until a new debug command is entered
+                let cmd = dbg_ctx.cmd.pop();
+                if let Some(DebuggerCommand::RUN) = cmd {
+                    dbg_ctx.display_cpu_reg = true;
+                    dbg_ctx.debugger_state = DebuggerState::RUN;
+                    // resume the execution after a fault
+                    emulator.clear_fault();
                 }
-                DebuggerState::RUN => {
-                    // run the emulator as in normal mode
-                    emulator.cycles_elapsed_in_frame += emulator.soc.run() as usize;
-
-                    if emulator.cycles_elapsed_in_frame >= ONE_FRAME_IN_CYCLES {
-                        emulator.cycles_elapsed_in_frame = 0;
-                        emulator.state = EmulatorState::WaitNextFrame;
-                    }
-
-                    // check if we have to break
-                    if dbg_ctx.break_enabled && (dbg_ctx.breakpoint == emulator.soc.cpu.pc) {
-                        // check pc
-                        dbg_ctx.display_cpu_reg = true;
-                        dbg_ctx.debugger_state = DebuggerState::HALT;
-                    }
-
-                    // wait until a new debug command is entered
-                    if let Some(DebuggerCommand::HALT) = dbg_ctx.cmd.pop() {
-                        dbg_ctx.display_cpu_reg = true;
-                        dbg_ctx.debugger_state = DebuggerState::HALT;
-                    }
+
+                if let Some(DebuggerCommand::STEP) = cmd {
+                    dbg_ctx.display_cpu_reg = true;
+                    dbg_ctx.debugger_state = DebuggerState::STEP;
+                    emulator.clear_fault();
                 }
-                DebuggerState::STEP => {
-                    // run the emulator once then go to halt state
-                    emulator.cycles_elapsed_in_frame += emulator.soc.run() as usize;
 
-                    if emulator.cycles_elapsed_in_frame >= ONE_FRAME_IN_CYCLES {
-                        emulator.cycles_elapsed_in_frame = 0;
-                        emulator.state = EmulatorState::WaitNextFrame;
-                    }
+                // the frame doesn't progress while the cpu is halted
+                if let DebuggerState::HALT = dbg_ctx.debugger_state {
+                    return frame;
+                }
+            }
+            DebuggerState::RUN => {
+                // run the emulator as in normal mode
+                frame.add(emulator.step_instruction());
+
+                // check if we have to break
+                if (dbg_ctx.break_enabled && (dbg_ctx.breakpoint == emulator.get_cpu_registers().pc)) || emulator.get_fault().is_some() {
+                    // check pc
+                    dbg_ctx.display_cpu_reg = true;
+                    dbg_ctx.debugger_state = DebuggerState::HALT;
+                }
 
+                // wait until a new debug command is entered
+                if let Some(DebuggerCommand::HALT) = dbg_ctx.cmd.pop() {
+                    dbg_ctx.display_cpu_reg = true;
                     dbg_ctx.debugger_state = DebuggerState::HALT;
                 }
             }
-        }
-        EmulatorState::WaitNextFrame => {
-            // check if 16,742706 ms have passed during this frame
-            if emulator.frame_tick.elapsed().as_nanos() >= ONE_FRAME_IN_NS as u128{
-                emulator.state = EmulatorState::DisplayFrame;
+            DebuggerState::STEP => {
+                // run the emulator once then go to halt state
+                frame.add(emulator.step_instruction());
+
+                dbg_ctx.debugger_state = DebuggerState::HALT;
             }
         }
-        EmulatorState::DisplayFrame => {
-            emulator.state = EmulatorState::GetTime;
+    }
 
-            // update vram debug buffer
-            for pixel_index in 0..NB_TILE_X * TILE_SIZE * NB_TILE_Y * TILE_SIZE {
-                // compute pixel_x and pixel_y indexes
-                let pixel_y_index = pixel_index / (NB_TILE_X * 8);
-                let pixel_x_index = pixel_index % (NB_TILE_X * 8);
+    emulator.flush_audio_samples();
 
-                // compute the tile index 
-                let tile_y_index = pixel_y_index / 8;
-                let tile_x_index = pixel_x_index / 8;
-                let tile_index = tile_y_index * NB_TILE_X + tile_x_index;
+    // update vram debug buffer
+    for pixel_index in 0..NB_TILE_X * TILE_SIZE * NB_TILE_Y * TILE_SIZE {
+        // compute pixel_x and pixel_y indexes
+        let pixel_y_index = pixel_index / (NB_TILE_X * 8);
+        let pixel_x_index = pixel_index % (NB_TILE_X * 8);
 
-                // compute VRAM address from pixel_index
-                let tile_row_offset = pixel_y_index % 8 * 2;
+        // compute the tile index 
+        let tile_y_index = pixel_y_index / 8;
+        let tile_x_index = pixel_x_index / 8;
+        let tile_index = tile_y_index * NB_TILE_X + tile_x_index;
 
-                // get row for the needed pixel
-                let data_0 = emulator.soc.peripheral.gpu.vram[tile_index * 16 + tile_row_offset];
-                let data_1 = emulator.soc.peripheral.gpu.vram[tile_index * 16 + tile_row_offset + 1];
+        // compute VRAM address from pixel_index
+        let tile_row_offset = pixel_y_index % 8 * 2;
 
-                // get pixel bits
-                let bit_0 = data_0 >> (7 - (pixel_index % 8)) & 0x01;
-                let bit_1 = data_1 >> (7 - (pixel_index % 8)) & 0x01;
+        // get row for the needed pixel
+        let tile_row_address = VRAM_BEGIN + (tile_index * 16 + tile_row_offset) as u16;
+        let data_0 = emulator.read_memory(tile_row_address);
+        let data_1 = emulator.read_memory(tile_row_address + 1);
 
-                let pixel_color = emulator.soc.peripheral.gpu.get_bg_pixel_color_from_palette((bit_1 << 1) | bit_0);
+        // get pixel bits
+        let bit_0 = data_0 >> (7 - (pixel_index % 8)) & 0x01;
+        let bit_1 = data_1 >> (7 - (pixel_index % 8)) & 0x01;
 
-                dbg_ctx.vram_viewer_buffer[pixel_index] =  0xFF << 24
-                            | (pixel_color as u32) << 16
-                            | (pixel_color as u32) << 8
-                            | (pixel_color as u32) << 0;
-            }
-        }
+        let pixel_color = TILE_COLORS[((bit_1 << 1) | bit_0) as usize];
+
+        dbg_ctx.vram_viewer_buffer[pixel_index] =  0xFF << 24
+                    | (pixel_color as u32) << 16
+                    | (pixel_color as u32) << 8
+                    | (pixel_color as u32) << 0;
     }
+
+    frame
 }
 
 pub fn debug_cli(debug_ctx: &Arc<Mutex<DebugCtx>>) {
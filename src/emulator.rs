@@ -1,8 +1,13 @@
 use crate::soc::Soc;
-pub use crate::soc::GameBoyKey;
-use crate::cartridge::Cartridge;
-use std::time::Instant;
-use crate::debug::{DebugCtx, run_debug_mode};
+pub use crate::soc::{GameBoyKey, GameBoyModel};
+pub use crate::soc::peripheral::gpu::PixelColor;
+pub use crate::soc::peripheral::BUILTIN_BOOT_ROM;
+pub use crate::soc::peripheral::serial::{SerialDevice, Disconnected, Loopback, SerialCapture, SERIAL_DISCONNECTED_BYTE};
+use crate::cartridge::{Cartridge, CartridgeHeader};
+use crate::savestate::{SaveState, StateWriter, StateReader, SAVE_STATE_MAGIC, SAVE_STATE_VERSION, invalid_state};
+use std::path::{Path, PathBuf};
+use crate::audio::AudioSink;
+use crate::error::{EmulatorError, FaultPolicy};
 
 pub const SCREEN_HEIGHT: usize = 144;
 pub const SCREEN_WIDTH: usize = 160;
@@ -13,93 +18,675 @@ pub const ONE_SECOND_IN_CYCLES: usize = 4194304; // Main sys clock 4.194304 MHz
 pub const ONE_FRAME_IN_CYCLES: usize = 70224;
 pub const ONE_FRAME_IN_NS: usize = ONE_FRAME_IN_CYCLES * ONE_SECOND_IN_MICROS / ONE_SECOND_IN_CYCLES;
 
-#[derive(PartialEq)]
-pub enum EmulatorState {
-    GetTime,
-    RunMachine,
-    WaitNextFrame,
-    DisplayFrame,
+// cycles reported for each step while the emulated system is halted
+const HALTED_CYCLES: usize = 4;
+
+/// What happened while the emulator was running.
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct RunResult {
+    /// Number of cycles executed, at the 4.194304 MHz system clock.
+    pub cycles: usize,
+    /// The gpu has entered the vertical blanking period, a new frame is complete.
+    pub vblank: bool,
+    /// The rumble motor of the cartridge has been turned on (`Some(true)`) or off
+    /// (`Some(false)`), only rumble cartridges drive one.
+    pub rumble: Option<bool>,
+    /// Faults raised by the emulated program, in order. The emulator doesn't print them,
+    /// logging them is up to the caller.
+    pub faults: Vec<EmulatorError>,
+}
+
+impl RunResult {
+    /// Merge the result of the following run.
+    pub fn add(&mut self, other: RunResult) {
+        self.cycles += other.cycles;
+        self.vblank |= other.vblank;
+        // the last change of the motor wins
+        self.rumble = other.rumble.or(self.rumble);
+        self.faults.extend(other.faults);
+    }
+}
+
+/// Registers of the cpu, read by debuggers.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct CpuRegisters {
+    pub pc: u16,
+    pub sp: u16,
+    pub af: u16,
+    pub bc: u16,
+    pub de: u16,
+    pub hl: u16,
 }
 
+/// A complete gameboy: cpu, peripherals and cartridge.
+///
+/// The emulator is `Send`, it can be moved to another thread together with its audio sink
+/// and serial device.
 pub struct Emulator {
     // gameboy emulated hardware
-    pub soc: Soc,
-    // emulator internal parameters
-    pub state: EmulatorState,
-    pub cycles_elapsed_in_frame: usize,
-    pub frame_tick: Instant,
-    run_routine: fn(&mut Emulator, &mut DebugCtx),
+    pub(crate) soc: Soc,
+    audio_sink: Option<Box<dyn AudioSink>>,
+    audio_error: Option<std::io::Error>,
+    // runtime faults handling
+    fault_policy: FaultPolicy,
+    fault: Option<EmulatorError>,
 }
 
 impl Emulator {
-    pub fn new(boot_rom: &[u8], rom: &[u8], debug_on: bool) -> Emulator {
-        let cartridge = Cartridge::new(rom);
+    /// Create a gameboy running `boot_rom` (256 bytes) then the game `rom`, the cartridge
+    /// type is read from the rom header. [`BUILTIN_BOOT_ROM`] can be used when no boot rom
+    /// dump is available.
+    pub fn new(boot_rom: &[u8], rom: &[u8]) -> Result<Emulator, EmulatorError> {
+        let cartridge = Cartridge::new(rom)?;
 
-        let soc = Soc::new(boot_rom, cartridge);
+        let soc = Soc::new(boot_rom, cartridge)?;
 
-        let run_routine = if debug_on {
-            run_debug_mode
-        } else {
-            run_normal_mode
-        };
+        Ok(Emulator::from_soc(soc))
+    }
+
+    /// Create a gameboy which starts the game `rom` right away, with the cpu and io
+    /// registers left by the boot rom of `model`.
+    pub fn new_without_boot_rom(rom: &[u8], model: GameBoyModel) -> Result<Emulator, EmulatorError> {
+        let cartridge = Cartridge::new(rom)?;
+
+        Ok(Emulator::from_soc(Soc::new_without_boot_rom(cartridge, model)))
+    }
 
+    fn from_soc(soc: Soc) -> Emulator {
         Emulator {
             // gameboy emulated hardware
-            soc: soc,
-            // emulator internal parameters
-            state: EmulatorState::GetTime,
-            cycles_elapsed_in_frame: 0 as usize,
-            frame_tick: Instant::now(),
-            // debugger parameters
-            run_routine: run_routine,
+            soc,
+            audio_sink: None,
+            audio_error: None,
+            fault_policy: FaultPolicy::LogAndContinue,
+            fault: None,
+        }
+    }
+
+    /// Run until the gpu enters the vertical blanking period, or for the duration of
+    /// a frame while the lcd is off. The host is in charge of the frame pacing.
+    pub fn run_frame(&mut self) -> RunResult {
+        let mut result = RunResult::default();
+
+        while !result.vblank && result.cycles < ONE_FRAME_IN_CYCLES {
+            result.add(self.step_instruction());
+        }
+
+        // hand the samples of the frame over to the audio sink
+        self.flush_audio_samples();
+
+        result
+    }
+
+    /// Run whole instructions until at least `cycles` cycles have been executed.
+    pub fn run_cycles(&mut self, cycles: usize) -> RunResult {
+        let mut result = RunResult::default();
+
+        while result.cycles < cycles {
+            result.add(self.step_instruction());
+        }
+
+        result
+    }
+
+    /// Run a single cpu instruction.
+    pub fn step_instruction(&mut self) -> RunResult {
+        // a halted system is frozen but the time goes on for the host
+        if self.fault.is_some() {
+            return RunResult { cycles: HALTED_CYCLES, ..RunResult::default() };
+        }
+
+        let cycles = self.soc.run() as usize;
+
+        let mut faults = Vec::new();
+        if let Some(error) = self.soc.take_fault() {
+            self.handle_fault(&error);
+            faults.push(error);
+        }
+
+        RunResult { cycles, vblank: self.soc.take_vblank(), rumble: self.soc.take_rumble(), faults }
+    }
+
+    /// Select what happens when the emulated program does something the emulator can't handle.
+    pub fn set_fault_policy(&mut self, fault_policy: FaultPolicy) {
+        self.fault_policy = fault_policy;
+    }
+
+    /// Fault which has halted the emulated system, if any.
+    pub fn get_fault(&self) -> Option<&EmulatorError> {
+        self.fault.as_ref()
+    }
+
+    /// Resume the execution after a fault has halted the emulated system.
+    pub fn clear_fault(&mut self) {
+        self.fault = None;
+    }
+
+    // the fault is reported in the run result, the policy only decides if the system is frozen
+    fn handle_fault(&mut self, error: &EmulatorError) {
+        match self.fault_policy {
+            FaultPolicy::Halt | FaultPolicy::Abort => self.fault = Some(error.clone()),
+            FaultPolicy::LogAndContinue => {}
         }
     }
 
-    pub fn run(&mut self, dbg_cmd: &mut DebugCtx) {
-        (self.run_routine)(self, dbg_cmd);
+    /// True when the cpu can't run anymore: it waits in HALT mode with every interrupt disabled.
+    pub fn is_locked_up(&self) -> bool {
+        self.soc.is_locked_up()
+    }
+
+    /// Read a byte of the gameboy address space as the cpu would see it.
+    pub fn read_memory(&self, address: u16) -> u8 {
+        self.soc.read_memory(address)
     }
 
-    pub fn frame_ready(&self) -> bool {
-        if self.state == EmulatorState::DisplayFrame {
-            true
-        } else {
-            false
+    /// Current registers of the cpu.
+    pub fn get_cpu_registers(&self) -> CpuRegisters {
+        let cpu = &self.soc.cpu;
+        CpuRegisters {
+            pc: cpu.pc,
+            sp: cpu.sp,
+            af: cpu.registers.read_af(),
+            bc: cpu.registers.read_bc(),
+            de: cpu.registers.read_de(),
+            hl: cpu.registers.read_hl(),
         }
     }
 
+    /// Gray shade of a pixel of the screen, from 255 (white) to 0 (black).
     pub fn get_frame_buffer(&self, pixel_index: usize) -> u8 {
         self.soc.get_frame_buffer(pixel_index)
     }
 
+    /// Gray shades of the whole screen, row by row.
+    pub fn get_frame(&self) -> &[u8] {
+        self.soc.get_frame()
+    }
+
+    /// Press (`true`) or release (`false`) a button of the gameboy.
     pub fn set_key(&mut self, key: GameBoyKey, value: bool) {
         self.soc.set_key(key, value);
     }
-}
 
-fn run_normal_mode(emulator: &mut Emulator, _dbg_ctx: &mut DebugCtx) {
-    match emulator.state {
-        EmulatorState::GetTime => {
-            emulator.frame_tick = Instant::now();
+    /// Tilt the gameboy, for the cartridges with an accelerometer. `x` and `y` are the
+    /// accelerations in g along the horizontal and vertical axes of the screen, 0.0 when
+    /// the gameboy lies flat.
+    pub fn set_tilt(&mut self, x: f32, y: f32) {
+        self.soc.set_tilt(x, y);
+    }
 
-            emulator.state = EmulatorState::RunMachine;
-        }
-        EmulatorState::RunMachine => {
-            emulator.cycles_elapsed_in_frame += emulator.soc.run() as usize;
+    /// Audio is produced as interleaved stereo samples at the selected rate.
+    pub fn set_audio_sample_rate(&mut self, sample_rate: u32) {
+        self.soc.set_audio_sample_rate(sample_rate);
+    }
+
+    pub fn get_audio_sample_rate(&self) -> u32 {
+        self.soc.get_audio_sample_rate()
+    }
+
+    /// Samples produced since the last call, when no audio sink is set.
+    pub fn take_audio_samples(&mut self) -> Vec<f32> {
+        self.soc.take_audio_samples()
+    }
 
-            if emulator.cycles_elapsed_in_frame >= ONE_FRAME_IN_CYCLES {
-                emulator.cycles_elapsed_in_frame = 0;
-                emulator.state = EmulatorState::WaitNextFrame;
+    /// The sink receives the samples at the end of each frame, at the selected sample rate.
+    pub fn set_audio_sink(&mut self, sink: Box<dyn AudioSink>) {
+        self.audio_sink = Some(sink);
+    }
+
+    pub fn remove_audio_sink(&mut self) -> Option<Box<dyn AudioSink>> {
+        self.audio_sink.take()
+    }
+
+    /// Hand the samples produced so far over to the audio sink.
+    pub fn flush_audio_samples(&mut self) {
+        if let Some(sink) = self.audio_sink.as_mut() {
+            let samples = self.soc.take_audio_samples();
+            if let Err(error) = sink.push_samples(&samples) {
+                // stop the audio output instead of failing on every frame
+                self.audio_error = Some(error);
+                self.audio_sink = None;
             }
         }
-        EmulatorState::WaitNextFrame => {
-            // check if 16,742706 ms have passed during this frame
-            if emulator.frame_tick.elapsed().as_nanos() >= ONE_FRAME_IN_NS as u128{
-                emulator.state = EmulatorState::DisplayFrame;
-            }
+    }
+
+    /// Error which has made the emulator remove the audio sink, reported once.
+    pub fn take_audio_error(&mut self) -> Option<std::io::Error> {
+        self.audio_error.take()
+    }
+
+    /// Plug a device on the other end of the link cable.
+    pub fn set_serial_device(&mut self, device: Box<dyn SerialDevice>) {
+        self.soc.set_serial_device(device);
+    }
+
+    /// Header of the cartridge inserted in the gameboy.
+    pub fn get_cartridge_header(&self) -> &CartridgeHeader {
+        self.soc.get_cartridge_header()
+    }
+
+    /// Load the battery backed ram from the .sav file next to the rom, if any. The clock
+    /// of the cartridge is moved forward by the time elapsed since the file was written.
+    /// Returns the path of the save file when the ram has been restored from it.
+    pub fn load_save_file(&mut self, rom_path: &str) -> std::io::Result<Option<PathBuf>> {
+        let save_path = Cartridge::get_save_path(rom_path);
+        let loaded = self.soc.attach_save_file(save_path.clone())?;
+        Ok(loaded.then_some(save_path))
+    }
+
+    /// Move the real time clock of the cartridge forward by `seconds`, or back when negative,
+    /// to reach time based events without waiting for them. Cartridges without a clock are
+    /// not affected.
+    pub fn advance_rtc(&mut self, seconds: i64) {
+        self.soc.advance_rtc(seconds);
+    }
+
+    /// Write the battery backed ram to the .sav file if it has changed.
+    pub fn flush_save_file(&mut self) -> std::io::Result<()> {
+        self.soc.flush_save_file()
+    }
+
+    /// Snapshot the whole emulated system.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut writer = StateWriter::new();
+        writer.write_u8(SAVE_STATE_MAGIC[0]);
+        writer.write_u8(SAVE_STATE_MAGIC[1]);
+        writer.write_u8(SAVE_STATE_MAGIC[2]);
+        writer.write_u8(SAVE_STATE_MAGIC[3]);
+        writer.write_u16(SAVE_STATE_VERSION);
+        self.soc.save_state(&mut writer);
+        writer.into_bytes()
+    }
+
+    /// Restore a snapshot made by `save_state`, the emulated system shall run the same rom.
+    /// The emulated system is left untouched when the snapshot can't be restored.
+    pub fn load_state(&mut self, data: &[u8]) -> std::io::Result<()> {
+        // the components are restored one after the other, keep the current state to roll
+        // back the ones already overwritten when a later one fails to decode
+        let current_state = self.save_state();
+        let result = self.restore_state(data);
+        if result.is_err() {
+            self.restore_state(&current_state)
+                .expect("a state made by save_state shall always be restored");
         }
-        EmulatorState::DisplayFrame => {
-            emulator.state = EmulatorState::GetTime;
+
+        result
+    }
+
+    fn restore_state(&mut self, data: &[u8]) -> std::io::Result<()> {
+        let mut reader = StateReader::new(data);
+
+        let magic = [reader.read_u8()?, reader.read_u8()?, reader.read_u8()?, reader.read_u8()?];
+        if magic != SAVE_STATE_MAGIC {
+            return Err(invalid_state("not a qoboy save state"));
+        }
+
+        let version = reader.read_u16()?;
+        if version != SAVE_STATE_VERSION {
+            return Err(invalid_state(&format!("unsupported version {}", version)));
+        }
+
+        self.soc.load_state(&mut reader)?;
+
+        if !reader.is_empty() {
+            return Err(invalid_state("unexpected data at the end of the state"));
         }
+
+        Ok(())
+    }
+
+    /// Write a snapshot of the emulated system in a file.
+    pub fn save_state_to_file<P: AsRef<Path>>(&self, path: P) -> std::io::Result<()> {
+        std::fs::write(path, self.save_state())
+    }
+
+    /// Restore a snapshot written by `save_state_to_file`.
+    pub fn load_state_from_file<P: AsRef<Path>>(&mut self, path: P) -> std::io::Result<()> {
+        let data = std::fs::read(path)?;
+        self.load_state(&data)
     }
 }
 
+#[cfg(test)]
+mod emulator_tests {
+    use super::*;
+    use crate::cartridge::{CARTRIDGE_TYPE_OFFSET, CARTRIDGE_RAM_SIZE_OFFSET, CARTRIDGE_ROM_SIZE_OFFSET};
+
+    fn build_emulator() -> Emulator {
+        // boot rom which enables the lcd then loops on incrementing register a
+        let mut boot_rom = [0x00; 256];
+        boot_rom[0..8].copy_from_slice(&[0x3E, 0x91, 0xE0, 0x40, 0x3C, 0x18, 0xFD, 0x00]);
+        let mut rom = [0xFF; 0x8000];
+        rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
+        rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
+        rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
+
+        Emulator::new(&boot_rom, &rom).unwrap()
+    }
+
+    #[test]
+    fn test_save_load_state() {
+        let mut emulator = build_emulator();
+        for _ in 0..10000 {
+            emulator.soc.run();
+        }
+        let state = emulator.save_state();
+
+        // run the emulator further from the saved point
+        for _ in 0..10000 {
+            emulator.soc.run();
+        }
+        let expected_state = emulator.save_state();
+        assert_ne!(state, expected_state);
+
+        // restoring the state shall give the same execution
+        emulator.load_state(&state).unwrap();
+        assert_eq!(emulator.save_state(), state);
+        for _ in 0..10000 {
+            emulator.soc.run();
+        }
+        assert_eq!(emulator.save_state(), expected_state);
+    }
+
+    #[test]
+    fn test_emulator_is_send() {
+        fn assert_send<T: Send>(_: &T) {}
+
+        let mut emulator = build_emulator();
+        emulator.set_serial_device(Box::new(Loopback));
+        assert_send(&emulator);
+    }
+
+    #[test]
+    fn test_load_invalid_state() {
+        let mut emulator = build_emulator();
+        let mut state = emulator.save_state();
+
+        // move the emulator away from the saved state
+        for _ in 0..10000 {
+            emulator.soc.run();
+        }
+        let current_state = emulator.save_state();
+        assert_ne!(state, current_state);
+
+        // truncated state, the first components are decoded before the error
+        assert!(emulator.load_state(&state[..state.len() - 1]).is_err());
+        assert_eq!(emulator.save_state(), current_state);
+
+        // trailing data after a complete state
+        let mut long_state = state.clone();
+        long_state.push(0x00);
+        assert!(emulator.load_state(&long_state).is_err());
+        assert_eq!(emulator.save_state(), current_state);
+
+        // wrong magic number
+        state[0] = 0x00;
+        assert!(emulator.load_state(&state).is_err());
+        assert_eq!(emulator.save_state(), current_state);
+    }
+
+    #[test]
+    fn test_run_frame() {
+        let mut emulator = build_emulator();
+
+        // the first frame ends as soon as the gpu enters vblank
+        let result = emulator.run_frame();
+        assert!(result.vblank);
+        assert!(result.cycles < ONE_FRAME_IN_CYCLES);
+
+        // then each frame lasts the duration of a frame
+        let result = emulator.run_frame();
+        assert!(result.vblank);
+        assert!(result.cycles.abs_diff(ONE_FRAME_IN_CYCLES) < 24);
+
+        // the emulation only depends on the executed cycles
+        let mut other_emulator = build_emulator();
+        other_emulator.run_cycles(ONE_FRAME_IN_CYCLES);
+        other_emulator.run_frame();
+        assert_eq!(emulator.get_frame(), other_emulator.get_frame());
+        assert_eq!(emulator.save_state(), other_emulator.save_state());
+    }
+
+    struct FailingSink;
+
+    impl AudioSink for FailingSink {
+        fn push_samples(&mut self, _: &[f32]) -> std::io::Result<()> {
+            Err(std::io::Error::other("device unplugged"))
+        }
+    }
+
+    #[test]
+    fn test_audio_sink_error() {
+        // the failing sink is removed and its error is kept for the caller
+        let mut emulator = build_emulator();
+        emulator.set_audio_sink(Box::new(FailingSink));
+        emulator.run_frame();
+        assert!(emulator.remove_audio_sink().is_none());
+        assert_eq!(emulator.take_audio_error().unwrap().to_string(), "device unplugged");
+        assert!(emulator.take_audio_error().is_none());
+    }
+
+    #[test]
+    fn test_run_frame_lcd_off() {
+        // jr -2
+        let mut boot_rom = [0x00; 256];
+        boot_rom[0..2].copy_from_slice(&[0x18, 0xFE]);
+        let mut rom = [0xFF; 0x8000];
+        rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
+        rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
+        rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
+        let mut emulator = Emulator::new(&boot_rom, &rom).unwrap();
+
+        let result = emulator.run_frame();
+        assert!(!result.vblank);
+        assert_eq!(result.cycles, ONE_FRAME_IN_CYCLES);
+
+        let result = emulator.run_cycles(100);
+        assert_eq!(result, RunResult { cycles: 108, vblank: false, rumble: None, faults: Vec::new() });
+    }
+
+    #[test]
+    fn test_rumble() {
+        // ld a,0x08 / ld (0x4000),a / xor a / ld (0x4000),a / ld a,0x08 / ld (0x4000),a / jr -2
+        let mut rom = [0xFF; 0x8000];
+        rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x1C;
+        rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
+        rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
+        rom[0x0100..0x0110].copy_from_slice(&[0x3E, 0x08, 0xEA, 0x00, 0x40, 0xAF, 0xEA, 0x00, 0x40,
+            0x3E, 0x08, 0xEA, 0x00, 0x40, 0x18, 0xFE]);
+        let mut emulator = Emulator::new_without_boot_rom(&rom, GameBoyModel::Dmg).unwrap();
+
+        emulator.step_instruction();
+        assert_eq!(emulator.step_instruction().rumble, Some(true));
+        emulator.step_instruction();
+        assert_eq!(emulator.step_instruction().rumble, Some(false));
+        // the last change of a run is reported
+        assert_eq!(emulator.run_cycles(20).rumble, Some(true));
+        assert_eq!(emulator.run_frame().rumble, None);
+    }
+
+    #[test]
+    fn test_invalid_boot_rom() {
+        let mut rom = [0xFF; 0x8000];
+        rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
+        rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
+        rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
+
+        assert_eq!(Emulator::new(&[0x00; 128], &rom).err(), Some(EmulatorError::InvalidBootRomSize(128)));
+    }
+
+    #[test]
+    fn test_new_without_boot_rom() {
+        // jr -2 at the entry point of the cartridge
+        let mut rom = [0xFF; 0x8000];
+        rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
+        rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
+        rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
+        rom[0x0100..0x0102].copy_from_slice(&[0x18, 0xFE]);
+
+        let mut emulator = Emulator::new_without_boot_rom(&rom, GameBoyModel::Dmg).unwrap();
+        assert_eq!(emulator.soc.cpu.pc, 0x0100);
+        assert_eq!(emulator.soc.cpu.registers.read_af(), 0x01B0);
+        // the cartridge is mapped from the beginning
+        assert_eq!(emulator.read_memory(0x0000), 0xFF);
+        let io_registers: Vec<u8> = [0xFF04, 0xFF0F, 0xFF26, 0xFF40, 0xFF41, 0xFF44]
+            .iter()
+            .map(|&address| emulator.read_memory(address))
+            .collect();
+        assert_eq!(io_registers, [0xAB, 0xE1, 0xF1, 0x91, 0x85, 0x00]);
+        assert_eq!(emulator.soc.peripheral.gpu.background_palette.color_0, PixelColor::WHITE);
+        assert_eq!(emulator.soc.peripheral.gpu.background_palette.color_1, PixelColor::BLACK);
+
+        // the vertical blanking period ends within a line, then 144 lines are drawn
+        assert_eq!(emulator.step_instruction().cycles, 12);
+        assert_eq!(emulator.soc.cpu.pc, 0x0100);
+        let cycles = emulator.run_frame().cycles;
+        assert!((144 * 456..146 * 456).contains(&cycles));
+
+        let emulator = Emulator::new_without_boot_rom(&rom, GameBoyModel::Dmg0).unwrap();
+        assert_eq!(emulator.read_memory(0xFF04), 0x18);
+        assert_eq!(emulator.read_memory(0xFF41), 0x81);
+        assert_eq!(emulator.read_memory(0xFF44), 0x91);
+    }
+
+    #[test]
+    fn test_builtin_boot_rom() {
+        // header with a logo made of its byte indexes and a valid checksum, jr -2 at the entry point
+        let mut rom = [0x00; 0x8000];
+        rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
+        rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
+        rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
+        rom[0x0100..0x0102].copy_from_slice(&[0x18, 0xFE]);
+        for (index, data) in rom[0x0104..0x0134].iter_mut().enumerate() {
+            *data = index as u8;
+        }
+        rom[0x014D] = rom[0x0134..0x014D].iter().fold(0, |checksum: u8, &data| checksum.wrapping_sub(data).wrapping_sub(1));
+
+        let mut emulator = Emulator::new(&BUILTIN_BOOT_ROM, &rom).unwrap();
+        let mut cycles = 0;
+        while emulator.soc.cpu.pc != 0x0100 {
+            cycles += emulator.step_instruction().cycles;
+            assert!(cycles < 200 * ONE_FRAME_IN_CYCLES);
+        }
+
+        // the registers are the ones of the original boot rom
+        let skipped = Emulator::new_without_boot_rom(&rom, GameBoyModel::Dmg).unwrap();
+        let registers = |emulator: &Emulator| {
+            let cpu = &emulator.soc.cpu;
+            (cpu.registers.read_af(), cpu.registers.read_bc(), cpu.registers.read_de(), cpu.registers.read_hl(), cpu.sp)
+        };
+        assert_eq!(registers(&emulator), registers(&skipped));
+        // the io registers too, with the gpu on line 153 and the same divider value
+        for address in [0xFF04, 0xFF0F, 0xFF40, 0xFF41, 0xFF42, 0xFF43, 0xFF44, 0xFF45] {
+            assert_eq!(emulator.read_memory(address), skipped.read_memory(address));
+        }
+        assert_eq!(emulator.read_memory(0xFF26), 0xF1);
+        assert_eq!(emulator.read_memory(0x0000), 0x00);
+
+        // the logo is drawn twice as large, the low nibble of its second byte is the bottom of tile 1
+        assert_eq!(emulator.read_memory(0x801A), 0x00);
+        assert_eq!(emulator.read_memory(0x801C), 0x03);
+        assert_eq!(emulator.read_memory(0x801E), 0x03);
+        assert_eq!(emulator.read_memory(0x9904), 0x01);
+        assert_eq!(emulator.read_memory(0x992F), 0x18);
+    }
+
+    #[test]
+    fn test_fault_policy() {
+        // nop / unknown opcode 0xD3 / inc a / jr -3
+        let mut boot_rom = [0x00; 256];
+        boot_rom[0..5].copy_from_slice(&[0x00, 0xD3, 0x3C, 0x18, 0xFD]);
+        let mut rom = [0xFF; 0x8000];
+        rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
+        rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
+        rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
+
+        // the halted system doesn't execute any instruction until the fault is cleared
+        let mut emulator = Emulator::new(&boot_rom, &rom).unwrap();
+        emulator.set_fault_policy(FaultPolicy::Halt);
+        emulator.step_instruction();
+        let result = emulator.step_instruction();
+        assert_eq!(result.faults, [EmulatorError::UnknownOpcode { opcode: 0xD3, address: 0x0001 }]);
+        assert_eq!(emulator.get_fault(), Some(&EmulatorError::UnknownOpcode { opcode: 0xD3, address: 0x0001 }));
+        let pc = emulator.soc.cpu.pc;
+        let result = emulator.step_instruction();
+        assert_eq!(emulator.soc.cpu.pc, pc);
+        assert!(result.faults.is_empty());
+        emulator.clear_fault();
+        emulator.step_instruction();
+        assert_eq!(emulator.soc.cpu.pc, pc + 1);
+
+        // the unknown opcode is skipped
+        let mut emulator = Emulator::new(&boot_rom, &rom).unwrap();
+        emulator.set_fault_policy(FaultPolicy::LogAndContinue);
+        let result = emulator.run_cycles(16);
+        assert_eq!(result.faults, [EmulatorError::UnknownOpcode { opcode: 0xD3, address: 0x0001 }]);
+        assert!(emulator.get_fault().is_none());
+        assert_eq!(emulator.soc.cpu.pc, 0x0002);
+    }
+
+    // ldh a,(0x03) / ldh (0x03),a / inc a / jr -3, 0xFF03 is not mapped
+    fn build_unknown_io_emulator(fault_policy: FaultPolicy) -> Emulator {
+        let mut boot_rom = [0x00; 256];
+        boot_rom[0..7].copy_from_slice(&[0xF0, 0x03, 0xE0, 0x03, 0x3C, 0x18, 0xFD]);
+        let mut rom = [0xFF; 0x8000];
+        rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
+        rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
+        rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
+
+        let mut emulator = Emulator::new(&boot_rom, &rom).unwrap();
+        emulator.set_fault_policy(fault_policy);
+        emulator
+    }
+
+    #[test]
+    fn test_unknown_io_halt() {
+        // each access halts the system until the fault is cleared
+        let mut emulator = build_unknown_io_emulator(FaultPolicy::Halt);
+        emulator.step_instruction();
+        assert_eq!(emulator.get_fault(), Some(&EmulatorError::UnknownIoRead(0xFF03)));
+        emulator.step_instruction();
+        assert_eq!(emulator.soc.cpu.pc, 0x0002);
+
+        emulator.clear_fault();
+        emulator.step_instruction();
+        assert_eq!(emulator.get_fault(), Some(&EmulatorError::UnknownIoWrite(0xFF03, 0xFF)));
+        assert_eq!(emulator.soc.cpu.pc, 0x0004);
+    }
+
+    #[test]
+    fn test_unknown_io_log_and_continue() {
+        // the unknown registers read as 0xFF and ignore the writes
+        let mut emulator = build_unknown_io_emulator(FaultPolicy::LogAndContinue);
+        let mut result = RunResult::default();
+        for _ in 0..3 {
+            result.add(emulator.step_instruction());
+        }
+        assert_eq!(result.faults, [EmulatorError::UnknownIoRead(0xFF03), EmulatorError::UnknownIoWrite(0xFF03, 0xFF)]);
+        assert!(emulator.get_fault().is_none());
+        assert_eq!(emulator.soc.cpu.pc, 0x0005);
+        assert_eq!(emulator.soc.cpu.registers.a, 0x00);
+    }
+
+    #[test]
+    fn test_unknown_io_read_abort() {
+        // the fault is returned to the caller instead of stopping the host
+        let mut emulator = build_unknown_io_emulator(FaultPolicy::Abort);
+        let result = emulator.step_instruction();
+        assert_eq!(result.faults, [EmulatorError::UnknownIoRead(0xFF03)]);
+        assert_eq!(emulator.get_fault(), Some(&EmulatorError::UnknownIoRead(0xFF03)));
+        emulator.step_instruction();
+        assert_eq!(emulator.soc.cpu.pc, 0x0002);
+    }
+
+    #[test]
+    fn test_unknown_io_write_abort() {
+        // go past the read before selecting the abort policy
+        let mut emulator = build_unknown_io_emulator(FaultPolicy::LogAndContinue);
+        emulator.step_instruction();
+        emulator.set_fault_policy(FaultPolicy::Abort);
+        let result = emulator.run_frame();
+        assert_eq!(result.faults, [EmulatorError::UnknownIoWrite(0xFF03, 0xFF)]);
+        assert_eq!(emulator.soc.cpu.pc, 0x0004);
+    }
+}
@@ -0,0 +1,45 @@
+use crate::cartridge::MbcType;
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum EmulatorError {
+    // the rom is too small to contain a cartridge header
+    RomTooSmall(usize),
+    UnknownMbcType(u8),
+    UnknownRomSize(u8),
+    UnknownRamSize(u8),
+    UnsupportedMbc(MbcType),
+    InvalidBootRomSize(usize),
+    // runtime faults
+    UnknownOpcode { opcode: u8, address: u16 },
+    UnknownIoRead(u16),
+    UnknownIoWrite(u16, u8),
+}
+
+impl std::fmt::Display for EmulatorError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EmulatorError::RomTooSmall(size) => write!(f, "rom of {} bytes is too small to contain a cartridge header", size),
+            EmulatorError::UnknownMbcType(code) => write!(f, "cartridge with mbc type {:#04x} is unknown", code),
+            EmulatorError::UnknownRomSize(code) => write!(f, "cartridge with rom size code {:#04x} is unknown", code),
+            EmulatorError::UnknownRamSize(code) => write!(f, "cartridge with ram size code {:#04x} is unknown", code),
+            EmulatorError::UnsupportedMbc(mbc_type) => write!(f, "cartridge with mbc type {} is not supported", mbc_type),
+            EmulatorError::InvalidBootRomSize(size) => write!(f, "boot rom of {} bytes shall be 256 bytes long", size),
+            EmulatorError::UnknownOpcode { opcode, address } => write!(f, "unknown instruction {:#04x} at address {:#06x}", opcode, address),
+            EmulatorError::UnknownIoRead(address) => write!(f, "reading from an unknown I/O register {:#06x}", address),
+            EmulatorError::UnknownIoWrite(address, data) => write!(f, "writing {:#04x} to an unknown I/O register {:#06x}", data, address),
+        }
+    }
+}
+
+impl std::error::Error for EmulatorError {}
+
+// what to do when the emulated program does something the emulator can't handle
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum FaultPolicy {
+    // freeze the emulated system until the fault is cleared, for instance by the debugger
+    Halt,
+    // keep running, the caller logs the faults of the run result
+    LogAndContinue,
+    // freeze the emulated system, the caller is expected to stop instead of resuming it
+    Abort,
+}
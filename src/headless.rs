@@ -0,0 +1,273 @@
+use crate::emulator::{Emulator, GameBoyKey, RunResult, SCREEN_HEIGHT, SCREEN_WIDTH};
+use crate::error::EmulatorError;
+use crate::image::{write_grayscale_png, GRAY_SHADES};
+use crate::soc::peripheral::gpu::{color_to_index, PixelColor};
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+
+// fnv-1a 64 bits parameters
+const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+/// A key pressed or released at the beginning of a frame.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct InputEvent {
+    pub frame: usize,
+    pub key: GameBoyKey,
+    pub pressed: bool,
+}
+
+/// Keys to press and release while a rom runs without a window.
+///
+/// Each line of a script holds the frame number, the action and the key, empty lines
+/// and lines starting with `#` are ignored:
+///
+/// ```text
+/// # open the menu then leave it
+/// 60 press START
+/// 65 release START
+/// ```
+pub struct InputScript {
+    events: Vec<InputEvent>,
+}
+
+impl InputScript {
+    pub fn new() -> InputScript {
+        InputScript {
+            events: Vec::new(),
+        }
+    }
+
+    pub fn parse(text: &str) -> std::io::Result<InputScript> {
+        let mut events = Vec::new();
+
+        for (line_index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let invalid_line = || Error::new(ErrorKind::InvalidData, format!("invalid input script line {}: {}", line_index + 1, line));
+
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() != 3 {
+                return Err(invalid_line());
+            }
+
+            let frame = fields[0].parse().map_err(|_| invalid_line())?;
+            let pressed = match fields[1] {
+                "press" => true,
+                "release" => false,
+                _ => return Err(invalid_line()),
+            };
+            let key = get_key(fields[2]).ok_or_else(invalid_line)?;
+
+            events.push(InputEvent { frame, key, pressed });
+        }
+
+        // events of a frame keep the order of the script
+        events.sort_by_key(|event| event.frame);
+
+        Ok(InputScript {
+            events,
+        })
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> std::io::Result<InputScript> {
+        InputScript::parse(&std::fs::read_to_string(path)?)
+    }
+
+    pub fn get_events(&self) -> &[InputEvent] {
+        &self.events
+    }
+
+    // set the keys of the frame before it runs
+    pub fn apply(&self, frame: usize, emulator: &mut Emulator) {
+        for event in self.events.iter().filter(|event| event.frame == frame) {
+            emulator.set_key(event.key, event.pressed);
+        }
+    }
+}
+
+impl Default for InputScript {
+    fn default() -> Self {
+        InputScript::new()
+    }
+}
+
+fn get_key(name: &str) -> Option<GameBoyKey> {
+    let key = match name.to_ascii_uppercase().as_str() {
+        "START" => GameBoyKey::START,
+        "SELECT" => GameBoyKey::SELECT,
+        "A" => GameBoyKey::A,
+        "B" => GameBoyKey::B,
+        "UP" => GameBoyKey::UP,
+        "DOWN" => GameBoyKey::DOWN,
+        "LEFT" => GameBoyKey::LEFT,
+        "RIGHT" => GameBoyKey::RIGHT,
+        _ => return None,
+    };
+
+    Some(key)
+}
+
+/// How a headless run has ended.
+#[derive(Clone, PartialEq, Debug)]
+pub enum HeadlessStatus {
+    /// All the frames have been run.
+    Completed,
+    /// The cpu can't run anymore since the given frame.
+    LockedUp(usize),
+    /// The emulated program did something the emulator can't handle.
+    Fault(EmulatorError),
+}
+
+/// Run `frames` frames as fast as possible, stop early if the emulated system can't go on.
+///
+/// Faults only stop the run when the emulator uses the `FaultPolicy::Halt` or `FaultPolicy::Abort` policy.
+pub fn run_headless(emulator: &mut Emulator, frames: usize, script: &InputScript) -> HeadlessStatus {
+    run_headless_with(emulator, frames, script, |_, _| {})
+}
+
+/// Same as [`run_headless`], `on_frame` receives the index and the result of each frame run,
+/// for instance to report the changes of the rumble motor.
+pub fn run_headless_with<F>(emulator: &mut Emulator, frames: usize, script: &InputScript, mut on_frame: F) -> HeadlessStatus
+where
+    F: FnMut(usize, &RunResult),
+{
+    for frame in 0..frames {
+        script.apply(frame, emulator);
+        let result = emulator.run_frame();
+        on_frame(frame, &result);
+
+        if let Some(error) = emulator.get_fault() {
+            return HeadlessStatus::Fault(error.clone());
+        }
+
+        if emulator.is_locked_up() {
+            return HeadlessStatus::LockedUp(frame);
+        }
+    }
+
+    HeadlessStatus::Completed
+}
+
+/// Fnv-1a hash of a frame, to compare the output of two runs.
+pub fn frame_hash(frame: &[u8]) -> u64 {
+    frame.iter().fold(FNV_OFFSET_BASIS, |hash, &shade| (hash ^ shade as u64).wrapping_mul(FNV_PRIME))
+}
+
+/// Write the last frame rendered by the gpu in a grayscale png file, with the gray levels
+/// of the reference images.
+pub fn write_frame_png<P: AsRef<Path>>(path: P, emulator: &Emulator) -> std::io::Result<()> {
+    let pixels: Vec<u8> = emulator.get_frame()
+        .iter()
+        .map(|&shade| PixelColor::from_shade(shade).map_or(shade, |color| GRAY_SHADES[color_to_index(color) as usize]))
+        .collect();
+
+    write_grayscale_png(path, SCREEN_WIDTH, SCREEN_HEIGHT, &pixels)
+}
+
+/// The whole address space of the gameboy, from 0x0000 to 0xFFFF.
+pub fn dump_memory(emulator: &Emulator) -> Vec<u8> {
+    (0..=0xFFFF).map(|address| emulator.read_memory(address)).collect()
+}
+
+#[cfg(test)]
+mod headless_tests {
+    use super::*;
+    use crate::error::FaultPolicy;
+    use crate::emulator::GameBoyModel;
+    use crate::cartridge::{CARTRIDGE_TYPE_OFFSET, CARTRIDGE_RAM_SIZE_OFFSET, CARTRIDGE_ROM_SIZE_OFFSET};
+
+    fn build_emulator(program: &[u8]) -> Emulator {
+        let mut boot_rom = [0x00; 256];
+        boot_rom[0..program.len()].copy_from_slice(program);
+        let mut rom = [0xFF; 0x8000];
+        rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
+        rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
+        rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
+
+        let mut emulator = Emulator::new(&boot_rom, &rom).unwrap();
+        emulator.set_fault_policy(FaultPolicy::Halt);
+        emulator
+    }
+
+    #[test]
+    fn test_input_script() {
+        let script = InputScript::parse("# comment\n\n10 release start\n  2 press A  \n10 press START\n").unwrap();
+        assert_eq!(script.get_events(), &[
+            InputEvent { frame: 2, key: GameBoyKey::A, pressed: true },
+            InputEvent { frame: 10, key: GameBoyKey::START, pressed: false },
+            InputEvent { frame: 10, key: GameBoyKey::START, pressed: true },
+        ]);
+
+        assert!(InputScript::parse("10 press").is_err());
+        assert!(InputScript::parse("10 hold A").is_err());
+        assert!(InputScript::parse("10 press C").is_err());
+        assert!(InputScript::parse("-1 press A").is_err());
+    }
+
+    #[test]
+    fn test_input_script_run() {
+        // ld a,0x10 / ldh (P1),a / ldh a,(P1) / ld (0xC000),a / jr -7
+        let mut emulator = build_emulator(&[0x3E, 0x10, 0xE0, 0x00, 0xF0, 0x00, 0xEA, 0x00, 0xC0, 0x18, 0xF7]);
+        let script = InputScript::parse("1 press A\n2 release A").unwrap();
+
+        assert_eq!(run_headless(&mut emulator, 1, &script), HeadlessStatus::Completed);
+        assert_eq!(emulator.read_memory(0xC000) & 0x0F, 0x0F);
+        assert_eq!(run_headless(&mut emulator, 2, &script), HeadlessStatus::Completed);
+        assert_eq!(emulator.read_memory(0xC000) & 0x0F, 0x0E);
+    }
+
+    #[test]
+    fn test_run_headless_with() {
+        // rumble cartridge which turns its motor on: ld a,0x08 / ld (0x4000),a / jr -2
+        let mut rom = [0xFF; 0x8000];
+        rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x1C;
+        rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
+        rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
+        rom[0x0100..0x0107].copy_from_slice(&[0x3E, 0x08, 0xEA, 0x00, 0x40, 0x18, 0xFE]);
+        let mut emulator = Emulator::new_without_boot_rom(&rom, GameBoyModel::Dmg).unwrap();
+
+        // each frame result is reported, with the change of the motor
+        let mut rumble = Vec::new();
+        let status = run_headless_with(&mut emulator, 3, &InputScript::new(), |frame, result| rumble.push((frame, result.rumble)));
+        assert_eq!(status, HeadlessStatus::Completed);
+        assert_eq!(rumble, [(0, Some(true)), (1, None), (2, None)]);
+    }
+
+    #[test]
+    fn test_lockup() {
+        // di / xor a / ldh (IE),a / halt
+        let mut emulator = build_emulator(&[0xF3, 0xAF, 0xE0, 0xFF, 0x76]);
+        assert_eq!(run_headless(&mut emulator, 10, &InputScript::new()), HeadlessStatus::LockedUp(0));
+
+        // nop / unknown opcode
+        let mut emulator = build_emulator(&[0x00, 0xD3]);
+        assert_eq!(run_headless(&mut emulator, 10, &InputScript::new()),
+            HeadlessStatus::Fault(EmulatorError::UnknownOpcode { opcode: 0xD3, address: 0x0001 }));
+    }
+
+    #[test]
+    fn test_frame_hash() {
+        assert_eq!(frame_hash(&[]), FNV_OFFSET_BASIS);
+        assert_eq!(frame_hash(b"a"), 0xaf63dc4c8601ec8c);
+        assert_ne!(frame_hash(&[0, 255]), frame_hash(&[255, 0]));
+    }
+
+    #[test]
+    fn test_dump_memory() {
+        // ld a,0x42 / ld (0xC123),a / jr -2
+        let mut emulator = build_emulator(&[0x3E, 0x42, 0xEA, 0x23, 0xC1, 0x18, 0xFE]);
+        run_headless(&mut emulator, 1, &InputScript::new());
+
+        let memory = dump_memory(&emulator);
+        assert_eq!(memory.len(), 0x10000);
+        assert_eq!(memory[0x0000], 0x3E);
+        assert_eq!(memory[0xC123], 0x42);
+        // reading unknown registers is not a fault
+        assert!(emulator.get_fault().is_none());
+        assert_eq!(emulator.step_instruction().cycles, 12);
+    }
+}
@@ -0,0 +1,88 @@
+use std::fs::File;
+use std::io::{BufWriter, Error, ErrorKind};
+use std::path::Path;
+
+// shades of the gameboy screen and printer, from white to black
+pub const GRAY_SHADES: [u8; 4] = [255, 170, 85, 0];
+
+// write 8 bits grayscale pixels, row by row, in a png file
+pub fn write_grayscale_png<P: AsRef<Path>>(path: P, width: usize, height: usize, pixels: &[u8]) -> std::io::Result<()> {
+    write_png(path, width, height, png::ColorType::Grayscale, pixels)
+}
+
+// write 8 bits red, green and blue pixels, row by row, in a png file
+pub fn write_rgb_png<P: AsRef<Path>>(path: P, width: usize, height: usize, pixels: &[u8]) -> std::io::Result<()> {
+    write_png(path, width, height, png::ColorType::Rgb, pixels)
+}
+
+// read any png file as 8 bits red, green and blue pixels, returns the width, the height and the pixels
+pub fn read_rgb_png<P: AsRef<Path>>(path: P) -> std::io::Result<(usize, usize, Vec<u8>)> {
+    let mut decoder = png::Decoder::new(File::open(path)?);
+    // palettes and other bit depths are converted to 8 bits per channel
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+
+    let mut reader = decoder.read_info().map_err(std::io::Error::other)?;
+    let mut buffer = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buffer).map_err(std::io::Error::other)?;
+    buffer.truncate(info.buffer_size());
+
+    let pixels = match info.color_type {
+        png::ColorType::Grayscale => buffer.iter().flat_map(|&gray| [gray, gray, gray]).collect(),
+        png::ColorType::GrayscaleAlpha => buffer.chunks_exact(2).flat_map(|pixel| [pixel[0], pixel[0], pixel[0]]).collect(),
+        png::ColorType::Rgb => buffer,
+        png::ColorType::Rgba => buffer.chunks_exact(4).flat_map(|pixel| [pixel[0], pixel[1], pixel[2]]).collect(),
+        png::ColorType::Indexed => return Err(Error::new(ErrorKind::InvalidData, "png palette has not been expanded")),
+    };
+
+    Ok((info.width as usize, info.height as usize, pixels))
+}
+
+fn write_png<P: AsRef<Path>>(path: P, width: usize, height: usize, color_type: png::ColorType, pixels: &[u8]) -> std::io::Result<()> {
+    let file = BufWriter::new(File::create(path)?);
+
+    let mut encoder = png::Encoder::new(file, width as u32, height as u32);
+    encoder.set_color(color_type);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let mut writer = encoder.write_header().map_err(std::io::Error::other)?;
+    writer.write_image_data(pixels).map_err(std::io::Error::other)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod image_tests {
+    use super::*;
+
+    #[test]
+    fn test_write_png() {
+        let path = std::env::temp_dir().join("qoboy_test_image.png");
+        let pixels: Vec<u8> = (0..4 * 3).map(|index| GRAY_SHADES[index % 4]).collect();
+
+        write_grayscale_png(&path, 4, 3, &pixels).unwrap();
+
+        let decoder = png::Decoder::new(File::open(&path).unwrap());
+        let mut reader = decoder.read_info().unwrap();
+        let mut buffer = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buffer).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!((info.width, info.height), (4, 3));
+        assert_eq!(info.color_type, png::ColorType::Grayscale);
+        assert_eq!(buffer, pixels);
+    }
+
+    #[test]
+    fn test_read_rgb_png() {
+        let path = std::env::temp_dir().join("qoboy_test_rgb_image.png");
+
+        // grayscale pixels are read as rgb pixels
+        write_grayscale_png(&path, 2, 1, &[0x00, 0xAA]).unwrap();
+        assert_eq!(read_rgb_png(&path).unwrap(), (2, 1, vec![0x00, 0x00, 0x00, 0xAA, 0xAA, 0xAA]));
+
+        write_rgb_png(&path, 1, 2, &[0x01, 0x02, 0x03, 0x04, 0x05, 0x06]).unwrap();
+        assert_eq!(read_rgb_png(&path).unwrap(), (1, 2, vec![0x01, 0x02, 0x03, 0x04, 0x05, 0x06]));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
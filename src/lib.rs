@@ -0,0 +1,48 @@
+//! Qoboy, a gameboy emulator which can be embedded in other programs.
+//!
+//! The [`Emulator`] is created from a boot rom and a game rom, then driven by the
+//! host which feeds the keys and reads the frames. The [`BUILTIN_BOOT_ROM`] replaces the
+//! original boot rom when no dump is available. The emulator never looks at the
+//! host clock, it runs as fast as the host allows and the frame pacing is left to
+//! the caller:
+//!
+//! ```no_run
+//! use qoboy::{Emulator, GameBoyKey, SCREEN_WIDTH, SCREEN_HEIGHT};
+//!
+//! let boot_rom = std::fs::read("dmg_boot.bin").unwrap();
+//! let rom = std::fs::read("game.gb").unwrap();
+//! let mut emulator = Emulator::new(&boot_rom, &rom).unwrap();
+//!
+//! emulator.set_key(GameBoyKey::START, true);
+//! emulator.run_frame();
+//!
+//! // one 8 bits gray shade per pixel, row by row
+//! let frame = emulator.get_frame();
+//! assert_eq!(frame.len(), SCREEN_WIDTH * SCREEN_HEIGHT);
+//! ```
+//!
+//! The minifb window and the debugger threads are only built with the `frontend`
+//! feature, enabled by default. Embedders can disable the default features to
+//! avoid pulling in the window dependencies.
+
+pub mod emulator;
+pub mod error;
+pub mod audio;
+pub mod headless;
+pub mod testrom;
+pub mod screenshot;
+mod cartridge;
+mod link;
+mod netplay;
+mod printer;
+mod soc;
+mod savestate;
+mod image;
+
+pub use crate::emulator::{Emulator, RunResult, GameBoyKey, GameBoyModel, PixelColor, SerialDevice, BUILTIN_BOOT_ROM, SCREEN_WIDTH, SCREEN_HEIGHT};
+pub use crate::error::{EmulatorError, FaultPolicy};
+pub use crate::cartridge::{CartridgeHeader, CgbSupport, Destination, MbcType, RomSize, RamSize};
+pub use crate::link::LinkedEmulators;
+pub use crate::netplay::NetworkLink;
+pub use crate::printer::Printer;
+pub use crate::savestate::get_state_path;
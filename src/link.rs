@@ -0,0 +1,170 @@
+use crate::emulator::{Emulator, RunResult, ONE_FRAME_IN_CYCLES};
+use crate::soc::peripheral::serial::{SerialDevice, SERIAL_DISCONNECTED_BYTE};
+use std::sync::{Arc, Mutex};
+
+// state of the cable shared by both ends
+struct LinkState {
+    // byte of a gameboy waiting for the other end to drive the clock
+    waiting: [Option<u8>; 2],
+    // byte sent by the master to a waiting gameboy
+    received: [Option<u8>; 2],
+}
+
+// one end of a link cable between two emulators of the same process
+pub struct LinkPort {
+    side: usize,
+    state: Arc<Mutex<LinkState>>,
+}
+
+// create both ends of a link cable
+pub fn link_ports() -> (LinkPort, LinkPort) {
+    let state = Arc::new(Mutex::new(LinkState {
+        waiting: [None; 2],
+        received: [None; 2],
+    }));
+
+    (
+        LinkPort { side: 0, state: Arc::clone(&state) },
+        LinkPort { side: 1, state },
+    )
+}
+
+impl SerialDevice for LinkPort {
+    fn transfer(&mut self, data: u8) -> u8 {
+        let mut state = self.state.lock().unwrap();
+        let other_side = 1 - self.side;
+
+        // the other end only shifts bits when it waits for an external clock
+        match state.waiting[other_side].take() {
+            Some(other_data) => {
+                state.received[other_side] = Some(data);
+                other_data
+            }
+            None => SERIAL_DISCONNECTED_BYTE,
+        }
+    }
+
+    fn poll_external(&mut self, data: u8) -> Option<u8> {
+        let mut state = self.state.lock().unwrap();
+
+        match state.received[self.side].take() {
+            Some(received_data) => Some(received_data),
+            None => {
+                state.waiting[self.side] = Some(data);
+                None
+            }
+        }
+    }
+
+    fn cancel_external(&mut self) {
+        // neither the other end nor a transfer it has already started can reach us anymore
+        let mut state = self.state.lock().unwrap();
+        state.waiting[self.side] = None;
+        state.received[self.side] = None;
+    }
+}
+
+// two emulators connected with a link cable, the master is the one using its internal clock
+pub struct LinkedEmulators {
+    pub emulators: [Emulator; 2],
+    cycles: [usize; 2],
+}
+
+impl LinkedEmulators {
+    pub fn new(mut first: Emulator, mut second: Emulator) -> LinkedEmulators {
+        let (first_port, second_port) = link_ports();
+        first.set_serial_device(Box::new(first_port));
+        second.set_serial_device(Box::new(second_port));
+
+        LinkedEmulators {
+            emulators: [first, second],
+            cycles: [0; 2],
+        }
+    }
+
+    // run both emulators for one frame in lockstep: the late emulator runs the next
+    // instruction so both stay within one instruction of each other, far below
+    // the duration of a serial bit
+    pub fn run_frame(&mut self) -> [RunResult; 2] {
+        let mut results: [RunResult; 2] = Default::default();
+        while self.cycles[0] < ONE_FRAME_IN_CYCLES || self.cycles[1] < ONE_FRAME_IN_CYCLES {
+            let index = if self.cycles[0] <= self.cycles[1] { 0 } else { 1 };
+            let result = self.emulators[index].step_instruction();
+            self.cycles[index] += result.cycles;
+            results[index].add(result);
+        }
+
+        for index in 0..self.emulators.len() {
+            self.cycles[index] -= ONE_FRAME_IN_CYCLES;
+            self.emulators[index].flush_audio_samples();
+        }
+
+        results
+    }
+}
+
+#[cfg(test)]
+mod link_tests {
+    use super::*;
+    use crate::soc::peripheral::IoAccess;
+    use crate::cartridge::{CARTRIDGE_TYPE_OFFSET, CARTRIDGE_RAM_SIZE_OFFSET, CARTRIDGE_ROM_SIZE_OFFSET};
+
+    fn build_emulator(program: &[u8]) -> Emulator {
+        let mut boot_rom = [0x00; 256];
+        boot_rom[0..program.len()].copy_from_slice(program);
+        let mut rom = [0xFF; 0x8000];
+        rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
+        rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
+        rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
+
+        Emulator::new(&boot_rom, &rom).unwrap()
+    }
+
+    #[test]
+    fn test_link_transfer() {
+        // ld a,0x5A / ldh (SB),a / ld a,0x80 / ldh (SC),a / jr -2
+        let slave = build_emulator(&[0x3E, 0x5A, 0xE0, 0x01, 0x3E, 0x80, 0xE0, 0x02, 0x18, 0xFE]);
+        // nop / nop / ld a,0xA5 / ldh (SB),a / ld a,0x81 / ldh (SC),a / jr -2
+        let master = build_emulator(&[0x00, 0x00, 0x3E, 0xA5, 0xE0, 0x01, 0x3E, 0x81, 0xE0, 0x02, 0x18, 0xFE]);
+
+        let mut link = LinkedEmulators::new(master, slave);
+        link.run_frame();
+
+        // both gameboys have exchanged their bytes
+        assert_eq!(link.emulators[0].soc.peripheral.read(0xFF01), 0x5A);
+        assert_eq!(link.emulators[0].soc.peripheral.read(0xFF02), 0x7F);
+        assert_eq!(link.emulators[1].soc.peripheral.read(0xFF01), 0xA5);
+        assert_eq!(link.emulators[1].soc.peripheral.read(0xFF02), 0x7E);
+    }
+
+    #[test]
+    fn test_link_without_slave() {
+        // the other end does not start any transfer
+        let idle = build_emulator(&[0x18, 0xFE]);
+        let master = build_emulator(&[0x3E, 0xA5, 0xE0, 0x01, 0x3E, 0x81, 0xE0, 0x02, 0x18, 0xFE]);
+
+        let mut link = LinkedEmulators::new(master, idle);
+        link.run_frame();
+
+        assert_eq!(link.emulators[0].soc.peripheral.read(0xFF01), SERIAL_DISCONNECTED_BYTE);
+        assert_eq!(link.emulators[0].soc.peripheral.read(0xFF02), 0x7F);
+    }
+
+    #[test]
+    fn test_link_aborted_transfer() {
+        // the slave stops waiting right after it has started
+        // ld a,0x5A / ldh (SB),a / ld a,0x80 / ldh (SC),a / ld a,0x00 / ldh (SC),a / jr -2
+        let slave = build_emulator(&[0x3E, 0x5A, 0xE0, 0x01, 0x3E, 0x80, 0xE0, 0x02, 0x3E, 0x00, 0xE0, 0x02, 0x18, 0xFE]);
+        // the master starts its transfer after the abort
+        let master = build_emulator(&[0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x3E, 0xA5, 0xE0, 0x01, 0x3E, 0x81, 0xE0, 0x02, 0x18, 0xFE]);
+
+        let mut link = LinkedEmulators::new(master, slave);
+        link.run_frame();
+
+        // the stale byte of the slave is not exchanged
+        assert_eq!(link.emulators[0].soc.peripheral.read(0xFF01), SERIAL_DISCONNECTED_BYTE);
+        assert_eq!(link.emulators[0].soc.peripheral.read(0xFF02), 0x7F);
+        assert_eq!(link.emulators[1].soc.peripheral.read(0xFF01), 0x5A);
+        assert_eq!(link.emulators[1].soc.peripheral.read(0xFF02), 0x7E);
+    }
+}
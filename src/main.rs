@@ -1,36 +1,139 @@
-mod emulator;
-mod soc;
 mod debug;
-mod cartridge;
 
-use minifb::{Key, Window, WindowOptions};
-use std::{fs::File, io::Read, env};
+use minifb::{Key, KeyRepeat, MouseMode, Window, WindowOptions};
+use std::env;
+use std::time::{Duration, Instant};
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
 
-use crate::emulator::{Emulator, SCREEN_HEIGHT, SCREEN_WIDTH};
-use crate::debug::{DebugCtx, debug_cli, debug_vram};
+use qoboy::{Emulator, EmulatorError, FaultPolicy, RunResult, GameBoyKey, GameBoyModel, CartridgeHeader, LinkedEmulators, NetworkLink, Printer, BUILTIN_BOOT_ROM, SCREEN_HEIGHT, SCREEN_WIDTH, get_state_path};
+use qoboy::emulator::ONE_FRAME_IN_NS;
+use crate::debug::{DebugCtx, debug_cli, debug_vram, run_debug_frame};
+use qoboy::headless::{InputScript, HeadlessStatus, run_headless_with, frame_hash, write_frame_png, dump_memory};
+use qoboy::screenshot::{compare_screenshot, ReferencePalette};
+use qoboy::audio::WavSink;
+#[cfg(feature = "audio")]
+use qoboy::audio::PlaybackSink;
 
 // Window parameters
 const SCALE_FACTOR: usize = 3;
 const WINDOW_DIMENSIONS: [usize; 2] = [(SCREEN_WIDTH * SCALE_FACTOR), (SCREEN_HEIGHT * SCALE_FACTOR)];
 
-fn main() {
-    // get arguments from the command line   
-    let (boot_rom_path, game_rom_path, debug_mode) = parse_args();
+// battery backed ram is written to the save file every ~5 seconds
+const SAVE_FILE_FLUSH_PERIOD_IN_FRAMES: usize = 300;
+
+// save state slots: F1-F4 to save, F5-F8 to load
+const SAVE_STATE_KEYS: [Key; 4] = [Key::F1, Key::F2, Key::F3, Key::F4];
+const LOAD_STATE_KEYS: [Key; 4] = [Key::F5, Key::F6, Key::F7, Key::F8];
+
+// keyboard mapping of each window, the second one is used by the linked gameboy
+const PLAYER_1_KEYS: [(Key, GameBoyKey); 8] = [
+    (Key::Up, GameBoyKey::UP),
+    (Key::Down, GameBoyKey::DOWN),
+    (Key::Left, GameBoyKey::LEFT),
+    (Key::Right, GameBoyKey::RIGHT),
+    (Key::A, GameBoyKey::A),
+    (Key::S, GameBoyKey::B),
+    (Key::Enter, GameBoyKey::START),
+    (Key::Space, GameBoyKey::SELECT),
+];
+const PLAYER_2_KEYS: [(Key, GameBoyKey); 8] = [
+    (Key::I, GameBoyKey::UP),
+    (Key::K, GameBoyKey::DOWN),
+    (Key::J, GameBoyKey::LEFT),
+    (Key::L, GameBoyKey::RIGHT),
+    (Key::G, GameBoyKey::A),
+    (Key::H, GameBoyKey::B),
+    (Key::T, GameBoyKey::START),
+    (Key::Y, GameBoyKey::SELECT),
+];
+
+// only the processes of this computer can connect to the link cable by default
+const DEFAULT_LINK_BIND_ADDRESS: &str = "127.0.0.1";
+
+// exit codes of the headless mode
+const EXIT_ERROR: i32 = 1;
+const EXIT_LOCKUP: i32 = 2;
+const EXIT_SCREENSHOT_MISMATCH: i32 = 3;
+
+const HEADLESS_USAGE: &str = "usage: qoboy headless <game_rom_path> --frames <count> [--boot-rom <path>] \
+[--skip-boot <model>] [--input <path>] [--png <path>] [--dump <path>] [--reference <path>]";
+
+// how the emulated gameboy starts
+enum Boot {
+    Rom(Vec<u8>),
+    Skip(GameBoyModel),
+}
+
+struct Args {
+    boot_rom_path: Option<String>,
+    skip_boot: Option<String>,
+    game_rom_path: String,
+    debug_mode: bool,
+    wav_path: Option<String>,
+    link_rom_path: Option<String>,
+    link_bind_address: String,
+    link_listen_port: Option<u16>,
+    link_connect_address: Option<String>,
+    printer_dir: Option<String>,
+    rtc_offset: i64,
+}
 
-    let mut file = File::open(boot_rom_path).unwrap();
-    let mut bin_data = [0xFF as u8; 256];
-    if let Err(message) = file.read_exact(&mut bin_data) {
-        panic!("Cannot read file with error message: {}", message);
+struct HeadlessArgs {
+    game_rom_path: String,
+    boot_rom_path: Option<String>,
+    skip_boot: Option<String>,
+    frames: usize,
+    input_path: Option<String>,
+    png_path: Option<String>,
+    dump_path: Option<String>,
+    reference_path: Option<String>,
+}
+
+fn main() {
+    // print the cartridge header of the rom
+    if env::args().nth(1).as_deref() == Some("info") {
+        std::process::exit(run_info_mode(&env::args().nth(2).unwrap_or_default()));
     }
 
-    let mut rom_file = File::open(game_rom_path).unwrap();
-    let rom_len = rom_file.metadata().unwrap().len();
-    let mut rom_data = vec![0xFF as u8; rom_len as usize];
-    if let Err(message) = rom_file.read_exact(&mut rom_data) {
-        panic!("Cannot read file with error message: {}", message);
+    // run the rom without any window
+    if env::args().nth(1).as_deref() == Some("headless") {
+        match parse_headless_args() {
+            Ok(args) => std::process::exit(run_headless_mode(args)),
+            Err(message) => {
+                println!("{}", message);
+                println!("{}", HEADLESS_USAGE);
+                std::process::exit(EXIT_ERROR);
+            }
+        }
     }
 
+    // get arguments from the command line   
+    let args = match parse_args() {
+        Ok(args) => args,
+        Err(message) => {
+            println!("{}", message);
+            std::process::exit(EXIT_ERROR);
+        }
+    };
+    let game_rom_path = args.game_rom_path;
+    let debug_mode = args.debug_mode;
+
+    let boot = match load_boot(args.boot_rom_path.as_deref(), args.skip_boot.as_deref()) {
+        Ok(boot) => boot,
+        Err(message) => {
+            println!("{}", message);
+            std::process::exit(EXIT_ERROR);
+        }
+    };
+    let rom_data = match read_rom_file(&game_rom_path) {
+        Ok(rom_data) => rom_data,
+        Err(message) => {
+            println!("{}", message);
+            std::process::exit(EXIT_ERROR);
+        }
+    };
+
     // launch the debugger cli
     let dbg_ctx = Arc::new(Mutex::new(DebugCtx::new()));
     if debug_mode {
@@ -39,7 +142,70 @@ fn main() {
     }
 
     // create the emulated system
-    let mut emulator = Emulator::new(&bin_data, &rom_data, debug_mode);
+    let mut emulator = create_emulator(&boot, &rom_data);
+    if debug_mode {
+        // faults are reported to the debugger
+        emulator.set_fault_policy(FaultPolicy::Halt);
+    }
+
+    // restore the game progress from the save file
+    load_save_file(&mut emulator, &game_rom_path);
+    emulator.advance_rtc(args.rtc_offset);
+    let mut frames_since_flush = 0;
+
+    // record the sound in a wav file or play it on the host sound device
+    if let Some(wav_path) = args.wav_path {
+        match WavSink::new(&wav_path, emulator.get_audio_sample_rate()) {
+            Ok(sink) => emulator.set_audio_sink(Box::new(sink)),
+            Err(message) => println!("Cannot create wav file with error message: {}", message),
+        }
+    } else {
+        #[cfg(feature = "audio")]
+        match PlaybackSink::new(emulator.get_audio_sample_rate()) {
+            Ok(sink) => emulator.set_audio_sink(Box::new(sink)),
+            Err(message) => println!("Cannot open audio output with error message: {}", message),
+        }
+    }
+
+    // plug the game boy printer on the serial port
+    if let Some(printer_dir) = args.printer_dir {
+        emulator.set_serial_device(Box::new(Printer::new(PathBuf::from(printer_dir))));
+    }
+
+    // connect a second gameboy with the link cable
+    if let Some(link_rom_path) = args.link_rom_path {
+        let link_rom_data = match read_rom_file(&link_rom_path) {
+            Ok(rom_data) => rom_data,
+            Err(message) => {
+                println!("{}", message);
+                std::process::exit(EXIT_ERROR);
+            }
+        };
+        let mut link_emulator = create_emulator(&boot, &link_rom_data);
+        load_save_file(&mut link_emulator, &link_rom_path);
+        link_emulator.advance_rtc(args.rtc_offset);
+
+        run_linked(LinkedEmulators::new(emulator, link_emulator));
+        return;
+    }
+
+    // connect the link cable to another qoboy process
+    let network_link = if let Some(port) = args.link_listen_port {
+        println!("Waiting for the link cable peer on {}:{}", args.link_bind_address, port);
+        Some(NetworkLink::listen(&args.link_bind_address, port))
+    } else {
+        args.link_connect_address.map(|address| NetworkLink::connect(&address))
+    };
+
+    match network_link {
+        Some(Ok(network_link)) => {
+            emulator.set_serial_device(Box::new(network_link.get_port()));
+            run_network_linked(emulator, network_link);
+            return;
+        }
+        Some(Err(message)) => println!("Cannot connect the link cable with error message: {}", message),
+        None => {}
+    }
 
     // run the emulator
     let mut buffer = [0; SCREEN_HEIGHT * SCREEN_WIDTH];
@@ -53,93 +219,507 @@ fn main() {
     .unwrap();
 
     while window.is_open() && !window.is_key_down(Key::Escape) {
+        let frame_tick = Instant::now();
+
         // get key from the keyboard
-        if window.is_key_down(Key::Up) {
-            emulator.set_key(soc::GameBoyKey::UP, true);
-        } else {
-            emulator.set_key(soc::GameBoyKey::UP, false);
+        update_keys(&window, &mut emulator, &PLAYER_1_KEYS);
+        update_tilt(&window, &mut emulator);
+
+        // save or restore the emulator state
+        for slot in 0..SAVE_STATE_KEYS.len() {
+            let state_path = get_state_path(&game_rom_path, slot + 1);
+
+            if window.is_key_pressed(SAVE_STATE_KEYS[slot], KeyRepeat::No) {
+                match emulator.save_state_to_file(&state_path) {
+                    Ok(()) => println!("State saved in slot {}", slot + 1),
+                    Err(message) => println!("Cannot save state with error message: {}", message),
+                }
+            }
+
+            if window.is_key_pressed(LOAD_STATE_KEYS[slot], KeyRepeat::No) {
+                match emulator.load_state_from_file(&state_path) {
+                    Ok(()) => println!("State loaded from slot {}", slot + 1),
+                    Err(message) => println!("Cannot load state with error message: {}", message),
+                }
+            }
         }
 
-        if window.is_key_down(Key::Down) {
-            emulator.set_key(soc::GameBoyKey::DOWN, true);
+        // run emulator until a new frame is ready
+        let result = if debug_mode {
+            run_debug_frame(&mut emulator, &mut dbg_ctx.lock().unwrap())
         } else {
-            emulator.set_key(soc::GameBoyKey::DOWN, false);
+            emulator.run_frame()
+        };
+        print_faults(&result);
+        print_audio_error(&mut emulator);
+        show_rumble(&mut window, "Qoboy", result.rumble);
+
+        // display the frame rendered by the gpu
+        display_frame(&mut window, &emulator, &mut buffer);
+
+        // periodically persist the game progress
+        frames_since_flush += 1;
+        if frames_since_flush >= SAVE_FILE_FLUSH_PERIOD_IN_FRAMES {
+            frames_since_flush = 0;
+            if let Err(message) = emulator.flush_save_file() {
+                println!("Cannot write save file with error message: {}", message);
+            }
         }
 
-        if window.is_key_down(Key::Left) {
-            emulator.set_key(soc::GameBoyKey::LEFT, true);
-        } else {
-            emulator.set_key(soc::GameBoyKey::LEFT, false);
+        wait_next_frame(frame_tick);
+    }
+
+    // persist the game progress on exit
+    if let Err(message) = emulator.flush_save_file() {
+        println!("Cannot write save file with error message: {}", message);
+    }
+}
+
+// run two linked gameboys side by side, each one in its own window
+fn run_linked(mut link: LinkedEmulators) {
+    let mut buffer = [0; SCREEN_HEIGHT * SCREEN_WIDTH];
+    let mut windows = Vec::new();
+    for index in 0..link.emulators.len() {
+        let mut window = Window::new(
+            &format!("Qoboy - player {}", index + 1),
+            WINDOW_DIMENSIONS[0],
+            WINDOW_DIMENSIONS[1],
+            WindowOptions::default(),
+        )
+        .unwrap();
+        window.set_position((index * (WINDOW_DIMENSIONS[0] + 20)) as isize, 0);
+        windows.push(window);
+    }
+    let key_maps = [PLAYER_1_KEYS, PLAYER_2_KEYS];
+    let mut frames_since_flush = 0;
+
+    while windows.iter().all(|window| window.is_open() && !window.is_key_down(Key::Escape)) {
+        let frame_tick = Instant::now();
+
+        for (index, window) in windows.iter().enumerate() {
+            update_keys(window, &mut link.emulators[index], &key_maps[index]);
         }
 
-        if window.is_key_down(Key::Right) {
-            emulator.set_key(soc::GameBoyKey::RIGHT, true);
-        } else {
-            emulator.set_key(soc::GameBoyKey::RIGHT, false);
+        let results = link.run_frame();
+        for emulator in link.emulators.iter_mut() {
+            print_audio_error(emulator);
         }
 
-        if window.is_key_down(Key::A) {
-            emulator.set_key(soc::GameBoyKey::A, true);
-        } else {
-            emulator.set_key(soc::GameBoyKey::A, false);
+        for (index, (window, emulator)) in windows.iter_mut().zip(link.emulators.iter()).enumerate() {
+            print_faults(&results[index]);
+            show_rumble(window, &format!("Qoboy - player {}", index + 1), results[index].rumble);
+            display_frame(window, emulator, &mut buffer);
         }
 
-        if window.is_key_down(Key::S) {
-            emulator.set_key(soc::GameBoyKey::B, true);
-        } else {
-            emulator.set_key(soc::GameBoyKey::B, false);
+        // periodically persist the game progress
+        frames_since_flush += 1;
+        if frames_since_flush >= SAVE_FILE_FLUSH_PERIOD_IN_FRAMES {
+            frames_since_flush = 0;
+            flush_save_files(&mut link);
         }
 
-        if window.is_key_down(Key::Enter) {
-            emulator.set_key(soc::GameBoyKey::START, true);
-        } else {
-            emulator.set_key(soc::GameBoyKey::START, false);
+        wait_next_frame(frame_tick);
+    }
+
+    // persist the game progress on exit
+    flush_save_files(&mut link);
+}
+
+// run the gameboy linked over the network, the peer drives the pace of the emulation
+fn run_network_linked(mut emulator: Emulator, mut link: NetworkLink) {
+    let mut buffer = [0; SCREEN_HEIGHT * SCREEN_WIDTH];
+    let mut window = Window::new(
+        "Qoboy - link cable",
+        WINDOW_DIMENSIONS[0],
+        WINDOW_DIMENSIONS[1],
+        WindowOptions::default(),
+    )
+    .unwrap();
+    let mut frames_since_flush = 0;
+
+    while window.is_open() && !window.is_key_down(Key::Escape) {
+        let frame_tick = Instant::now();
+
+        update_keys(&window, &mut emulator, &PLAYER_1_KEYS);
+
+        let result = link.run_frame(&mut emulator);
+        print_faults(&result);
+        print_audio_error(&mut emulator);
+
+        show_rumble(&mut window, "Qoboy - link cable", result.rumble);
+        display_frame(&mut window, &emulator, &mut buffer);
+
+        // periodically persist the game progress
+        frames_since_flush += 1;
+        if frames_since_flush >= SAVE_FILE_FLUSH_PERIOD_IN_FRAMES {
+            frames_since_flush = 0;
+            if let Err(message) = emulator.flush_save_file() {
+                println!("Cannot write save file with error message: {}", message);
+            }
         }
 
-        if window.is_key_down(Key::Space) {
-            emulator.set_key(soc::GameBoyKey::SELECT, true);
-        } else {
-            emulator.set_key(soc::GameBoyKey::SELECT, false);
+        wait_next_frame(frame_tick);
+    }
+
+    // persist the game progress on exit
+    if let Err(message) = emulator.flush_save_file() {
+        println!("Cannot write save file with error message: {}", message);
+    }
+}
+
+// run the rom as fast as possible then write the last frame, returns the exit code
+fn run_headless_mode(args: HeadlessArgs) -> i32 {
+    let boot = match load_boot(args.boot_rom_path.as_deref(), args.skip_boot.as_deref()) {
+        Ok(boot) => boot,
+        Err(message) => {
+            println!("{}", message);
+            return EXIT_ERROR;
         }
+    };
 
-        // run emulator until a new frame is ready
-        emulator.run(&mut *dbg_ctx.lock().unwrap());
-
-        if emulator.frame_ready() {
-            // copy the current frame from gpu frame buffer
-            for i in 0..SCREEN_HEIGHT * SCREEN_WIDTH {
-                buffer[i] =  255 << 24
-                            | (emulator.get_frame_buffer(i) as u32) << 16
-                            | (emulator.get_frame_buffer(i) as u32) << 8
-                            | (emulator.get_frame_buffer(i) as u32) << 0;
+    let script = match args.input_path {
+        Some(path) => match InputScript::from_file(&path) {
+            Ok(script) => script,
+            Err(message) => {
+                println!("Cannot read input script with error message: {}", message);
+                return EXIT_ERROR;
+            }
+        },
+        None => InputScript::new(),
+    };
+
+    let mut emulator = match std::fs::read(&args.game_rom_path) {
+        Ok(rom) => match new_emulator(&boot, &rom) {
+            Ok(emulator) => emulator,
+            Err(error) => {
+                println!("Cannot create the emulator with error message: {}", error);
+                return EXIT_ERROR;
+            }
+        },
+        Err(message) => {
+            println!("Cannot read file with error message: {}", message);
+            return EXIT_ERROR;
+        }
+    };
+    emulator.set_fault_policy(FaultPolicy::Halt);
+
+    let status = run_headless_with(&mut emulator, args.frames, &script, |frame, result| {
+        if let Some(rumble) = result.rumble {
+            println!("rumble {} at frame {}", if rumble { "on" } else { "off" }, frame);
+        }
+    });
+
+    // the outputs are written even when the run has failed
+    let png_path = match args.png_path {
+        Some(path) => PathBuf::from(path),
+        None => Path::new(&args.game_rom_path).with_extension("png"),
+    };
+    if let Err(message) = write_frame_png(&png_path, &emulator) {
+        println!("Cannot write frame with error message: {}", message);
+        return EXIT_ERROR;
+    }
+    println!("frame hash: {:016x}", frame_hash(emulator.get_frame()));
+
+    if let Some(dump_path) = args.dump_path {
+        if let Err(message) = std::fs::write(&dump_path, dump_memory(&emulator)) {
+            println!("Cannot write memory dump with error message: {}", message);
+            return EXIT_ERROR;
+        }
+    }
+
+    // compare the last frame with a reference screenshot
+    if let Some(reference_path) = args.reference_path {
+        let diff_path = png_path.with_extension("diff.png");
+        match compare_screenshot(emulator.get_frame(), &reference_path, &ReferencePalette::GRAYSCALE, &diff_path) {
+            Ok(0) => {}
+            Ok(different_pixels) => {
+                println!("{} pixels differ from the reference, see {}", different_pixels, diff_path.display());
+                if status == HeadlessStatus::Completed {
+                    return EXIT_SCREENSHOT_MISMATCH;
+                }
+            }
+            Err(message) => {
+                println!("Cannot compare with the reference with error message: {}", message);
+                return EXIT_ERROR;
             }
-            // display the frame rendered by the gpu
-            window.update_with_buffer(&buffer, SCREEN_WIDTH, SCREEN_HEIGHT).unwrap();
+        }
+    }
+
+    match status {
+        HeadlessStatus::Completed => 0,
+        HeadlessStatus::LockedUp(frame) => {
+            println!("The cpu is locked up since frame {}", frame);
+            EXIT_LOCKUP
+        }
+        HeadlessStatus::Fault(error) => {
+            println!("Emulator fault: {}", error);
+            EXIT_ERROR
         }
     }
 }
 
-fn parse_args() -> (String, String, bool) {
-    let mut boot_rom_path = String::new();
-    let mut game_rom_path = String::new();
-    let mut debug_opt = false;
+// print the content of the cartridge header, returns the exit code
+fn run_info_mode(rom_path: &str) -> i32 {
+    let header = match std::fs::read(rom_path) {
+        Ok(rom) => match CartridgeHeader::parse(&rom) {
+            Ok(header) => header,
+            Err(error) => {
+                println!("Cannot read the cartridge header with error message: {}", error);
+                return EXIT_ERROR;
+            }
+        },
+        Err(message) => {
+            println!("Cannot read file with error message: {}", message);
+            return EXIT_ERROR;
+        }
+    };
+
+    let checksum_status = |valid: bool, expected: String| if valid { String::from("valid") } else { format!("invalid, expected {}", expected) };
+
+    println!("title:             {}", header.title);
+    println!("manufacturer code: {}", header.manufacturer_code.as_deref().unwrap_or("none"));
+    println!("cgb flag:          {:#04x} ({:?})", header.cgb_flag, header.get_cgb_support());
+    println!("sgb support:       {}", if header.sgb_support { "yes" } else { "no" });
+    println!("licensee code:     {}", header.get_licensee_code());
+    println!("destination:       {:?}", header.destination);
+    println!("version:           {}", header.version);
+    println!("cartridge type:    {:#04x} ({})", header.cartridge_type, header.get_mbc_type().map_or(String::from("unknown"), |mbc_type| mbc_type.to_string()));
+    println!("rom size:          {:#04x} ({})", header.rom_size_code, header.get_rom_size().map_or(String::from("unknown"), |rom_size| rom_size.to_string()));
+    println!("ram size:          {:#04x} ({})", header.ram_size_code, header.get_ram_size().map_or(String::from("unknown"), |ram_size| ram_size.to_string()));
+    println!("header checksum:   {:#04x} ({})", header.header_checksum,
+        checksum_status(header.is_header_checksum_valid(), format!("{:#04x}", header.get_expected_header_checksum())));
+    println!("global checksum:   {:#06x} ({})", header.global_checksum,
+        checksum_status(header.is_global_checksum_valid(), format!("{:#06x}", header.get_expected_global_checksum())));
+    for (index, logo_line) in header.logo.chunks(24).enumerate() {
+        let label = if index == 0 { "logo:" } else { "" };
+        let bytes: Vec<String> = logo_line.iter().map(|data| format!("{:02X}", data)).collect();
+        println!("{:<19}{}", label, bytes.join(" "));
+    }
+
+    for warning in header.get_warnings() {
+        println!("warning: {}", warning);
+    }
+
+    0
+}
+
+fn wait_next_frame(frame_tick: Instant) {
+    let frame_duration = Duration::from_nanos(ONE_FRAME_IN_NS as u64);
+    if let Some(remaining_time) = frame_duration.checked_sub(frame_tick.elapsed()) {
+        std::thread::sleep(remaining_time);
+    }
+}
+
+fn flush_save_files(link: &mut LinkedEmulators) {
+    for emulator in link.emulators.iter_mut() {
+        if let Err(message) = emulator.flush_save_file() {
+            println!("Cannot write save file with error message: {}", message);
+        }
+    }
+}
+
+fn update_keys(window: &Window, emulator: &mut Emulator, key_map: &[(Key, GameBoyKey)]) {
+    for (key, gameboy_key) in key_map {
+        emulator.set_key(*gameboy_key, window.is_key_down(*key));
+    }
+}
+
+// the mouse tilts the gameboy by up to 1 g when it reaches the borders of the window,
+// only cartridges with an accelerometer use it
+fn update_tilt(window: &Window, emulator: &mut Emulator) {
+    if let Some((x, y)) = window.get_mouse_pos(MouseMode::Clamp) {
+        let tilt_x = x / WINDOW_DIMENSIONS[0] as f32 * 2.0 - 1.0;
+        let tilt_y = y / WINDOW_DIMENSIONS[1] as f32 * 2.0 - 1.0;
+        emulator.set_tilt(tilt_x, tilt_y);
+    }
+}
+
+// the emulator reports the faults of the emulated program, the executable prints them
+fn print_faults(result: &RunResult) {
+    for error in &result.faults {
+        println!("Emulator fault: {}", error);
+    }
+}
+
+// the emulator stops the audio output on error, the executable prints why
+fn print_audio_error(emulator: &mut Emulator) {
+    if let Some(message) = emulator.take_audio_error() {
+        println!("Cannot output audio with error message: {}", message);
+    }
+}
+
+// no force feedback on a keyboard, the motor state is shown in the title
+fn show_rumble(window: &mut Window, title: &str, rumble: Option<bool>) {
+    match rumble {
+        Some(true) => window.set_title(&format!("{} (rumble)", title)),
+        Some(false) => window.set_title(title),
+        None => {}
+    }
+}
+
+fn display_frame(window: &mut Window, emulator: &Emulator, buffer: &mut [u32]) {
+    // copy the current frame from gpu frame buffer
+    for (i, pixel) in buffer.iter_mut().enumerate() {
+        let shade = emulator.get_frame_buffer(i) as u32;
+        *pixel = 255 << 24 | shade << 16 | shade << 8 | shade;
+    }
+
+    window.update_with_buffer(buffer, SCREEN_WIDTH, SCREEN_HEIGHT).unwrap();
+}
+
+fn create_emulator(boot: &Boot, rom: &[u8]) -> Emulator {
+    match new_emulator(boot, rom) {
+        Ok(emulator) => emulator,
+        Err(error) => {
+            println!("Cannot create the emulator with error message: {}", error);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn new_emulator(boot: &Boot, rom: &[u8]) -> Result<Emulator, EmulatorError> {
+    let emulator = match boot {
+        Boot::Rom(boot_rom) => Emulator::new(boot_rom, rom),
+        Boot::Skip(model) => Emulator::new_without_boot_rom(rom, *model),
+    }?;
+    print_cartridge_summary(emulator.get_cartridge_header());
 
-    for (index, argument) in env::args().enumerate() {
-        match index {
-            1 => {
-                boot_rom_path = argument.clone();
-                println!("boot_rom: {}", boot_rom_path);
+    Ok(emulator)
+}
+
+// the header of a running cartridge is valid, only its warnings can be reported
+fn print_cartridge_summary(header: &CartridgeHeader) {
+    if let (Ok(mbc_type), Ok(rom_size), Ok(ram_size)) = (header.get_mbc_type(), header.get_rom_size(), header.get_ram_size()) {
+        println!("Cartridge with mbc type {}, rom size: {}, ram size: {}", mbc_type, rom_size, ram_size);
+    }
+    for warning in header.get_warnings() {
+        println!("Cartridge header warning: {}", warning);
+    }
+}
+
+// the boot rom file, the model whose boot rom is skipped, or the built-in boot rom
+fn load_boot(boot_rom_path: Option<&str>, skip_boot: Option<&str>) -> Result<Boot, String> {
+    match (skip_boot, boot_rom_path) {
+        (Some(name), _) => GameBoyModel::from_name(name)
+            .map(Boot::Skip)
+            .ok_or_else(|| format!("Unknown gameboy model {}, use dmg0, dmg or mgb", name)),
+        (None, Some(path)) => std::fs::read(path)
+            .map(Boot::Rom)
+            .map_err(|message| format!("Cannot read boot rom with error message: {}", message)),
+        (None, None) => Ok(Boot::Rom(BUILTIN_BOOT_ROM.to_vec())),
+    }
+}
+
+// the emulator restores the battery backed ram, the executable reports it
+fn load_save_file(emulator: &mut Emulator, rom_path: &str) {
+    match emulator.load_save_file(rom_path) {
+        Ok(Some(save_path)) => println!("Cartridge ram loaded from {}", save_path.display()),
+        Ok(None) => {}
+        Err(message) => println!("Cannot load save file with error message: {}", message),
+    }
+}
+
+fn read_rom_file(rom_path: &str) -> Result<Vec<u8>, String> {
+    std::fs::read(rom_path).map_err(|message| format!("Cannot read rom {} with error message: {}", rom_path, message))
+}
+
+fn parse_headless_args() -> Result<HeadlessArgs, String> {
+    let mut game_rom_path = None;
+    let mut boot_rom_path = None;
+    let mut skip_boot = None;
+    let mut frames = None;
+    let mut input_path = None;
+    let mut png_path = None;
+    let mut dump_path = None;
+    let mut reference_path = None;
+
+    // skip the executable and the headless command
+    let mut args = env::args().skip(2);
+    while let Some(argument) = args.next() {
+        match argument.as_str() {
+            "--boot-rom" => boot_rom_path = args.next(),
+            "--skip-boot" => skip_boot = args.next(),
+            "--frames" => {
+                let count = args.next().ok_or("missing frame count after --frames")?;
+                frames = Some(count.parse().map_err(|_| format!("invalid frame count: {}", count))?);
             }
-            2 => {
-                game_rom_path = argument.clone();
-                println!("game_rom: {}", game_rom_path);
+            "--input" => input_path = args.next(),
+            "--png" => png_path = args.next(),
+            "--dump" => dump_path = args.next(),
+            "--reference" => reference_path = args.next(),
+            _ if argument.starts_with("--") => return Err(format!("unknown option: {}", argument)),
+            _ => game_rom_path = Some(argument),
+        }
+    }
+
+    let game_rom_path = game_rom_path.ok_or("missing game rom path")?;
+    let frames = frames.ok_or("missing --frames")?;
+
+    Ok(HeadlessArgs {
+        game_rom_path,
+        boot_rom_path,
+        skip_boot,
+        frames,
+        input_path,
+        png_path,
+        dump_path,
+        reference_path,
+    })
+}
+
+fn parse_args() -> Result<Args, String> {
+    let mut rom_paths = Vec::new();
+    let mut skip_boot = None;
+    let mut debug_opt = false;
+    let mut wav_path = None;
+    let mut link_rom_path = None;
+    let mut link_bind_address = String::from(DEFAULT_LINK_BIND_ADDRESS);
+    let mut link_listen_port = None;
+    let mut link_connect_address = None;
+    let mut printer_dir = None;
+    let mut rtc_offset = 0;
+
+    let mut args = env::args().skip(1);
+    while let Some(argument) = args.next() {
+        match argument.as_str() {
+            "--skip-boot" => skip_boot = args.next(),
+            "--debug" => debug_opt = true,
+            "--wav" => wav_path = args.next(),
+            "--link" => link_rom_path = args.next(),
+            "--link-bind" => link_bind_address = args.next().ok_or("missing address after --link-bind")?,
+            "--link-listen" => {
+                let port = args.next().ok_or("missing port after --link-listen")?;
+                link_listen_port = Some(port.parse().map_err(|_| format!("invalid port: {}", port))?);
             }
-            3 => if argument.eq("--debug") {
-                    debug_opt = true;
+            "--link-connect" => link_connect_address = Some(args.next().ok_or("missing address after --link-connect")?),
+            "--printer" => printer_dir = args.next(),
+            "--rtc-offset" => {
+                let seconds = args.next().ok_or("missing seconds after --rtc-offset")?;
+                rtc_offset = seconds.parse().map_err(|_| format!("invalid rtc offset: {}", seconds))?;
             }
-            _ => {} // nothing to do
+            _ => rom_paths.push(argument),
         }
     }
 
-    (boot_rom_path, game_rom_path, debug_opt)
-}
\ No newline at end of file
+    // without boot rom path, the built-in boot rom is used
+    let game_rom_path = rom_paths.pop().unwrap_or_default();
+    let boot_rom_path = rom_paths.pop();
+    if let Some(boot_rom_path) = &boot_rom_path {
+        println!("boot_rom: {}", boot_rom_path);
+    }
+    println!("game_rom: {}", game_rom_path);
+
+    Ok(Args {
+        boot_rom_path,
+        skip_boot,
+        game_rom_path,
+        debug_mode: debug_opt,
+        wav_path,
+        link_rom_path,
+        link_bind_address,
+        link_listen_port,
+        link_connect_address,
+        printer_dir,
+        rtc_offset,
+    })
+}
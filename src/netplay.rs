@@ -0,0 +1,356 @@
+use crate::emulator::{Emulator, RunResult, ONE_FRAME_IN_CYCLES};
+use crate::soc::peripheral::serial::{SerialDevice, SERIAL_DISCONNECTED_BYTE};
+use std::io::{BufReader, Error, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+// handshake sent by both peers when the connection is established
+const LINK_MAGIC: [u8; 4] = *b"QOBL";
+const LINK_VERSION: u8 = 2;
+
+// a peer which doesn't answer in time is considered gone
+const DEFAULT_LINK_TIMEOUT: Duration = Duration::from_secs(5);
+
+// both emulators exchange their serial events every 4096 cycles (one serial byte),
+// the events of a slice are applied by the peer at the beginning of its next slice
+const SYNC_PERIOD_IN_CYCLES: usize = 4096;
+
+// messages are 2 bytes long: the message type then its data
+const MESSAGE_SYNC: u8 = 0x00;
+const MESSAGE_WAIT: u8 = 0x01;
+const MESSAGE_TRANSFER: u8 = 0x02;
+const MESSAGE_CANCEL: u8 = 0x03;
+
+// serial state shared by the link and its serial port
+struct NetworkState {
+    connected: bool,
+    // byte of the peer waiting for our clock
+    peer_waiting: Option<u8>,
+    // byte sent by the peer clock
+    received: Option<u8>,
+    // byte announced to the peer while we wait for its clock
+    announced: Option<u8>,
+    // messages to send at the end of the current slice
+    outgoing: Vec<u8>,
+}
+
+// serial port plugged on a tcp link
+pub struct NetworkPort {
+    state: Arc<Mutex<NetworkState>>,
+}
+
+impl SerialDevice for NetworkPort {
+    fn transfer(&mut self, data: u8) -> u8 {
+        let mut state = self.state.lock().unwrap();
+
+        match state.peer_waiting.take() {
+            Some(peer_data) if state.connected => {
+                state.outgoing.extend_from_slice(&[MESSAGE_TRANSFER, data]);
+                peer_data
+            }
+            _ => SERIAL_DISCONNECTED_BYTE,
+        }
+    }
+
+    fn poll_external(&mut self, data: u8) -> Option<u8> {
+        let mut state = self.state.lock().unwrap();
+
+        if let Some(received_data) = state.received.take() {
+            state.announced = None;
+            return Some(received_data);
+        }
+
+        // tell the peer we are ready to receive its clock
+        if state.connected && state.announced != Some(data) {
+            state.outgoing.extend_from_slice(&[MESSAGE_WAIT, data]);
+            state.announced = Some(data);
+        }
+
+        None
+    }
+
+    fn cancel_external(&mut self) {
+        let mut state = self.state.lock().unwrap();
+
+        // tell the peer our byte is not available anymore
+        if state.connected && state.announced.is_some() {
+            state.outgoing.extend_from_slice(&[MESSAGE_CANCEL, 0x00]);
+        }
+        state.announced = None;
+        state.received = None;
+    }
+}
+
+// link cable over a tcp connection, both emulators run in lockstep
+pub struct NetworkLink {
+    writer: TcpStream,
+    reader: BufReader<TcpStream>,
+    state: Arc<Mutex<NetworkState>>,
+    frame_cycles: usize,
+    slice_cycles: usize,
+}
+
+impl NetworkLink {
+    // wait for a peer to connect on the given address and port
+    pub fn listen(bind_address: &str, port: u16) -> std::io::Result<NetworkLink> {
+        let listener = TcpListener::bind((bind_address, port))?;
+        let (stream, _) = listener.accept()?;
+
+        NetworkLink::from_stream(stream)
+    }
+
+    // connect to a peer listening at host:port
+    pub fn connect(address: &str) -> std::io::Result<NetworkLink> {
+        NetworkLink::from_stream(TcpStream::connect(address)?)
+    }
+
+    pub fn from_stream(stream: TcpStream) -> std::io::Result<NetworkLink> {
+        // messages are small and sent on every slice
+        stream.set_nodelay(true)?;
+        stream.set_read_timeout(Some(DEFAULT_LINK_TIMEOUT))?;
+        stream.set_write_timeout(Some(DEFAULT_LINK_TIMEOUT))?;
+
+        let mut writer = stream.try_clone()?;
+        let mut reader = BufReader::new(stream);
+
+        writer.write_all(&LINK_MAGIC)?;
+        writer.write_all(&[LINK_VERSION])?;
+
+        let mut handshake = [0; 5];
+        reader.read_exact(&mut handshake)?;
+        if handshake[0..4] != LINK_MAGIC || handshake[4] != LINK_VERSION {
+            return Err(Error::new(ErrorKind::InvalidData, "peer is not a compatible qoboy link"));
+        }
+
+        Ok(NetworkLink {
+            writer,
+            reader,
+            state: Arc::new(Mutex::new(NetworkState {
+                connected: true,
+                peer_waiting: None,
+                received: None,
+                announced: None,
+                outgoing: Vec::new(),
+            })),
+            frame_cycles: 0,
+            slice_cycles: 0,
+        })
+    }
+
+    // serial device to plug in the emulator
+    pub fn get_port(&self) -> NetworkPort {
+        NetworkPort {
+            state: Arc::clone(&self.state),
+        }
+    }
+
+    // the link is unplugged when the peer doesn't answer within the timeout
+    pub fn set_timeout(&self, timeout: Duration) -> std::io::Result<()> {
+        // the reader shares the socket of the writer
+        self.writer.set_read_timeout(Some(timeout))?;
+        self.writer.set_write_timeout(Some(timeout))
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.state.lock().unwrap().connected
+    }
+
+    // run the emulator for one frame, synchronized with the peer at each slice
+    pub fn run_frame(&mut self, emulator: &mut Emulator) -> RunResult {
+        let mut frame = RunResult::default();
+        while self.frame_cycles < ONE_FRAME_IN_CYCLES {
+            let result = emulator.step_instruction();
+            self.frame_cycles += result.cycles;
+            self.slice_cycles += result.cycles;
+            frame.add(result);
+
+            if self.slice_cycles >= SYNC_PERIOD_IN_CYCLES {
+                self.slice_cycles -= SYNC_PERIOD_IN_CYCLES;
+                self.synchronize();
+            }
+        }
+
+        self.frame_cycles -= ONE_FRAME_IN_CYCLES;
+        emulator.flush_audio_samples();
+
+        frame
+    }
+
+    fn synchronize(&mut self) {
+        if !self.is_connected() {
+            return;
+        }
+
+        if let Err(error) = self.exchange_messages() {
+            // keep running alone, the cable is now unplugged
+            match error.kind() {
+                ErrorKind::WouldBlock | ErrorKind::TimedOut => println!("Link cable disconnected: the peer has not answered in time"),
+                _ => println!("Link cable disconnected with error message: {}", error),
+            }
+            let mut state = self.state.lock().unwrap();
+            state.connected = false;
+            state.peer_waiting = None;
+            state.received = None;
+            state.outgoing.clear();
+        }
+    }
+
+    // send the events of our slice then apply the events of the peer slice
+    fn exchange_messages(&mut self) -> std::io::Result<()> {
+        let mut outgoing = std::mem::take(&mut self.state.lock().unwrap().outgoing);
+        outgoing.extend_from_slice(&[MESSAGE_SYNC, 0x00]);
+        self.writer.write_all(&outgoing)?;
+
+        loop {
+            let mut message = [0; 2];
+            self.reader.read_exact(&mut message)?;
+
+            let mut state = self.state.lock().unwrap();
+            match message[0] {
+                MESSAGE_SYNC => return Ok(()),
+                MESSAGE_WAIT => state.peer_waiting = Some(message[1]),
+                MESSAGE_CANCEL => state.peer_waiting = None,
+                // a transfer which has crossed our cancel message is dropped
+                MESSAGE_TRANSFER if state.announced.is_some() => state.received = Some(message[1]),
+                MESSAGE_TRANSFER => (),
+                _ => return Err(Error::new(ErrorKind::InvalidData, "unknown link message")),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod netplay_tests {
+    use super::*;
+    use crate::soc::peripheral::IoAccess;
+    use crate::cartridge::{CARTRIDGE_TYPE_OFFSET, CARTRIDGE_RAM_SIZE_OFFSET, CARTRIDGE_ROM_SIZE_OFFSET};
+    use std::thread;
+
+    fn build_emulator(program: &[u8]) -> Emulator {
+        let mut boot_rom = [0x00; 256];
+        boot_rom[0..program.len()].copy_from_slice(program);
+        let mut rom = [0xFF; 0x8000];
+        rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
+        rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
+        rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
+
+        Emulator::new(&boot_rom, &rom).unwrap()
+    }
+
+    #[test]
+    fn test_network_transfer() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+
+        // the slave waits for the clock of the master
+        let slave = thread::spawn(move || {
+            // ld a,0x5A / ldh (SB),a / ld a,0x80 / ldh (SC),a / jr -2
+            let mut emulator = build_emulator(&[0x3E, 0x5A, 0xE0, 0x01, 0x3E, 0x80, 0xE0, 0x02, 0x18, 0xFE]);
+            let mut link = NetworkLink::connect(&address.to_string()).unwrap();
+            emulator.set_serial_device(Box::new(link.get_port()));
+            for _ in 0..2 {
+                link.run_frame(&mut emulator);
+            }
+            (emulator.soc.peripheral.read(0xFF01), emulator.soc.peripheral.read(0xFF02))
+        });
+
+        // ld a,0xA5 / ldh (SB),a / jr -2
+        let mut emulator = build_emulator(&[0x3E, 0xA5, 0xE0, 0x01, 0x18, 0xFE]);
+        let mut link = NetworkLink::from_stream(listener.accept().unwrap().0).unwrap();
+        emulator.set_serial_device(Box::new(link.get_port()));
+
+        // the slave has announced it waits after the first slice, the master starts its
+        // transfer once the whole first frame has been exchanged
+        link.run_frame(&mut emulator);
+        emulator.soc.peripheral.write(0xFF02, 0x81);
+        link.run_frame(&mut emulator);
+        assert!(link.is_connected());
+
+        assert_eq!(emulator.soc.peripheral.read(0xFF01), 0x5A);
+        assert_eq!(emulator.soc.peripheral.read(0xFF02), 0x7F);
+        assert_eq!(slave.join().unwrap(), (0xA5, 0x7E));
+    }
+
+    #[test]
+    fn test_network_aborted_transfer() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+
+        // the slave stops waiting right after it has started
+        let slave = thread::spawn(move || {
+            // ld a,0x5A / ldh (SB),a / ld a,0x80 / ldh (SC),a / ld a,0x00 / ldh (SC),a / jr -2
+            let mut emulator = build_emulator(&[0x3E, 0x5A, 0xE0, 0x01, 0x3E, 0x80, 0xE0, 0x02, 0x3E, 0x00, 0xE0, 0x02, 0x18, 0xFE]);
+            let mut link = NetworkLink::connect(&address.to_string()).unwrap();
+            emulator.set_serial_device(Box::new(link.get_port()));
+            for _ in 0..2 {
+                link.run_frame(&mut emulator);
+            }
+            (emulator.soc.peripheral.read(0xFF01), emulator.soc.peripheral.read(0xFF02))
+        });
+
+        // ld a,0xA5 / ldh (SB),a / jr -2
+        let mut emulator = build_emulator(&[0x3E, 0xA5, 0xE0, 0x01, 0x18, 0xFE]);
+        let mut link = NetworkLink::from_stream(listener.accept().unwrap().0).unwrap();
+        emulator.set_serial_device(Box::new(link.get_port()));
+
+        // the master starts its transfer once the abort has been exchanged
+        link.run_frame(&mut emulator);
+        emulator.soc.peripheral.write(0xFF02, 0x81);
+        link.run_frame(&mut emulator);
+        assert!(link.is_connected());
+
+        // the stale byte of the slave is not exchanged
+        assert_eq!(emulator.soc.peripheral.read(0xFF01), SERIAL_DISCONNECTED_BYTE);
+        assert_eq!(emulator.soc.peripheral.read(0xFF02), 0x7F);
+        assert_eq!(slave.join().unwrap(), (0x5A, 0x7E));
+    }
+
+    #[test]
+    fn test_peer_disconnection() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+
+        // the peer leaves right after the handshake
+        let peer = thread::spawn(move || {
+            NetworkLink::connect(&address.to_string()).unwrap();
+        });
+
+        let mut emulator = build_emulator(&[0x3E, 0xA5, 0xE0, 0x01, 0x3E, 0x81, 0xE0, 0x02, 0x18, 0xFE]);
+        let mut link = NetworkLink::from_stream(listener.accept().unwrap().0).unwrap();
+        emulator.set_serial_device(Box::new(link.get_port()));
+        peer.join().unwrap();
+
+        // the emulator keeps running without the cable
+        link.run_frame(&mut emulator);
+        assert!(!link.is_connected());
+        assert_eq!(emulator.soc.peripheral.read(0xFF01), SERIAL_DISCONNECTED_BYTE);
+        assert_eq!(emulator.soc.peripheral.read(0xFF02), 0x7F);
+    }
+
+    #[test]
+    fn test_peer_timeout() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let (done_sender, done_receiver) = std::sync::mpsc::channel::<()>();
+
+        // the peer stays connected but stops answering after the handshake
+        let peer = thread::spawn(move || {
+            let _link = NetworkLink::connect(&address.to_string()).unwrap();
+            let _ = done_receiver.recv();
+        });
+
+        let mut emulator = build_emulator(&[0x3E, 0xA5, 0xE0, 0x01, 0x3E, 0x81, 0xE0, 0x02, 0x18, 0xFE]);
+        let mut link = NetworkLink::from_stream(listener.accept().unwrap().0).unwrap();
+        link.set_timeout(Duration::from_millis(100)).unwrap();
+        emulator.set_serial_device(Box::new(link.get_port()));
+
+        // the silent peer is unplugged and the emulator keeps running alone
+        link.run_frame(&mut emulator);
+        assert!(!link.is_connected());
+        assert_eq!(emulator.soc.peripheral.read(0xFF01), SERIAL_DISCONNECTED_BYTE);
+
+        drop(done_sender);
+        peer.join().unwrap();
+    }
+}
@@ -0,0 +1,372 @@
+use crate::image::{write_grayscale_png, GRAY_SHADES};
+use crate::soc::peripheral::serial::SerialDevice;
+use std::path::PathBuf;
+
+// packet format: magic bytes, command, compression, data length, data, checksum,
+// then the printer answers with its device id and its status
+const PRINTER_MAGIC: [u8; 2] = [0x88, 0x33];
+const PRINTER_DEVICE_ID: u8 = 0x81;
+
+const COMMAND_INIT: u8 = 0x01;
+const COMMAND_PRINT: u8 = 0x02;
+const COMMAND_DATA: u8 = 0x04;
+const COMMAND_STATUS: u8 = 0x0F;
+
+// status bits
+const STATUS_CHECKSUM_ERROR: u8 = 0x01;
+const STATUS_PRINTING: u8 = 0x02;
+const STATUS_IMAGE_FULL: u8 = 0x04;
+const STATUS_UNPROCESSED_DATA: u8 = 0x08;
+
+// the printer stays busy for a few status requests after a print command
+const PRINTING_STATUS_COUNT: u8 = 4;
+
+// a band is 2 rows of 20 tiles, the printer memory holds 9 bands
+const PAGE_WIDTH: usize = 160;
+const BAND_SIZE: usize = 640;
+const BAND_HEIGHT: usize = 16;
+const TILES_PER_ROW: usize = PAGE_WIDTH / 8;
+const IMAGE_BUFFER_SIZE: usize = 9 * BAND_SIZE;
+
+// height of one paper feed of the margins
+const FEED_HEIGHT: usize = 16;
+
+#[derive(Copy, Clone, PartialEq)]
+enum PacketState {
+    Magic0,
+    Magic1,
+    Command,
+    Compression,
+    LengthLow,
+    LengthHigh,
+    Data,
+    ChecksumLow,
+    ChecksumHigh,
+    DeviceId,
+    Status,
+}
+
+// game boy printer plugged on the serial port, each printed page is written in a png file
+pub struct Printer {
+    output_dir: PathBuf,
+    // packet being received
+    state: PacketState,
+    command: u8,
+    compressed: bool,
+    length: usize,
+    data: Vec<u8>,
+    checksum: u16,
+    received_checksum: u16,
+    // printer state
+    status: u8,
+    printing_count: u8,
+    image: Vec<u8>,
+    page: Vec<u8>,
+    page_count: usize,
+}
+
+impl Printer {
+    pub fn new(output_dir: PathBuf) -> Printer {
+        Printer {
+            output_dir,
+            state: PacketState::Magic0,
+            command: 0,
+            compressed: false,
+            length: 0,
+            data: Vec::new(),
+            checksum: 0,
+            received_checksum: 0,
+            status: 0,
+            printing_count: 0,
+            image: Vec::new(),
+            page: Vec::new(),
+            page_count: 0,
+        }
+    }
+
+    fn receive(&mut self, data: u8) -> u8 {
+        match self.state {
+            PacketState::Magic0 => {
+                if data == PRINTER_MAGIC[0] {
+                    self.state = PacketState::Magic1;
+                }
+            }
+            PacketState::Magic1 => {
+                self.state = if data == PRINTER_MAGIC[1] {
+                    PacketState::Command
+                } else {
+                    PacketState::Magic0
+                };
+            }
+            PacketState::Command => {
+                self.command = data;
+                self.checksum = data as u16;
+                self.state = PacketState::Compression;
+            }
+            PacketState::Compression => {
+                self.compressed = data & 0x01 != 0;
+                self.checksum = self.checksum.wrapping_add(data as u16);
+                self.state = PacketState::LengthLow;
+            }
+            PacketState::LengthLow => {
+                self.length = data as usize;
+                self.checksum = self.checksum.wrapping_add(data as u16);
+                self.state = PacketState::LengthHigh;
+            }
+            PacketState::LengthHigh => {
+                self.length |= (data as usize) << 8;
+                self.checksum = self.checksum.wrapping_add(data as u16);
+                self.data.clear();
+                self.state = if self.length == 0 {
+                    PacketState::ChecksumLow
+                } else {
+                    PacketState::Data
+                };
+            }
+            PacketState::Data => {
+                self.data.push(data);
+                self.checksum = self.checksum.wrapping_add(data as u16);
+                if self.data.len() == self.length {
+                    self.state = PacketState::ChecksumLow;
+                }
+            }
+            PacketState::ChecksumLow => {
+                self.received_checksum = data as u16;
+                self.state = PacketState::ChecksumHigh;
+            }
+            PacketState::ChecksumHigh => {
+                self.received_checksum |= (data as u16) << 8;
+                self.state = PacketState::DeviceId;
+            }
+            PacketState::DeviceId => {
+                self.state = PacketState::Status;
+                return PRINTER_DEVICE_ID;
+            }
+            PacketState::Status => {
+                self.execute_command();
+                self.state = PacketState::Magic0;
+                return self.status;
+            }
+        }
+
+        0x00
+    }
+
+    fn execute_command(&mut self) {
+        if self.checksum != self.received_checksum {
+            self.status |= STATUS_CHECKSUM_ERROR;
+            return;
+        }
+        self.status &= !STATUS_CHECKSUM_ERROR;
+
+        match self.command {
+            COMMAND_INIT => {
+                self.image.clear();
+                self.status = 0;
+                self.printing_count = 0;
+            }
+            COMMAND_DATA => {
+                let data = if self.compressed {
+                    decompress(&self.data)
+                } else {
+                    self.data.clone()
+                };
+
+                let free_size = IMAGE_BUFFER_SIZE - self.image.len();
+                self.image.extend_from_slice(&data[..data.len().min(free_size)]);
+
+                if !self.image.is_empty() {
+                    self.status |= STATUS_UNPROCESSED_DATA;
+                }
+                if self.image.len() == IMAGE_BUFFER_SIZE {
+                    self.status |= STATUS_IMAGE_FULL;
+                }
+            }
+            COMMAND_PRINT if self.data.len() >= 4 => {
+                // sheets count, margins, palette, exposure
+                self.print(self.data[1], self.data[2]);
+            }
+            COMMAND_STATUS if self.printing_count > 0 => {
+                self.printing_count -= 1;
+                if self.printing_count == 0 {
+                    self.status &= !STATUS_PRINTING;
+                }
+            }
+            _ => {} // unknown commands are ignored
+        }
+    }
+
+    fn print(&mut self, margins: u8, palette: u8) {
+        // the upper nibble is the number of feeds before the image and the lower one after
+        let margin_before = (margins >> 4) as usize;
+        let margin_after = (margins & 0x0F) as usize;
+
+        // 0x00 is used by some games for the default palette
+        let palette = if palette == 0x00 { 0xE4 } else { palette };
+
+        self.page.resize(self.page.len() + margin_before * FEED_HEIGHT * PAGE_WIDTH, GRAY_SHADES[0]);
+        for band in self.image.chunks_exact(BAND_SIZE) {
+            for y in 0..BAND_HEIGHT {
+                for x in 0..PAGE_WIDTH {
+                    let tile_index = (y / 8) * TILES_PER_ROW + x / 8;
+                    let offset = tile_index * 16 + (y % 8) * 2;
+                    let bit = 7 - (x % 8);
+                    let color = ((band[offset + 1] >> bit) & 0x01) << 1 | ((band[offset] >> bit) & 0x01);
+                    let shade = (palette >> (color * 2)) & 0x03;
+                    self.page.push(GRAY_SHADES[shade as usize]);
+                }
+            }
+        }
+        self.page.resize(self.page.len() + margin_after * FEED_HEIGHT * PAGE_WIDTH, GRAY_SHADES[0]);
+
+        self.image.clear();
+        self.status = (self.status & !(STATUS_UNPROCESSED_DATA | STATUS_IMAGE_FULL)) | STATUS_PRINTING;
+        self.printing_count = PRINTING_STATUS_COUNT;
+
+        // images printed without margin after them are continued on the same page
+        if margin_after > 0 {
+            self.write_page();
+        }
+    }
+
+    fn write_page(&mut self) {
+        if self.page.is_empty() {
+            return;
+        }
+
+        self.page_count += 1;
+        let page_path = self.output_dir.join(format!("print_{:03}.png", self.page_count));
+        let height = self.page.len() / PAGE_WIDTH;
+        match write_grayscale_png(&page_path, PAGE_WIDTH, height, &self.page) {
+            Ok(()) => println!("Page printed in {}", page_path.display()),
+            Err(message) => println!("Cannot write printed page with error message: {}", message),
+        }
+
+        self.page.clear();
+    }
+}
+
+impl SerialDevice for Printer {
+    fn transfer(&mut self, data: u8) -> u8 {
+        self.receive(data)
+    }
+
+    fn poll_external(&mut self, _data: u8) -> Option<u8> {
+        // the printer never drives the clock
+        None
+    }
+}
+
+impl Drop for Printer {
+    fn drop(&mut self) {
+        // output the last page even if its bottom margin has not been printed
+        self.write_page();
+    }
+}
+
+// run length encoding used by the data packets: a control byte with bit 7 set is
+// followed by a byte repeated (n & 0x7F) + 2 times, otherwise by n + 1 raw bytes
+fn decompress(data: &[u8]) -> Vec<u8> {
+    let mut output = Vec::new();
+    let mut index = 0;
+
+    while index < data.len() {
+        let control = data[index];
+        index += 1;
+
+        if control & 0x80 != 0 {
+            if let Some(&value) = data.get(index) {
+                output.resize(output.len() + (control & 0x7F) as usize + 2, value);
+            }
+            index += 1;
+        } else {
+            let end = (index + control as usize + 1).min(data.len());
+            output.extend_from_slice(&data[index..end]);
+            index = end;
+        }
+    }
+
+    output
+}
+
+#[cfg(test)]
+mod printer_tests {
+    use super::*;
+
+    fn send_packet(printer: &mut Printer, command: u8, compression: u8, data: &[u8]) -> Vec<u8> {
+        let mut packet = vec![PRINTER_MAGIC[0], PRINTER_MAGIC[1], command, compression, data.len() as u8, (data.len() >> 8) as u8];
+        packet.extend_from_slice(data);
+        let checksum = packet[2..].iter().fold(0u16, |sum, &byte| sum.wrapping_add(byte as u16));
+        packet.extend_from_slice(&[checksum as u8, (checksum >> 8) as u8, 0x00, 0x00]);
+
+        packet.iter().map(|&byte| printer.transfer(byte)).collect()
+    }
+
+    #[test]
+    fn test_status() {
+        let mut printer = Printer::new(std::env::temp_dir());
+
+        let response = send_packet(&mut printer, COMMAND_INIT, 0, &[]);
+        assert_eq!(response[..8], [0x00; 8]);
+        assert_eq!(response[8..], [PRINTER_DEVICE_ID, 0x00]);
+
+        // data waiting to be printed
+        let response = send_packet(&mut printer, COMMAND_DATA, 0, &[0x00; BAND_SIZE]);
+        assert_eq!(response[response.len() - 1], STATUS_UNPROCESSED_DATA);
+
+        // wrong checksum
+        let response: Vec<u8> = [0x88, 0x33, 0x0F, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]
+            .iter()
+            .map(|&byte| printer.transfer(byte))
+            .collect();
+        assert_eq!(response[9], STATUS_UNPROCESSED_DATA | STATUS_CHECKSUM_ERROR);
+    }
+
+    #[test]
+    fn test_decompress() {
+        assert_eq!(decompress(&[0x81, 0xAA, 0x01, 0x12, 0x34]), vec![0xAA, 0xAA, 0xAA, 0x12, 0x34]);
+        assert_eq!(decompress(&[0x80, 0x55]), vec![0x55, 0x55]);
+    }
+
+    #[test]
+    fn test_print_page() {
+        let output_dir = std::env::temp_dir().join("qoboy_test_printer");
+        std::fs::create_dir_all(&output_dir).unwrap();
+        let mut printer = Printer::new(output_dir.clone());
+
+        // first band is black, second band is compressed and uses the light gray shade
+        let mut compressed_band = Vec::new();
+        for _ in 0..5 {
+            compressed_band.push(0x7F);
+            for _ in 0..64 {
+                compressed_band.extend_from_slice(&[0xFF, 0x00]);
+            }
+        }
+        send_packet(&mut printer, COMMAND_INIT, 0, &[]);
+        send_packet(&mut printer, COMMAND_DATA, 0, &[0xFF; BAND_SIZE]);
+        send_packet(&mut printer, COMMAND_DATA, 1, &compressed_band);
+        let response = send_packet(&mut printer, COMMAND_PRINT, 0, &[0x01, 0x01, 0xE4, 0x40]);
+        assert_eq!(response[response.len() - 1], STATUS_PRINTING);
+
+        // the printer is busy for a few status requests
+        for _ in 0..PRINTING_STATUS_COUNT - 1 {
+            let response = send_packet(&mut printer, COMMAND_STATUS, 0, &[]);
+            assert_eq!(response[response.len() - 1], STATUS_PRINTING);
+        }
+        let response = send_packet(&mut printer, COMMAND_STATUS, 0, &[]);
+        assert_eq!(response[response.len() - 1], 0x00);
+
+        let page_path = output_dir.join("print_001.png");
+        let decoder = png::Decoder::new(std::fs::File::open(&page_path).unwrap());
+        let mut reader = decoder.read_info().unwrap();
+        let mut page = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut page).unwrap();
+        std::fs::remove_dir_all(&output_dir).unwrap();
+
+        // 2 bands followed by the bottom margin
+        assert_eq!((info.width, info.height), (160, 2 * 16 + 16));
+        assert!(page[..160 * 16].iter().all(|&pixel| pixel == GRAY_SHADES[3]));
+        assert!(page[160 * 16..160 * 16 * 2].iter().all(|&pixel| pixel == GRAY_SHADES[1]));
+        assert!(page[160 * 16 * 2..].iter().all(|&pixel| pixel == GRAY_SHADES[0]));
+    }
+}
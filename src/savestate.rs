@@ -0,0 +1,176 @@
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+
+// save state file header
+pub const SAVE_STATE_MAGIC: [u8; 4] = *b"QOBS";
+pub const SAVE_STATE_VERSION: u16 = 6;
+
+pub trait SaveState {
+    fn save_state(&self, writer: &mut StateWriter);
+
+    fn load_state(&mut self, reader: &mut StateReader) -> std::io::Result<()>;
+}
+
+// all values are stored in little endian
+pub struct StateWriter {
+    data: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn new() -> StateWriter {
+        StateWriter {
+            data: Vec::new(),
+        }
+    }
+
+    pub fn write_u8(&mut self, value: u8) {
+        self.data.push(value);
+    }
+
+    pub fn write_bool(&mut self, value: bool) {
+        self.data.push(value as u8);
+    }
+
+    pub fn write_u16(&mut self, value: u16) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_u32(&mut self, value: u32) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_u64(&mut self, value: u64) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    // variable length data is prefixed with its length
+    pub fn write_bytes(&mut self, value: &[u8]) {
+        self.write_u32(value.len() as u32);
+        self.data.extend_from_slice(value);
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+pub struct StateReader<'a> {
+    data: &'a [u8],
+    index: usize,
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(data: &'a [u8]) -> StateReader<'a> {
+        StateReader {
+            data,
+            index: 0,
+        }
+    }
+
+    fn take(&mut self, size: usize) -> std::io::Result<&'a [u8]> {
+        if self.index + size > self.data.len() {
+            return Err(Error::new(ErrorKind::UnexpectedEof, "save state is truncated"));
+        }
+
+        let data = &self.data[self.index..self.index + size];
+        self.index += size;
+        Ok(data)
+    }
+
+    pub fn read_u8(&mut self) -> std::io::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn read_bool(&mut self) -> std::io::Result<bool> {
+        Ok(self.read_u8()? != 0)
+    }
+
+    pub fn read_u16(&mut self) -> std::io::Result<u16> {
+        let mut bytes = [0; 2];
+        bytes.copy_from_slice(self.take(2)?);
+        Ok(u16::from_le_bytes(bytes))
+    }
+
+    pub fn read_u32(&mut self) -> std::io::Result<u32> {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(self.take(4)?);
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    pub fn read_u64(&mut self) -> std::io::Result<u64> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    pub fn read_bytes(&mut self) -> std::io::Result<&'a [u8]> {
+        let size = self.read_u32()? as usize;
+        self.take(size)
+    }
+
+    // read variable length data into a buffer which shall have the same size
+    pub fn read_bytes_into(&mut self, buffer: &mut [u8]) -> std::io::Result<()> {
+        let data = self.read_bytes()?;
+        if data.len() != buffer.len() {
+            return Err(invalid_state("memory size mismatch"));
+        }
+
+        buffer.copy_from_slice(data);
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.index == self.data.len()
+    }
+}
+
+pub fn invalid_state(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("invalid save state: {}", message))
+}
+
+// save states are stored next to the rom file, one file per slot
+pub fn get_state_path(rom_path: &str, slot: usize) -> PathBuf {
+    Path::new(rom_path).with_extension(format!("ss{}", slot))
+}
+
+#[cfg(test)]
+mod savestate_tests {
+    use super::*;
+
+    #[test]
+    fn test_write_read() {
+        let mut writer = StateWriter::new();
+        writer.write_u8(0xA5);
+        writer.write_bool(true);
+        writer.write_u16(0x1234);
+        writer.write_u32(0xDEADBEEF);
+        writer.write_u64(0x0123456789ABCDEF);
+        writer.write_bytes(&[0x01, 0x02, 0x03]);
+        let data = writer.into_bytes();
+
+        let mut reader = StateReader::new(&data);
+        assert_eq!(reader.read_u8().unwrap(), 0xA5);
+        assert!(reader.read_bool().unwrap());
+        assert_eq!(reader.read_u16().unwrap(), 0x1234);
+        assert_eq!(reader.read_u32().unwrap(), 0xDEADBEEF);
+        assert_eq!(reader.read_u64().unwrap(), 0x0123456789ABCDEF);
+        let mut buffer = [0; 3];
+        reader.read_bytes_into(&mut buffer).unwrap();
+        assert_eq!(buffer, [0x01, 0x02, 0x03]);
+        assert!(reader.is_empty());
+
+        // reading past the end of the state is an error
+        assert!(reader.read_u8().is_err());
+    }
+
+    #[test]
+    fn test_size_mismatch() {
+        let mut writer = StateWriter::new();
+        writer.write_bytes(&[0x01, 0x02, 0x03]);
+        let data = writer.into_bytes();
+
+        let mut reader = StateReader::new(&data);
+        let mut buffer = [0; 4];
+        assert!(reader.read_bytes_into(&mut buffer).is_err());
+    }
+}
@@ -0,0 +1,109 @@
+use crate::emulator::{PixelColor, SCREEN_HEIGHT, SCREEN_WIDTH};
+use crate::image::{read_rgb_png, write_rgb_png, GRAY_SHADES};
+use crate::soc::peripheral::gpu::color_to_index;
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+
+// colors of the diff image
+const DIFF_COLOR: [u8; 3] = [0xFF, 0x00, 0x00];
+const UNKNOWN_SHADE_COLOR: [u8; 3] = [0xFF, 0x00, 0xFF];
+
+/// Colors of the four gameboy shades in the reference images.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct ReferencePalette {
+    /// Red, green and blue components of white, light gray, dark gray and black.
+    pub colors: [[u8; 3]; 4],
+}
+
+impl ReferencePalette {
+    /// Gray levels of the dmg-acid2 and mooneye reference images.
+    pub const GRAYSCALE: ReferencePalette = ReferencePalette {
+        colors: [[GRAY_SHADES[0]; 3], [GRAY_SHADES[1]; 3], [GRAY_SHADES[2]; 3], [GRAY_SHADES[3]; 3]],
+    };
+
+    /// Red, green and blue components of a gameboy color.
+    pub fn get_color(&self, color: PixelColor) -> [u8; 3] {
+        self.colors[color_to_index(color) as usize]
+    }
+}
+
+impl Default for ReferencePalette {
+    fn default() -> Self {
+        ReferencePalette::GRAYSCALE
+    }
+}
+
+/// Compare a frame of the emulator with a reference png image, returns the number of
+/// different pixels. On mismatch, a diff image is written at `diff_path`: the differing
+/// pixels are red and the others are faded.
+pub fn compare_screenshot<P: AsRef<Path>, Q: AsRef<Path>>(frame: &[u8], reference_path: P, palette: &ReferencePalette, diff_path: Q) -> std::io::Result<usize> {
+    let (width, height, reference) = read_rgb_png(reference_path)?;
+    if (width, height) != (SCREEN_WIDTH, SCREEN_HEIGHT) {
+        return Err(Error::new(ErrorKind::InvalidData, format!("reference image is {}x{} instead of {}x{}", width, height, SCREEN_WIDTH, SCREEN_HEIGHT)));
+    }
+
+    let mut different_pixels = 0;
+    let mut diff_image = Vec::with_capacity(reference.len());
+    for (&shade, expected_color) in frame.iter().zip(reference.chunks_exact(3)) {
+        let color = match PixelColor::from_shade(shade) {
+            Some(pixel_color) => palette.get_color(pixel_color),
+            None => UNKNOWN_SHADE_COLOR,
+        };
+
+        if color == expected_color {
+            diff_image.extend(color.iter().map(|&component| component / 4 + 0xC0));
+        } else {
+            different_pixels += 1;
+            diff_image.extend_from_slice(&DIFF_COLOR);
+        }
+    }
+
+    if different_pixels > 0 {
+        write_rgb_png(diff_path, SCREEN_WIDTH, SCREEN_HEIGHT, &diff_image)?;
+    }
+
+    Ok(different_pixels)
+}
+
+#[cfg(test)]
+mod screenshot_tests {
+    use super::*;
+
+    #[test]
+    fn test_palette() {
+        let palette = ReferencePalette::GRAYSCALE;
+        assert_eq!(palette.get_color(PixelColor::WHITE), [0xFF, 0xFF, 0xFF]);
+        assert_eq!(palette.get_color(PixelColor::LIGHT_GRAY), [0xAA, 0xAA, 0xAA]);
+        assert_eq!(palette.get_color(PixelColor::DARK_GRAY), [0x55, 0x55, 0x55]);
+        assert_eq!(palette.get_color(PixelColor::BLACK), [0x00, 0x00, 0x00]);
+    }
+
+    #[test]
+    fn test_compare_screenshot() {
+        let reference_path = std::env::temp_dir().join("qoboy_test_reference.png");
+        let diff_path = std::env::temp_dir().join("qoboy_test_reference_diff.png");
+        let _ = std::fs::remove_file(&diff_path);
+
+        // reference with the acid2 palette, a light gray line at the top of a white screen
+        let mut reference = vec![0xFF; SCREEN_WIDTH * SCREEN_HEIGHT * 3];
+        reference[0..SCREEN_WIDTH * 3].fill(0xAA);
+        write_rgb_png(&reference_path, SCREEN_WIDTH, SCREEN_HEIGHT, &reference).unwrap();
+
+        let mut frame = vec![PixelColor::WHITE as u8; SCREEN_WIDTH * SCREEN_HEIGHT];
+        frame[0..SCREEN_WIDTH].fill(PixelColor::LIGHT_GRAY as u8);
+        assert_eq!(compare_screenshot(&frame, &reference_path, &ReferencePalette::GRAYSCALE, &diff_path).unwrap(), 0);
+        assert!(!diff_path.exists());
+
+        // the differing pixels are red in the diff image
+        frame[SCREEN_WIDTH + 1] = PixelColor::BLACK as u8;
+        frame[SCREEN_WIDTH + 2] = PixelColor::BLACK as u8;
+        assert_eq!(compare_screenshot(&frame, &reference_path, &ReferencePalette::GRAYSCALE, &diff_path).unwrap(), 2);
+        let (_, _, diff_image) = read_rgb_png(&diff_path).unwrap();
+        assert_eq!(diff_image[(SCREEN_WIDTH + 1) * 3..(SCREEN_WIDTH + 3) * 3], [0xFF, 0x00, 0x00, 0xFF, 0x00, 0x00]);
+        assert_eq!(diff_image[0..3], [0xEA, 0xEA, 0xEA]);
+        assert_eq!(diff_image[SCREEN_WIDTH * 3..SCREEN_WIDTH * 3 + 3], [0xFF, 0xFF, 0xFF]);
+
+        std::fs::remove_file(&reference_path).unwrap();
+        std::fs::remove_file(&diff_path).unwrap();
+    }
+}
@@ -7,8 +7,11 @@ use instruction::{
 };
 use register::Registers;
 
-use crate::soc::peripheral::{IoAccess, Interrupt, VBLANK_VECTOR, LCDSTAT_VECTOR, TIMER_VECTOR};
+use crate::soc::peripheral::{IoAccess, Interrupt, VBLANK_VECTOR, LCDSTAT_VECTOR, TIMER_VECTOR, SERIAL_VECTOR, JOYPAD_VECTOR, JOYPAD_REGISTER, DIVIDER_REGISTER};
 use crate::soc::peripheral::nvic::InterruptSources;
+use crate::savestate::{SaveState, StateWriter, StateReader, invalid_state};
+use crate::error::EmulatorError;
+use crate::soc::model::GameBoyModel;
 
 const RUN_0_CYCLE: u8 = 0;
 const RUN_1_CYCLE: u8 = 1;
@@ -725,6 +728,8 @@ pub struct Cpu {
     pub pc: u16,
     pub sp: u16,
     mode: CpuMode,
+    // last instruction the cpu could not execute
+    fault: Option<EmulatorError>,
 }
 
 impl Cpu {
@@ -734,6 +739,51 @@ impl Cpu {
             pc: 0x0000,
             sp: 0x0000,
             mode: CpuMode::RUN,
+            fault: None,
+        }
+    }
+
+    // registers left by the boot rom of the model when it jumps to the cartridge, the
+    // boot rom of the dmg and the mgb sets the H and C flags when the header checksum is not 0
+    pub fn skip_boot(&mut self, model: GameBoyModel, header_checksum: u8) {
+        let checksum_flags = if header_checksum != 0 { 0x0030 } else { 0x0000 };
+
+        match model {
+            GameBoyModel::Dmg0 => {
+                self.registers.write_af(0x0100);
+                self.registers.write_bc(0xFF13);
+                self.registers.write_de(0x00C1);
+                self.registers.write_hl(0x8403);
+            }
+            GameBoyModel::Dmg | GameBoyModel::Mgb => {
+                let a = if model == GameBoyModel::Mgb { 0xFF00 } else { 0x0100 };
+                self.registers.write_af(a | 0x0080 | checksum_flags);
+                self.registers.write_bc(0x0013);
+                self.registers.write_de(0x00D8);
+                self.registers.write_hl(0x014D);
+            }
+        }
+
+        self.sp = 0xFFFE;
+        self.pc = 0x0100;
+    }
+
+    fn mode_to_byte(&self) -> u8 {
+        match self.mode {
+            CpuMode::RUN => 0,
+            CpuMode::INTERRUPT => 1,
+            CpuMode::STOP => 2,
+            CpuMode::HALT => 3,
+        }
+    }
+
+    fn mode_from_byte(data: u8) -> Option<CpuMode> {
+        match data {
+            0 => Some(CpuMode::RUN),
+            1 => Some(CpuMode::INTERRUPT),
+            2 => Some(CpuMode::STOP),
+            3 => Some(CpuMode::HALT),
+            _ => None,
         }
     }
 
@@ -763,7 +813,9 @@ impl Cpu {
                     // execute instruction
                     self.execute(instruction, peripheral)
                 } else {
-                    panic!("Unknown instruction found for 0x{:x}", instruction_byte);
+                    // skip the unknown opcode
+                    self.fault = Some(EmulatorError::UnknownOpcode { opcode: instruction_byte, address: self.pc });
+                    (self.pc.wrapping_add(1), RUN_1_CYCLE)
                 };
 
                 // update PC value & cycles value
@@ -803,8 +855,13 @@ impl Cpu {
             }
     
             CpuMode::STOP => {
-                // all system is stopped
-                RUN_0_CYCLE
+                // all system is stopped until a selected button is pressed
+                if peripheral.read(JOYPAD_REGISTER) & 0x0F != 0x0F {
+                    self.mode = CpuMode::RUN
+                }
+
+                // time goes on for the host but the peripherals are not clocked
+                RUN_1_CYCLE
             }
         }
     }
@@ -815,7 +872,8 @@ impl Cpu {
             InterruptSources::VBLANK => self.pc = VBLANK_VECTOR,
             InterruptSources::STAT => self.pc = LCDSTAT_VECTOR,
             InterruptSources::TIMER => self.pc = TIMER_VECTOR,
-            _ => {},
+            InterruptSources::SERIAL => self.pc = SERIAL_VECTOR,
+            InterruptSources::JOYPAD => self.pc = JOYPAD_VECTOR,
         }
     }
 
@@ -867,7 +925,11 @@ impl Cpu {
 
             // Control instructions
             Instruction::NOP => (self.pc.wrapping_add(1), RUN_1_CYCLE),
-            Instruction::STOP => (self.set_cpu_mode(CpuMode::STOP), RUN_1_CYCLE),
+            Instruction::STOP => {
+                // the divider is reset when entering STOP mode
+                peripheral.write(DIVIDER_REGISTER, 0x00);
+                (self.set_cpu_mode(CpuMode::STOP), RUN_1_CYCLE)
+            }
             Instruction::HALT => (self.set_cpu_mode(CpuMode::HALT), RUN_1_CYCLE),
             Instruction::DAA => (self.decimal_adjust(), RUN_1_CYCLE),
             Instruction::SCF => (self.set_carry(CarryOp::SET), RUN_1_CYCLE),
@@ -1232,6 +1294,18 @@ impl Cpu {
         }
     }
 
+    pub fn take_fault(&mut self) -> Option<EmulatorError> {
+        self.fault.take()
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.mode == CpuMode::STOP
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.mode == CpuMode::HALT
+    }
+
     fn set_cpu_mode(&mut self, mode: CpuMode) -> u16 {
         self.mode = mode;
         self.pc.wrapping_add(1)
@@ -1457,6 +1531,30 @@ impl Cpu {
     }
 }
 
+impl SaveState for Cpu {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u16(self.registers.read_af());
+        writer.write_u16(self.registers.read_bc());
+        writer.write_u16(self.registers.read_de());
+        writer.write_u16(self.registers.read_hl());
+        writer.write_u16(self.pc);
+        writer.write_u16(self.sp);
+        writer.write_u8(self.mode_to_byte());
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> std::io::Result<()> {
+        self.registers.write_af(reader.read_u16()?);
+        self.registers.write_bc(reader.read_u16()?);
+        self.registers.write_de(reader.read_u16()?);
+        self.registers.write_hl(reader.read_u16()?);
+        self.pc = reader.read_u16()?;
+        self.sp = reader.read_u16()?;
+        self.mode = Cpu::mode_from_byte(reader.read_u8()?).ok_or_else(|| invalid_state("unknown cpu mode"))?;
+
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod cpu_tests {
     use super::*;
@@ -1470,6 +1568,7 @@ mod cpu_tests {
     };
     use crate::cartridge::{Cartridge, CARTRIDGE_TYPE_OFFSET, CARTRIDGE_RAM_SIZE_OFFSET, CARTRIDGE_ROM_SIZE_OFFSET};
     use crate::soc::peripheral::Peripheral;
+    use crate::soc::peripheral::keypad::GameBoyKey;
 
     #[test]
     fn test_add_registers() {
@@ -1478,7 +1577,7 @@ mod cpu_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Peripheral::new(Cartridge::new(&rom).unwrap());
         cpu.registers.write_bc(0xAABB);
         cpu.execute(ADD(B), &mut peripheral);
         assert_eq!(cpu.registers.read_af(), 0xAA00);
@@ -1491,7 +1590,7 @@ mod cpu_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Peripheral::new(Cartridge::new(&rom).unwrap());
         let address = 0xC000;
         let data = 0xAA;
 
@@ -1509,7 +1608,7 @@ mod cpu_tests {
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
         rom[0x0001 as usize] = 0x23;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Peripheral::new(Cartridge::new(&rom).unwrap());
 
         cpu.execute(ADD(D8), &mut peripheral);
         assert_eq!(cpu.registers.read_af(), 0x2300);
@@ -1522,7 +1621,7 @@ mod cpu_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Peripheral::new(Cartridge::new(&rom).unwrap());
         cpu.registers.write_bc(0x2200);
         cpu.registers.write_hl(0x0125);
         cpu.execute(ADD16(U16Target::BC), &mut peripheral);
@@ -1550,7 +1649,7 @@ mod cpu_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Peripheral::new(Cartridge::new(&rom).unwrap());
         
         cpu.registers.write_af(0x0110);
         cpu.registers.write_bc(0xAABB);
@@ -1570,7 +1669,7 @@ mod cpu_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Peripheral::new(Cartridge::new(&rom).unwrap());
         let address = 0xC000;
         let data = 0xAA;
 
@@ -1587,7 +1686,7 @@ mod cpu_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Peripheral::new(Cartridge::new(&rom).unwrap());
         let address = 0xC001;
         let data = 0x23;
 
@@ -1605,7 +1704,7 @@ mod cpu_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Peripheral::new(Cartridge::new(&rom).unwrap());
         cpu.registers.write_bc(0xAABB);
         cpu.registers.write_af(0xFF00);
         cpu.execute(SUB(C), &mut peripheral);
@@ -1619,7 +1718,7 @@ mod cpu_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Peripheral::new(Cartridge::new(&rom).unwrap());
         cpu.registers.write_bc(0xAABB);
         cpu.registers.write_af(0xFF10);
         cpu.execute(SBC(C), &mut peripheral);
@@ -1633,7 +1732,7 @@ mod cpu_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Peripheral::new(Cartridge::new(&rom).unwrap());
         cpu.registers.write_bc(0xAABB);
         cpu.registers.write_af(0xAA00);
         cpu.execute(AND(B), &mut peripheral);
@@ -1647,7 +1746,7 @@ mod cpu_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Peripheral::new(Cartridge::new(&rom).unwrap());
         cpu.registers.write_bc(0x0022);
         cpu.registers.write_af(0x2100);
         cpu.execute(XOR(C), &mut peripheral);
@@ -1661,7 +1760,7 @@ mod cpu_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Peripheral::new(Cartridge::new(&rom).unwrap());
         cpu.registers.write_bc(0x0022);
         cpu.registers.write_af(0x2100);
         cpu.execute(OR(C), &mut peripheral);
@@ -1675,7 +1774,7 @@ mod cpu_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Peripheral::new(Cartridge::new(&rom).unwrap());
         
         cpu.registers.write_bc(0x0022);
         cpu.registers.write_af(0x2200);
@@ -1695,7 +1794,7 @@ mod cpu_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Peripheral::new(Cartridge::new(&rom).unwrap());
         
         cpu.registers.write_bc(0x2200);
         cpu.execute(INC(IncDecTarget::B), &mut peripheral);
@@ -1720,7 +1819,7 @@ mod cpu_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Peripheral::new(Cartridge::new(&rom).unwrap());
         
         cpu.registers.write_bc(0x2200);
         cpu.execute(INC16(U16Target::BC), &mut peripheral);
@@ -1746,7 +1845,7 @@ mod cpu_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Peripheral::new(Cartridge::new(&rom).unwrap());
         
         cpu.registers.write_bc(0x2200);
         cpu.execute(DEC(IncDecTarget::B), &mut peripheral);
@@ -1771,7 +1870,7 @@ mod cpu_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Peripheral::new(Cartridge::new(&rom).unwrap());
         
         cpu.registers.write_bc(0x2200);
         cpu.execute(DEC16(U16Target::BC), &mut peripheral);
@@ -1797,7 +1896,7 @@ mod cpu_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Peripheral::new(Cartridge::new(&rom).unwrap());
         
         cpu.registers.write_de(0x0057);
         cpu.execute(LOAD(IncDecTarget::B, E), &mut peripheral);
@@ -1844,7 +1943,7 @@ mod cpu_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Peripheral::new(Cartridge::new(&rom).unwrap());
         
         let mem_address = 0xC000;
         let mut data = 0x56;
@@ -1868,7 +1967,7 @@ mod cpu_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Peripheral::new(Cartridge::new(&rom).unwrap());
         
         let low_data = 0x4C;
         let high_data = 0xB7;
@@ -1890,7 +1989,7 @@ mod cpu_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Peripheral::new(Cartridge::new(&rom).unwrap());
         
         let mem_address = 0xC000;
         let mut data = 0x5600;
@@ -1915,7 +2014,7 @@ mod cpu_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Peripheral::new(Cartridge::new(&rom).unwrap());
         
         // first, fill memory with program
         let base_address: u16 = 0x0000;
@@ -1951,7 +2050,7 @@ mod cpu_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Peripheral::new(Cartridge::new(&rom).unwrap());
         
         // first, fill memory with program
         let base_address: u16 = 0x0000;
@@ -1988,7 +2087,7 @@ mod cpu_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Peripheral::new(Cartridge::new(&rom).unwrap());
         
         // first, fill memory with program
         let base_address: u16 = 0x0000;
@@ -2018,7 +2117,7 @@ mod cpu_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Peripheral::new(Cartridge::new(&rom).unwrap());
         
         // first, fill memory with program
         let base_address: u16 = 0x0000;
@@ -2055,7 +2154,7 @@ mod cpu_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Peripheral::new(Cartridge::new(&rom).unwrap());
         
         // first, fill memory with program
         let jump_inst: u8 = 0xE9;
@@ -2080,7 +2179,7 @@ mod cpu_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Peripheral::new(Cartridge::new(&rom).unwrap());
         
         let data: u16 = 0xA7D8;
         cpu.registers.write_hl(data);
@@ -2095,7 +2194,7 @@ mod cpu_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Peripheral::new(Cartridge::new(&rom).unwrap());
         
         cpu.sp = 0x0010;
         let offset: u8 = 0x02;
@@ -2121,7 +2220,7 @@ mod cpu_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Peripheral::new(Cartridge::new(&rom).unwrap());
         
         // first, fill memory with program
         let base_address = 0xC000;
@@ -2154,7 +2253,7 @@ mod cpu_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Peripheral::new(Cartridge::new(&rom).unwrap());
         
         // initialize RAM memory
         let ram_data_address = 0xFFA5;
@@ -2184,7 +2283,7 @@ mod cpu_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Peripheral::new(Cartridge::new(&rom).unwrap());
         
         // initialize RAM memory
         let ram_data_address = 0xFFA5;
@@ -2218,7 +2317,7 @@ mod cpu_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Peripheral::new(Cartridge::new(&rom).unwrap());
         
         // initialize RAM memory
         let ram_data_address = 0xFFA5;
@@ -2249,7 +2348,7 @@ mod cpu_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Peripheral::new(Cartridge::new(&rom).unwrap());
         
         // initialize RAM memory
         let ram_data_address = 0xFFA5;
@@ -2279,7 +2378,7 @@ mod cpu_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Peripheral::new(Cartridge::new(&rom).unwrap());
         
         // initialize RAM memory parameters
         let ram_address = 0xFFA5;
@@ -2310,7 +2409,7 @@ mod cpu_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Peripheral::new(Cartridge::new(&rom).unwrap());
         
         // init parameters
         let data_to_add = 0x88;
@@ -2343,7 +2442,7 @@ mod cpu_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Peripheral::new(Cartridge::new(&rom).unwrap());
         
         // initialize RAM memory parameters
         let ram_address = 0xFFA5;
@@ -2364,7 +2463,7 @@ mod cpu_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Peripheral::new(Cartridge::new(&rom).unwrap());
         
         // test push instruction
         cpu.sp = 0xFFAF;
@@ -2379,7 +2478,7 @@ mod cpu_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Peripheral::new(Cartridge::new(&rom).unwrap());
         
         cpu.execute(EI, &mut peripheral);
         assert_eq!(peripheral.nvic.interrupt_master_enable, true);
@@ -2407,7 +2506,7 @@ mod cpu_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Peripheral::new(Cartridge::new(&rom).unwrap());
         
         // first, fill memory with program
         let inst: u8 = 0xC4;
@@ -2433,7 +2532,7 @@ mod cpu_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Peripheral::new(Cartridge::new(&rom).unwrap());
         
         // first, fill memory with program
         let nop_inst: u8 = 0x00;
@@ -2471,6 +2570,50 @@ mod cpu_tests {
         assert_eq!(cpu.pc, 0x0004 + 0xC000);
     }
 
+    #[test]
+    fn test_stop_wakeup() {
+        let mut cpu = Cpu::new();
+        let mut rom = [0xFF; 0x8000];
+        rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
+        rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
+        rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
+        let mut peripheral = Peripheral::new(Cartridge::new(&rom).unwrap());
+        cpu.sp = 0xFFA5;
+
+        // STOP then NOP
+        peripheral.write(0xC000, 0x10);
+        peripheral.write(0xC001, 0x00);
+
+        // let the divider count
+        for _ in 0..1024 {
+            peripheral.run(4);
+        }
+        assert_ne!(peripheral.read(DIVIDER_REGISTER), 0x00);
+
+        // run CPU to do the STOP, the divider is reset
+        cpu.pc = 0xC000;
+        cpu.run(&mut peripheral);
+        assert_eq!(cpu.pc, 0xC001);
+        assert!(cpu.is_stopped());
+        assert_eq!(peripheral.read(DIVIDER_REGISTER), 0x00);
+
+        // an unselected button does not wake the cpu up
+        peripheral.write(JOYPAD_REGISTER, 0x20);
+        peripheral.keypad.set(GameBoyKey::A, true);
+        cpu.run(&mut peripheral);
+        assert!(cpu.is_stopped());
+
+        // a selected button wakes the cpu up and requests the joypad interrupt
+        peripheral.nvic.master_enable(true);
+        peripheral.nvic.enable_interrupt(InterruptSources::JOYPAD, true);
+        peripheral.keypad.set(GameBoyKey::UP, true);
+        cpu.run(&mut peripheral);
+        assert!(!cpu.is_stopped());
+        peripheral.run(4);
+        cpu.run(&mut peripheral);
+        assert_eq!(cpu.pc, JOYPAD_VECTOR);
+    }
+
     #[test]
     fn test_jump_to_interrupt() {
         let mut cpu = Cpu::new();
@@ -2478,7 +2621,7 @@ mod cpu_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Peripheral::new(Cartridge::new(&rom).unwrap());
         
         // init stack pointer
         cpu.sp = 0xFFA5;
@@ -2532,7 +2675,7 @@ mod cpu_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Peripheral::new(Cartridge::new(&rom).unwrap());
         
         cpu.registers.a = 0x55;
         cpu.execute(Instruction::CPL, &mut peripheral);
@@ -2546,7 +2689,7 @@ mod cpu_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Peripheral::new(Cartridge::new(&rom).unwrap());
         
         cpu.execute(Instruction::SCF, &mut peripheral);
         assert_eq!(cpu.registers.f.carry, true);
@@ -2563,7 +2706,7 @@ mod cpu_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Peripheral::new(Cartridge::new(&rom).unwrap());
         
         cpu.registers.a = 0x0B;
         cpu.execute(Instruction::DAA, &mut peripheral);
@@ -2577,7 +2720,7 @@ mod cpu_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Peripheral::new(Cartridge::new(&rom).unwrap());
         
         cpu.registers.a = 0xB5;
         cpu.execute(Instruction::RCA(Direction::LEFT), &mut peripheral);
@@ -2592,7 +2735,7 @@ mod cpu_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Peripheral::new(Cartridge::new(&rom).unwrap());
         
         cpu.registers.a = 0xB5;
         cpu.registers.f.carry = true;
@@ -2608,7 +2751,7 @@ mod cpu_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Peripheral::new(Cartridge::new(&rom).unwrap());
         
         let program: [u8; 2] = [0xCB, 0x19];
         let mut index = 0;
@@ -2635,7 +2778,7 @@ mod cpu_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Peripheral::new(Cartridge::new(&rom).unwrap());
         
         cpu.registers.b = 0xB5;
         cpu.execute(Instruction::RC(Direction::LEFT, IncDecTarget::B), &mut peripheral);
@@ -2650,7 +2793,7 @@ mod cpu_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Peripheral::new(Cartridge::new(&rom).unwrap());
         
         let address = 0xC000;
         let data = 0xB5;
@@ -2667,7 +2810,7 @@ mod cpu_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Peripheral::new(Cartridge::new(&rom).unwrap());
         
         cpu.registers.e = 0xB5;
         cpu.registers.f.carry = true;
@@ -2683,7 +2826,7 @@ mod cpu_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Peripheral::new(Cartridge::new(&rom).unwrap());
         
         cpu.registers.d = 0xB5;
         cpu.execute(Instruction::SLA(IncDecTarget::D), &mut peripheral);
@@ -2698,7 +2841,7 @@ mod cpu_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Peripheral::new(Cartridge::new(&rom).unwrap());
         
         cpu.registers.h = 0xB5;
         cpu.execute(Instruction::SRL(IncDecTarget::H), &mut peripheral);
@@ -2713,7 +2856,7 @@ mod cpu_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Peripheral::new(Cartridge::new(&rom).unwrap());
         
         let address = 0xC000;
         let data = 0xB5;
@@ -2731,7 +2874,7 @@ mod cpu_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Peripheral::new(Cartridge::new(&rom).unwrap());
         
         cpu.registers.c = 0xB5;
         cpu.execute(Instruction::SRA(IncDecTarget::C), &mut peripheral);
@@ -2746,7 +2889,7 @@ mod cpu_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Peripheral::new(Cartridge::new(&rom).unwrap());
         
         cpu.registers.l = 0xB5;
         cpu.execute(Instruction::SWAP(IncDecTarget::L), &mut peripheral);
@@ -2760,7 +2903,7 @@ mod cpu_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Peripheral::new(Cartridge::new(&rom).unwrap());
         
         cpu.registers.h = 0xB5;
         cpu.execute(Instruction::BIT(BitTarget::BIT_1, IncDecTarget::H), &mut peripheral);
@@ -2786,7 +2929,7 @@ mod cpu_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Peripheral::new(Cartridge::new(&rom).unwrap());
         
         cpu.registers.b = 0xB5;
         cpu.execute(Instruction::RESET_BIT(BitTarget::BIT_2, IncDecTarget::B), &mut peripheral);
@@ -2804,7 +2947,7 @@ mod cpu_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Peripheral::new(Cartridge::new(&rom).unwrap());
         
         let address = 0xC000;
         let data = 0xB5;
@@ -2818,4 +2961,25 @@ mod cpu_tests {
         cpu.execute(Instruction::SET_BIT(BitTarget::BIT_3, IncDecTarget::HL), &mut peripheral);
         assert_eq!(peripheral.read(address), 0xB9);
     }
+
+    #[test]
+    fn test_skip_boot() {
+        let mut cpu = Cpu::new();
+        cpu.skip_boot(GameBoyModel::Dmg, 0x00);
+        assert_eq!(cpu.registers.read_af(), 0x0180);
+        assert_eq!(cpu.registers.read_bc(), 0x0013);
+        assert_eq!(cpu.registers.read_de(), 0x00D8);
+        assert_eq!(cpu.registers.read_hl(), 0x014D);
+        assert_eq!(cpu.sp, 0xFFFE);
+        assert_eq!(cpu.pc, 0x0100);
+
+        cpu.skip_boot(GameBoyModel::Mgb, 0x42);
+        assert_eq!(cpu.registers.read_af(), 0xFFB0);
+
+        cpu.skip_boot(GameBoyModel::Dmg0, 0x42);
+        assert_eq!(cpu.registers.read_af(), 0x0100);
+        assert_eq!(cpu.registers.read_bc(), 0xFF13);
+        assert_eq!(cpu.registers.read_de(), 0x00C1);
+        assert_eq!(cpu.registers.read_hl(), 0x8403);
+    }
 }
\ No newline at end of file
@@ -1,10 +1,16 @@
 pub mod peripheral;
 mod cpu;
+mod model;
 
 use cpu::Cpu;
-use peripheral::Peripheral;
-use crate::cartridge::Cartridge;
+use peripheral::{Peripheral, INTERRUPT_ENABLE_REGISTER};
+use crate::cartridge::{Cartridge, CartridgeHeader, CARTRIDGE_HEADER_CHECKSUM_OFFSET};
+use crate::savestate::{SaveState, StateWriter, StateReader};
+use crate::error::EmulatorError;
+use std::path::PathBuf;
 pub use peripheral::keypad::GameBoyKey;
+pub use model::GameBoyModel;
+use peripheral::serial::SerialDevice;
 
 const CLOCK_TICK_PER_MACHINE_CYCLE: u8 = 4;
 
@@ -14,20 +20,51 @@ pub struct Soc {
 }
 
 impl Soc {
-    pub fn new(boot_rom: &[u8], cartridge: Cartridge) -> Soc {
+    pub fn new(boot_rom: &[u8], cartridge: Cartridge) -> Result<Soc, EmulatorError> {
         let mut peripheral = Peripheral::new(cartridge);
-        peripheral.load_bootrom(boot_rom);
+        peripheral.load_bootrom(boot_rom)?;
 
-        Soc {
+        Ok(Soc {
             cpu: Cpu::new(),
-            peripheral: peripheral,
+            peripheral,
+        })
+    }
+
+    // start the cartridge right away, in the state left by the boot rom of the model
+    pub fn new_without_boot_rom(cartridge: Cartridge, model: GameBoyModel) -> Soc {
+        let mut peripheral = Peripheral::new(cartridge);
+        peripheral.skip_boot(model);
+
+        let mut cpu = Cpu::new();
+        cpu.skip_boot(model, peripheral.peek(CARTRIDGE_HEADER_CHECKSUM_OFFSET));
+
+        Soc {
+            cpu,
+            peripheral,
         }
     }
 
+    // fault raised by the cpu or the bus during the last run
+    pub fn take_fault(&mut self) -> Option<EmulatorError> {
+        self.cpu.take_fault().or_else(|| self.peripheral.take_fault())
+    }
+
+    // the cpu waits in HALT mode for an interrupt which is never enabled
+    pub fn is_locked_up(&self) -> bool {
+        self.cpu.is_halted() && self.peripheral.peek(INTERRUPT_ENABLE_REGISTER) & 0x1F == 0
+    }
+
+    pub fn read_memory(&self, address: u16) -> u8 {
+        self.peripheral.peek(address)
+    }
+
     pub fn run(&mut self) -> u8 {
         let cycles = self.cpu.run(&mut self.peripheral) * CLOCK_TICK_PER_MACHINE_CYCLE;
 
-        self.peripheral.run(cycles);
+        // the system clock is stopped in STOP mode
+        if !self.cpu.is_stopped() {
+            self.peripheral.run(cycles);
+        }
 
         cycles
     }
@@ -36,7 +73,67 @@ impl Soc {
         self.peripheral.gpu.frame_buffer[pixel_index]
     }
 
+    pub fn take_vblank(&mut self) -> bool {
+        self.peripheral.gpu.take_vblank()
+    }
+
+    pub fn take_rumble(&mut self) -> Option<bool> {
+        self.peripheral.take_rumble()
+    }
+
+    pub fn advance_rtc(&mut self, seconds: i64) {
+        self.peripheral.advance_rtc(seconds);
+    }
+
+    pub fn set_tilt(&mut self, x: f32, y: f32) {
+        self.peripheral.set_tilt(x, y);
+    }
+
+    pub fn get_frame(&self) -> &[u8] {
+        &self.peripheral.gpu.frame_buffer
+    }
+
+    pub fn set_audio_sample_rate(&mut self, sample_rate: u32) {
+        self.peripheral.apu.set_sample_rate(sample_rate);
+    }
+
+    pub fn get_audio_sample_rate(&self) -> u32 {
+        self.peripheral.apu.get_sample_rate()
+    }
+
+    pub fn take_audio_samples(&mut self) -> Vec<f32> {
+        self.peripheral.apu.take_samples()
+    }
+
+    pub fn set_serial_device(&mut self, device: Box<dyn SerialDevice>) {
+        self.peripheral.set_serial_device(device);
+    }
+
+    pub fn get_cartridge_header(&self) -> &CartridgeHeader {
+        self.peripheral.get_cartridge_header()
+    }
+
+    pub fn attach_save_file(&mut self, save_path: PathBuf) -> std::io::Result<bool> {
+        self.peripheral.attach_save_file(save_path)
+    }
+
+    pub fn flush_save_file(&mut self) -> std::io::Result<()> {
+        self.peripheral.flush_save_file()
+    }
+
     pub fn set_key(&mut self, key: GameBoyKey, value: bool) {
         self.peripheral.keypad.set(key, value);
     }
+}
+
+impl SaveState for Soc {
+    fn save_state(&self, writer: &mut StateWriter) {
+        self.cpu.save_state(writer);
+        self.peripheral.save_state(writer);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> std::io::Result<()> {
+        self.cpu.load_state(reader)?;
+        self.peripheral.load_state(reader)
+    }
 }
\ No newline at end of file
@@ -0,0 +1,36 @@
+/// Gameboy models whose boot rom can be skipped, each boot rom leaves its own values in
+/// the registers when it hands over to the game.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum GameBoyModel {
+    /// Early gameboy, with the first revision of the boot rom.
+    Dmg0,
+    /// Gameboy.
+    Dmg,
+    /// Gameboy pocket.
+    Mgb,
+}
+
+impl GameBoyModel {
+    /// Model named `dmg0`, `dmg` or `mgb`, case insensitive.
+    pub fn from_name(name: &str) -> Option<GameBoyModel> {
+        match name.to_ascii_lowercase().as_str() {
+            "dmg0" => Some(GameBoyModel::Dmg0),
+            "dmg" => Some(GameBoyModel::Dmg),
+            "mgb" => Some(GameBoyModel::Mgb),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod model_tests {
+    use super::*;
+
+    #[test]
+    fn test_from_name() {
+        assert_eq!(GameBoyModel::from_name("dmg0"), Some(GameBoyModel::Dmg0));
+        assert_eq!(GameBoyModel::from_name("DMG"), Some(GameBoyModel::Dmg));
+        assert_eq!(GameBoyModel::from_name("Mgb"), Some(GameBoyModel::Mgb));
+        assert_eq!(GameBoyModel::from_name("cgb"), None);
+    }
+}
@@ -0,0 +1,1104 @@
+use crate::emulator::ONE_SECOND_IN_CYCLES;
+use crate::savestate::{SaveState, StateWriter, StateReader};
+
+pub const DEFAULT_SAMPLE_RATE: u32 = 48000;
+// maximum number of stereo samples kept when nobody drains the output
+const MAX_BUFFERED_SAMPLES_IN_SECONDS: usize = 1;
+
+pub const APU_REGISTERS_BEGIN: u16 = 0xFF10;
+pub const APU_REGISTERS_END: u16 = 0xFF2F;
+pub const WAVE_RAM_BEGIN: u16 = 0xFF30;
+pub const WAVE_RAM_END: u16 = 0xFF3F;
+const WAVE_RAM_SIZE: usize = (WAVE_RAM_END - WAVE_RAM_BEGIN + 1) as usize;
+
+const NR10: u16 = 0xFF10;
+const NR11: u16 = 0xFF11;
+const NR12: u16 = 0xFF12;
+const NR13: u16 = 0xFF13;
+const NR14: u16 = 0xFF14;
+const NR21: u16 = 0xFF16;
+const NR22: u16 = 0xFF17;
+const NR23: u16 = 0xFF18;
+const NR24: u16 = 0xFF19;
+const NR30: u16 = 0xFF1A;
+const NR31: u16 = 0xFF1B;
+const NR32: u16 = 0xFF1C;
+const NR33: u16 = 0xFF1D;
+const NR34: u16 = 0xFF1E;
+const NR41: u16 = 0xFF20;
+const NR42: u16 = 0xFF21;
+const NR43: u16 = 0xFF22;
+const NR44: u16 = 0xFF23;
+const NR50: u16 = 0xFF24;
+const NR51: u16 = 0xFF25;
+const NR52: u16 = 0xFF26;
+
+// bits which are not readable are returned as 1, from NR10 to 0xFF2F
+const REGISTERS_READ_MASK: [u8; (APU_REGISTERS_END - APU_REGISTERS_BEGIN + 1) as usize] = [
+    0x80, 0x3F, 0x00, 0xFF, 0xBF, // NR10 - NR14
+    0xFF, 0x3F, 0x00, 0xFF, 0xBF, // unused - NR24
+    0x7F, 0xFF, 0x9F, 0xFF, 0xBF, // NR30 - NR34
+    0xFF, 0xFF, 0x00, 0x00, 0xBF, // unused - NR44
+    0x00, 0x00, 0x70,             // NR50 - NR52
+    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, // unused
+];
+
+// the frame sequencer is clocked by the falling edge of this DIV bit (512 Hz)
+const FRAME_SEQUENCER_DIV_BIT: u8 = 4;
+
+const PULSE_LENGTH_MAX: u16 = 64;
+const WAVE_LENGTH_MAX: u16 = 256;
+const NOISE_LENGTH_MAX: u16 = 64;
+
+const MAX_FREQUENCY: u16 = 2047;
+const MAX_VOLUME: u8 = 15;
+
+const DUTY_PATTERNS: [[u8; 8]; 4] = [
+    [0, 0, 0, 0, 0, 0, 0, 1], // 12.5 %
+    [1, 0, 0, 0, 0, 0, 0, 1], // 25 %
+    [1, 0, 0, 0, 0, 1, 1, 1], // 50 %
+    [0, 1, 1, 1, 1, 1, 1, 0], // 75 %
+];
+
+const NOISE_DIVISORS: [u32; 8] = [8, 16, 32, 48, 64, 80, 96, 112];
+
+// charge factor of the output high pass filter, for each emulated cycle
+const CAPACITOR_CHARGE_FACTOR: f64 = 0.999958;
+
+struct LengthCounter {
+    enabled: bool,
+    counter: u16,
+    max: u16,
+}
+
+impl LengthCounter {
+    fn new(max: u16) -> LengthCounter {
+        LengthCounter {
+            enabled: false,
+            counter: 0,
+            max,
+        }
+    }
+
+    fn load(&mut self, data: u8) {
+        self.counter = self.max - (data as u16 & (self.max - 1));
+    }
+
+    // returns false when the counter expires
+    fn clock(&mut self) -> bool {
+        if self.enabled && self.counter > 0 {
+            self.counter -= 1;
+            self.counter != 0
+        } else {
+            true
+        }
+    }
+
+    // enabling the counter in the first half of the length period clocks it once more
+    // see https://gbdev.gg8.se/wiki/articles/Gameboy_sound_hardware#Obscure_Behavior
+    fn set_enable(&mut self, enable: bool, extra_clock: bool) -> bool {
+        let was_enabled = self.enabled;
+        self.enabled = enable;
+
+        if extra_clock && !was_enabled && enable && self.counter > 0 {
+            self.counter -= 1;
+            self.counter != 0
+        } else {
+            true
+        }
+    }
+
+    fn trigger(&mut self, extra_clock: bool) {
+        if self.counter == 0 {
+            self.counter = self.max;
+            if self.enabled && extra_clock {
+                self.counter -= 1;
+            }
+        }
+    }
+
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bool(self.enabled);
+        writer.write_u16(self.counter);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> std::io::Result<()> {
+        self.enabled = reader.read_bool()?;
+        self.counter = reader.read_u16()?;
+
+        Ok(())
+    }
+}
+
+struct Envelope {
+    initial_volume: u8,
+    increase: bool,
+    period: u8,
+    volume: u8,
+    timer: u8,
+}
+
+impl Envelope {
+    fn new() -> Envelope {
+        Envelope {
+            initial_volume: 0,
+            increase: false,
+            period: 0,
+            volume: 0,
+            timer: 0,
+        }
+    }
+
+    fn settings_from_byte(&mut self, data: u8) {
+        self.initial_volume = data >> 4;
+        self.increase = (data & 0x08) != 0;
+        self.period = data & 0x07;
+    }
+
+    // the DAC is powered off when the upper 5 bits of the envelope register are cleared
+    fn dac_enabled(&self) -> bool {
+        self.initial_volume != 0 || self.increase
+    }
+
+    fn trigger(&mut self) {
+        self.volume = self.initial_volume;
+        self.timer = self.period;
+    }
+
+    fn clock(&mut self) {
+        if self.period == 0 {
+            return;
+        }
+
+        if self.timer > 0 {
+            self.timer -= 1;
+        }
+
+        if self.timer == 0 {
+            self.timer = self.period;
+
+            if self.increase && self.volume < MAX_VOLUME {
+                self.volume += 1;
+            } else if !self.increase && self.volume > 0 {
+                self.volume -= 1;
+            }
+        }
+    }
+
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(self.volume);
+        writer.write_u8(self.timer);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> std::io::Result<()> {
+        self.volume = reader.read_u8()?;
+        self.timer = reader.read_u8()?;
+
+        Ok(())
+    }
+}
+
+struct Sweep {
+    period: u8,
+    negate: bool,
+    shift: u8,
+    timer: u8,
+    enabled: bool,
+    shadow_frequency: u16,
+    negate_used: bool,
+}
+
+impl Sweep {
+    fn new() -> Sweep {
+        Sweep {
+            period: 0,
+            negate: false,
+            shift: 0,
+            timer: 0,
+            enabled: false,
+            shadow_frequency: 0,
+            negate_used: false,
+        }
+    }
+
+    fn settings_from_byte(&mut self, data: u8) {
+        self.period = (data >> 4) & 0x07;
+        self.negate = (data & 0x08) != 0;
+        self.shift = data & 0x07;
+    }
+
+    fn reload_timer(&mut self) {
+        // a period of 0 is treated as 8
+        self.timer = if self.period != 0 { self.period } else { 8 };
+    }
+
+    fn compute_frequency(&mut self) -> u16 {
+        let offset = self.shadow_frequency >> self.shift;
+
+        if self.negate {
+            self.negate_used = true;
+            self.shadow_frequency - offset
+        } else {
+            self.shadow_frequency + offset
+        }
+    }
+
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(self.timer);
+        writer.write_bool(self.enabled);
+        writer.write_u16(self.shadow_frequency);
+        writer.write_bool(self.negate_used);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> std::io::Result<()> {
+        self.timer = reader.read_u8()?;
+        self.enabled = reader.read_bool()?;
+        self.shadow_frequency = reader.read_u16()?;
+        self.negate_used = reader.read_bool()?;
+
+        Ok(())
+    }
+}
+
+// channels 1 and 2, only channel 1 uses the sweep unit
+struct PulseChannel {
+    enabled: bool,
+    length: LengthCounter,
+    envelope: Envelope,
+    sweep: Sweep,
+    duty: u8,
+    duty_position: u8,
+    frequency: u16,
+    timer: u32,
+}
+
+impl PulseChannel {
+    fn new() -> PulseChannel {
+        PulseChannel {
+            enabled: false,
+            length: LengthCounter::new(PULSE_LENGTH_MAX),
+            envelope: Envelope::new(),
+            sweep: Sweep::new(),
+            duty: 0,
+            duty_position: 0,
+            frequency: 0,
+            timer: PulseChannel::period(0),
+        }
+    }
+
+    fn period(frequency: u16) -> u32 {
+        (2048 - frequency as u32) * 4
+    }
+
+    fn step(&mut self, cycles: u32) {
+        let mut cycles = cycles;
+        while cycles >= self.timer {
+            cycles -= self.timer;
+            self.timer = PulseChannel::period(self.frequency);
+            self.duty_position = (self.duty_position + 1) % 8;
+        }
+        self.timer -= cycles;
+    }
+
+    fn output(&self) -> u8 {
+        if self.enabled {
+            DUTY_PATTERNS[self.duty as usize][self.duty_position as usize] * self.envelope.volume
+        } else {
+            0
+        }
+    }
+
+    fn write_sweep(&mut self, data: u8) {
+        self.sweep.settings_from_byte(data);
+        // leaving negate mode after a negate computation disables the channel
+        if self.sweep.negate_used && !self.sweep.negate {
+            self.enabled = false;
+        }
+    }
+
+    fn write_length(&mut self, data: u8) {
+        self.duty = data >> 6;
+        self.length.load(data);
+    }
+
+    fn write_envelope(&mut self, data: u8) {
+        self.envelope.settings_from_byte(data);
+        if !self.envelope.dac_enabled() {
+            self.enabled = false;
+        }
+    }
+
+    fn write_frequency_lo(&mut self, data: u8) {
+        self.frequency = (self.frequency & 0x0700) | data as u16;
+    }
+
+    fn write_control(&mut self, data: u8, extra_clock: bool) {
+        self.frequency = (self.frequency & 0x00FF) | ((data as u16 & 0x07) << 8);
+
+        if !self.length.set_enable((data & 0x40) != 0, extra_clock) {
+            self.enabled = false;
+        }
+
+        if (data & 0x80) != 0 {
+            self.trigger(extra_clock);
+        }
+    }
+
+    fn trigger(&mut self, extra_clock: bool) {
+        self.enabled = self.envelope.dac_enabled();
+        self.length.trigger(extra_clock);
+        self.timer = PulseChannel::period(self.frequency);
+        self.envelope.trigger();
+
+        // sweep unit
+        self.sweep.shadow_frequency = self.frequency;
+        self.sweep.reload_timer();
+        self.sweep.enabled = self.sweep.period != 0 || self.sweep.shift != 0;
+        self.sweep.negate_used = false;
+        if self.sweep.shift != 0 && self.sweep.compute_frequency() > MAX_FREQUENCY {
+            self.enabled = false;
+        }
+    }
+
+    fn clock_length(&mut self) {
+        if !self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    fn clock_sweep(&mut self) {
+        if self.sweep.timer > 0 {
+            self.sweep.timer -= 1;
+        }
+
+        if self.sweep.timer == 0 {
+            self.sweep.reload_timer();
+
+            if self.sweep.enabled && self.sweep.period != 0 {
+                let new_frequency = self.sweep.compute_frequency();
+
+                if new_frequency > MAX_FREQUENCY {
+                    self.enabled = false;
+                } else if self.sweep.shift != 0 {
+                    self.sweep.shadow_frequency = new_frequency;
+                    self.frequency = new_frequency;
+
+                    // overflow check is done again with the new frequency
+                    if self.sweep.compute_frequency() > MAX_FREQUENCY {
+                        self.enabled = false;
+                    }
+                }
+            }
+        }
+    }
+
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bool(self.enabled);
+        self.length.save_state(writer);
+        self.envelope.save_state(writer);
+        self.sweep.save_state(writer);
+        writer.write_u8(self.duty_position);
+        writer.write_u16(self.frequency);
+        writer.write_u32(self.timer);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> std::io::Result<()> {
+        self.enabled = reader.read_bool()?;
+        self.length.load_state(reader)?;
+        self.envelope.load_state(reader)?;
+        self.sweep.load_state(reader)?;
+        self.duty_position = reader.read_u8()? % 8;
+        self.frequency = reader.read_u16()? & MAX_FREQUENCY;
+        self.timer = reader.read_u32()?.max(1);
+
+        Ok(())
+    }
+}
+
+// channel 3 plays the 32 4-bit samples stored in wave RAM
+struct WaveChannel {
+    enabled: bool,
+    dac_enabled: bool,
+    length: LengthCounter,
+    volume_code: u8,
+    frequency: u16,
+    timer: u32,
+    position: u8,
+    wave_ram: [u8; WAVE_RAM_SIZE],
+}
+
+impl WaveChannel {
+    fn new() -> WaveChannel {
+        WaveChannel {
+            enabled: false,
+            dac_enabled: false,
+            length: LengthCounter::new(WAVE_LENGTH_MAX),
+            volume_code: 0,
+            frequency: 0,
+            timer: WaveChannel::period(0),
+            position: 0,
+            wave_ram: [0x00; WAVE_RAM_SIZE],
+        }
+    }
+
+    fn period(frequency: u16) -> u32 {
+        (2048 - frequency as u32) * 2
+    }
+
+    fn step(&mut self, cycles: u32) {
+        let mut cycles = cycles;
+        while cycles >= self.timer {
+            cycles -= self.timer;
+            self.timer = WaveChannel::period(self.frequency);
+            self.position = (self.position + 1) % (WAVE_RAM_SIZE as u8 * 2);
+        }
+        self.timer -= cycles;
+    }
+
+    fn output(&self) -> u8 {
+        if self.enabled {
+            // high nibble is played first
+            let sample_byte = self.wave_ram[(self.position / 2) as usize];
+            let sample = if (self.position & 0x01) == 0 { sample_byte >> 4 } else { sample_byte & 0x0F };
+
+            match self.volume_code {
+                0 => 0,
+                1 => sample,
+                2 => sample >> 1,
+                _ => sample >> 2,
+            }
+        } else {
+            0
+        }
+    }
+
+    fn write_dac(&mut self, data: u8) {
+        self.dac_enabled = (data & 0x80) != 0;
+        if !self.dac_enabled {
+            self.enabled = false;
+        }
+    }
+
+    fn write_frequency_lo(&mut self, data: u8) {
+        self.frequency = (self.frequency & 0x0700) | data as u16;
+    }
+
+    fn write_control(&mut self, data: u8, extra_clock: bool) {
+        self.frequency = (self.frequency & 0x00FF) | ((data as u16 & 0x07) << 8);
+
+        if !self.length.set_enable((data & 0x40) != 0, extra_clock) {
+            self.enabled = false;
+        }
+
+        if (data & 0x80) != 0 {
+            self.enabled = self.dac_enabled;
+            self.length.trigger(extra_clock);
+            self.timer = WaveChannel::period(self.frequency);
+            self.position = 0;
+        }
+    }
+
+    fn clock_length(&mut self) {
+        if !self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bool(self.enabled);
+        self.length.save_state(writer);
+        writer.write_u16(self.frequency);
+        writer.write_u32(self.timer);
+        writer.write_u8(self.position);
+        writer.write_bytes(&self.wave_ram);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> std::io::Result<()> {
+        self.enabled = reader.read_bool()?;
+        self.length.load_state(reader)?;
+        self.frequency = reader.read_u16()? & MAX_FREQUENCY;
+        self.timer = reader.read_u32()?.max(1);
+        self.position = reader.read_u8()? % (WAVE_RAM_SIZE as u8 * 2);
+        reader.read_bytes_into(&mut self.wave_ram)?;
+
+        Ok(())
+    }
+}
+
+// channel 4 outputs the pseudo random bit from a linear feedback shift register
+struct NoiseChannel {
+    enabled: bool,
+    length: LengthCounter,
+    envelope: Envelope,
+    clock_shift: u8,
+    width_mode: bool,
+    divisor_code: u8,
+    lfsr: u16,
+    timer: u32,
+}
+
+impl NoiseChannel {
+    fn new() -> NoiseChannel {
+        NoiseChannel {
+            enabled: false,
+            length: LengthCounter::new(NOISE_LENGTH_MAX),
+            envelope: Envelope::new(),
+            clock_shift: 0,
+            width_mode: false,
+            divisor_code: 0,
+            lfsr: 0x7FFF,
+            timer: NOISE_DIVISORS[0],
+        }
+    }
+
+    fn period(&self) -> u32 {
+        NOISE_DIVISORS[self.divisor_code as usize] << self.clock_shift
+    }
+
+    fn step(&mut self, cycles: u32) {
+        let mut cycles = cycles;
+        while cycles >= self.timer {
+            cycles -= self.timer;
+            self.timer = self.period();
+
+            // shift values 14 and 15 don't clock the lfsr
+            if self.clock_shift < 14 {
+                let xor_bit = (self.lfsr & 0x01) ^ ((self.lfsr >> 1) & 0x01);
+                self.lfsr = (self.lfsr >> 1) | (xor_bit << 14);
+                // the xor result is also put in bit 6 in 7-bit mode
+                if self.width_mode {
+                    self.lfsr = (self.lfsr & !(1 << 6)) | (xor_bit << 6);
+                }
+            }
+        }
+        self.timer -= cycles;
+    }
+
+    fn output(&self) -> u8 {
+        if self.enabled && (self.lfsr & 0x01) == 0 {
+            self.envelope.volume
+        } else {
+            0
+        }
+    }
+
+    fn write_envelope(&mut self, data: u8) {
+        self.envelope.settings_from_byte(data);
+        if !self.envelope.dac_enabled() {
+            self.enabled = false;
+        }
+    }
+
+    fn write_polynomial(&mut self, data: u8) {
+        self.clock_shift = data >> 4;
+        self.width_mode = (data & 0x08) != 0;
+        self.divisor_code = data & 0x07;
+    }
+
+    fn write_control(&mut self, data: u8, extra_clock: bool) {
+        if !self.length.set_enable((data & 0x40) != 0, extra_clock) {
+            self.enabled = false;
+        }
+
+        if (data & 0x80) != 0 {
+            self.enabled = self.envelope.dac_enabled();
+            self.length.trigger(extra_clock);
+            self.envelope.trigger();
+            self.timer = self.period();
+            self.lfsr = 0x7FFF;
+        }
+    }
+
+    fn clock_length(&mut self) {
+        if !self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bool(self.enabled);
+        self.length.save_state(writer);
+        self.envelope.save_state(writer);
+        writer.write_u16(self.lfsr);
+        writer.write_u32(self.timer);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> std::io::Result<()> {
+        self.enabled = reader.read_bool()?;
+        self.length.load_state(reader)?;
+        self.envelope.load_state(reader)?;
+        self.lfsr = reader.read_u16()?;
+        self.timer = reader.read_u32()?.max(1);
+
+        Ok(())
+    }
+}
+
+pub struct Apu {
+    // registers as written by the cpu, from NR10 to 0xFF2F
+    registers: [u8; REGISTERS_READ_MASK.len()],
+    power: bool,
+    // sound channels
+    channel_1: PulseChannel,
+    channel_2: PulseChannel,
+    channel_3: WaveChannel,
+    channel_4: NoiseChannel,
+    // frame sequencer, the step is the next one to be run
+    frame_sequencer_step: u8,
+    div_bit: bool,
+    // output samples
+    sample_rate: u32,
+    sample_cycles: u64,
+    left_accumulator: f32,
+    right_accumulator: f32,
+    accumulated_cycles: u32,
+    left_capacitor: f32,
+    right_capacitor: f32,
+    capacitor_factor: f32,
+    samples: Vec<f32>,
+}
+
+impl Apu {
+    pub fn new() -> Apu {
+        let mut apu = Apu {
+            registers: [0x00; REGISTERS_READ_MASK.len()],
+            power: false,
+            channel_1: PulseChannel::new(),
+            channel_2: PulseChannel::new(),
+            channel_3: WaveChannel::new(),
+            channel_4: NoiseChannel::new(),
+            frame_sequencer_step: 0,
+            div_bit: false,
+            sample_rate: DEFAULT_SAMPLE_RATE,
+            sample_cycles: 0,
+            left_accumulator: 0.0,
+            right_accumulator: 0.0,
+            accumulated_cycles: 0,
+            left_capacitor: 0.0,
+            right_capacitor: 0.0,
+            capacitor_factor: 0.0,
+            samples: Vec::new(),
+        };
+        apu.set_sample_rate(DEFAULT_SAMPLE_RATE);
+
+        apu
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.sample_rate = sample_rate.max(1);
+        self.capacitor_factor = CAPACITOR_CHARGE_FACTOR.powf(ONE_SECOND_IN_CYCLES as f64 / self.sample_rate as f64) as f32;
+    }
+
+    pub fn get_sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    // the sound of the boot rom has faded out but channel 1 is still enabled, NR52 reads 0xF1
+    pub fn skip_boot(&mut self) {
+        self.channel_1.enabled = true;
+    }
+
+    // returns the interleaved stereo samples (left, right) produced since the last call
+    pub fn take_samples(&mut self) -> Vec<f32> {
+        std::mem::take(&mut self.samples)
+    }
+
+    pub fn run(&mut self, cycles: u8, divider: u8) {
+        let div_bit = ((divider >> FRAME_SEQUENCER_DIV_BIT) & 0x01) != 0;
+        if self.power && self.div_bit && !div_bit {
+            self.clock_frame_sequencer();
+        }
+        self.div_bit = div_bit;
+
+        if self.power {
+            self.channel_1.step(cycles as u32);
+            self.channel_2.step(cycles as u32);
+            self.channel_3.step(cycles as u32);
+            self.channel_4.step(cycles as u32);
+        }
+
+        self.generate_samples(cycles);
+    }
+
+    fn clock_frame_sequencer(&mut self) {
+        match self.frame_sequencer_step {
+            0 | 4 => self.clock_length(),
+            2 | 6 => {
+                self.clock_length();
+                self.channel_1.clock_sweep();
+            }
+            7 => {
+                self.channel_1.envelope.clock();
+                self.channel_2.envelope.clock();
+                self.channel_4.envelope.clock();
+            }
+            _ => {}
+        }
+
+        self.frame_sequencer_step = (self.frame_sequencer_step + 1) % 8;
+    }
+
+    fn clock_length(&mut self) {
+        self.channel_1.clock_length();
+        self.channel_2.clock_length();
+        self.channel_3.clock_length();
+        self.channel_4.clock_length();
+    }
+
+    // the last frame sequencer step clocked the length counters
+    fn is_length_extra_clock(&self) -> bool {
+        (self.frame_sequencer_step & 0x01) != 0
+    }
+
+    fn mix(&self) -> (f32, f32) {
+        let outputs = [
+            self.channel_1.output(),
+            self.channel_2.output(),
+            self.channel_3.output(),
+            self.channel_4.output(),
+        ];
+        let panning = self.registers[(NR51 - APU_REGISTERS_BEGIN) as usize];
+        let volume = self.registers[(NR50 - APU_REGISTERS_BEGIN) as usize];
+
+        let mut left = 0.0;
+        let mut right = 0.0;
+        for (channel_index, output) in outputs.iter().enumerate() {
+            let output = *output as f32 / MAX_VOLUME as f32;
+            if ((panning >> (channel_index + 4)) & 0x01) != 0 {
+                left += output;
+            }
+            if ((panning >> channel_index) & 0x01) != 0 {
+                right += output;
+            }
+        }
+
+        // each channel has the same weight, then master volume is applied
+        let left_volume = (((volume >> 4) & 0x07) + 1) as f32 / 8.0;
+        let right_volume = ((volume & 0x07) + 1) as f32 / 8.0;
+
+        (left / 4.0 * left_volume, right / 4.0 * right_volume)
+    }
+
+    fn generate_samples(&mut self, cycles: u8) {
+        // average the output over the emulated cycles of each sample
+        let (left, right) = self.mix();
+        self.left_accumulator += left * cycles as f32;
+        self.right_accumulator += right * cycles as f32;
+        self.accumulated_cycles += cycles as u32;
+
+        self.sample_cycles += cycles as u64 * self.sample_rate as u64;
+        while self.sample_cycles >= ONE_SECOND_IN_CYCLES as u64 {
+            self.sample_cycles -= ONE_SECOND_IN_CYCLES as u64;
+
+            let (left, right) = if self.accumulated_cycles != 0 {
+                (self.left_accumulator / self.accumulated_cycles as f32, self.right_accumulator / self.accumulated_cycles as f32)
+            } else {
+                (left, right)
+            };
+            self.left_accumulator = 0.0;
+            self.right_accumulator = 0.0;
+            self.accumulated_cycles = 0;
+
+            // remove the DC offset like the output capacitor of the gameboy
+            let left_sample = left - self.left_capacitor;
+            self.left_capacitor = left - left_sample * self.capacitor_factor;
+            let right_sample = right - self.right_capacitor;
+            self.right_capacitor = right - right_sample * self.capacitor_factor;
+
+            // drop the oldest samples if nobody reads them
+            if self.samples.len() >= self.sample_rate as usize * 2 * MAX_BUFFERED_SAMPLES_IN_SECONDS {
+                self.samples.drain(..self.sample_rate as usize);
+            }
+            self.samples.push(left_sample);
+            self.samples.push(right_sample);
+        }
+    }
+
+    pub fn read_register(&self, address: u16) -> u8 {
+        match address {
+            WAVE_RAM_BEGIN..=WAVE_RAM_END => self.channel_3.wave_ram[(address - WAVE_RAM_BEGIN) as usize],
+            NR52 => {
+                ((self.power as u8) << 7)
+                    | REGISTERS_READ_MASK[(NR52 - APU_REGISTERS_BEGIN) as usize]
+                    | ((self.channel_4.enabled as u8) << 3)
+                    | ((self.channel_3.enabled as u8) << 2)
+                    | ((self.channel_2.enabled as u8) << 1)
+                    | (self.channel_1.enabled as u8)
+            }
+            APU_REGISTERS_BEGIN..=APU_REGISTERS_END => {
+                let index = (address - APU_REGISTERS_BEGIN) as usize;
+                self.registers[index] | REGISTERS_READ_MASK[index]
+            }
+            _ => 0xFF,
+        }
+    }
+
+    pub fn write_register(&mut self, address: u16, data: u8) {
+        match address {
+            // wave RAM is always accessible
+            WAVE_RAM_BEGIN..=WAVE_RAM_END => {
+                self.channel_3.wave_ram[(address - WAVE_RAM_BEGIN) as usize] = data;
+            }
+            NR52 => self.set_power((data & 0x80) != 0),
+            // when powered off, only length counters can be written on DMG
+            _ if !self.power => match address {
+                NR11 => self.channel_1.length.load(data),
+                NR21 => self.channel_2.length.load(data),
+                NR31 => self.channel_3.length.load(data),
+                NR41 => self.channel_4.length.load(data),
+                _ => {}
+            },
+            APU_REGISTERS_BEGIN..=APU_REGISTERS_END => {
+                self.registers[(address - APU_REGISTERS_BEGIN) as usize] = data;
+                let extra_clock = self.is_length_extra_clock();
+
+                match address {
+                    NR10 => self.channel_1.write_sweep(data),
+                    NR11 => self.channel_1.write_length(data),
+                    NR12 => self.channel_1.write_envelope(data),
+                    NR13 => self.channel_1.write_frequency_lo(data),
+                    NR14 => self.channel_1.write_control(data, extra_clock),
+                    NR21 => self.channel_2.write_length(data),
+                    NR22 => self.channel_2.write_envelope(data),
+                    NR23 => self.channel_2.write_frequency_lo(data),
+                    NR24 => self.channel_2.write_control(data, extra_clock),
+                    NR30 => self.channel_3.write_dac(data),
+                    NR31 => self.channel_3.length.load(data),
+                    NR32 => self.channel_3.volume_code = (data >> 5) & 0x03,
+                    NR33 => self.channel_3.write_frequency_lo(data),
+                    NR34 => self.channel_3.write_control(data, extra_clock),
+                    NR41 => self.channel_4.length.load(data),
+                    NR42 => self.channel_4.write_envelope(data),
+                    NR43 => self.channel_4.write_polynomial(data),
+                    NR44 => self.channel_4.write_control(data, extra_clock),
+                    _ => { /* NR50 / NR51 are read when mixing, others are unused */ }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn set_power(&mut self, power: bool) {
+        if self.power && !power {
+            // all registers are cleared except length counters and wave RAM
+            let lengths = [
+                self.channel_1.length.counter,
+                self.channel_2.length.counter,
+                self.channel_3.length.counter,
+                self.channel_4.length.counter,
+            ];
+            let wave_ram = self.channel_3.wave_ram;
+
+            self.registers = [0x00; REGISTERS_READ_MASK.len()];
+            self.channel_1 = PulseChannel::new();
+            self.channel_2 = PulseChannel::new();
+            self.channel_3 = WaveChannel::new();
+            self.channel_4 = NoiseChannel::new();
+
+            self.channel_1.length.counter = lengths[0];
+            self.channel_2.length.counter = lengths[1];
+            self.channel_3.length.counter = lengths[2];
+            self.channel_4.length.counter = lengths[3];
+            self.channel_3.wave_ram = wave_ram;
+        } else if !self.power && power {
+            // the frame sequencer restarts from step 0
+            self.frame_sequencer_step = 0;
+        }
+
+        self.power = power;
+    }
+}
+
+impl SaveState for Apu {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bytes(&self.registers);
+        writer.write_bool(self.power);
+        self.channel_1.save_state(writer);
+        self.channel_2.save_state(writer);
+        self.channel_3.save_state(writer);
+        self.channel_4.save_state(writer);
+        writer.write_u8(self.frame_sequencer_step);
+        writer.write_bool(self.div_bit);
+        // output filter
+        writer.write_u64(self.sample_cycles);
+        writer.write_u32(self.left_accumulator.to_bits());
+        writer.write_u32(self.right_accumulator.to_bits());
+        writer.write_u32(self.accumulated_cycles);
+        writer.write_u32(self.left_capacitor.to_bits());
+        writer.write_u32(self.right_capacitor.to_bits());
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> std::io::Result<()> {
+        let mut registers = [0x00; REGISTERS_READ_MASK.len()];
+        reader.read_bytes_into(&mut registers)?;
+        self.power = reader.read_bool()?;
+
+        // channel settings are decoded from the registers, then internal counters are restored
+        self.registers = registers;
+        let nr10 = registers[(NR10 - APU_REGISTERS_BEGIN) as usize];
+        self.channel_1.sweep.settings_from_byte(nr10);
+        for (channel, offset) in [(&mut self.channel_1, NR11), (&mut self.channel_2, NR21)] {
+            let index = (offset - APU_REGISTERS_BEGIN) as usize;
+            channel.duty = registers[index] >> 6;
+            channel.envelope.settings_from_byte(registers[index + 1]);
+        }
+        self.channel_3.dac_enabled = (registers[(NR30 - APU_REGISTERS_BEGIN) as usize] & 0x80) != 0;
+        self.channel_3.volume_code = (registers[(NR32 - APU_REGISTERS_BEGIN) as usize] >> 5) & 0x03;
+        self.channel_4.envelope.settings_from_byte(registers[(NR42 - APU_REGISTERS_BEGIN) as usize]);
+        self.channel_4.write_polynomial(registers[(NR43 - APU_REGISTERS_BEGIN) as usize]);
+
+        self.channel_1.load_state(reader)?;
+        self.channel_2.load_state(reader)?;
+        self.channel_3.load_state(reader)?;
+        self.channel_4.load_state(reader)?;
+        self.frame_sequencer_step = reader.read_u8()? % 8;
+        self.div_bit = reader.read_bool()?;
+        // output filter
+        self.sample_cycles = reader.read_u64()?;
+        self.left_accumulator = f32::from_bits(reader.read_u32()?);
+        self.right_accumulator = f32::from_bits(reader.read_u32()?);
+        self.accumulated_cycles = reader.read_u32()?;
+        self.left_capacitor = f32::from_bits(reader.read_u32()?);
+        self.right_capacitor = f32::from_bits(reader.read_u32()?);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod apu_tests {
+    use super::*;
+
+    fn run_frame_sequencer(apu: &mut Apu, steps: usize) {
+        // each falling edge of DIV bit 4 clocks the frame sequencer once
+        for _ in 0..steps {
+            apu.run(4, 0x10);
+            apu.run(4, 0x00);
+        }
+    }
+
+    #[test]
+    fn test_read_masks() {
+        let mut apu = Apu::new();
+        apu.write_register(NR52, 0x80);
+
+        apu.write_register(NR10, 0x00);
+        assert_eq!(apu.read_register(NR10), 0x80);
+        apu.write_register(NR11, 0x80);
+        assert_eq!(apu.read_register(NR11), 0xBF);
+        apu.write_register(NR12, 0xF3);
+        assert_eq!(apu.read_register(NR12), 0xF3);
+        apu.write_register(NR13, 0x12);
+        assert_eq!(apu.read_register(NR13), 0xFF);
+        apu.write_register(NR14, 0x40);
+        assert_eq!(apu.read_register(NR14), 0xFF);
+        apu.write_register(NR32, 0x20);
+        assert_eq!(apu.read_register(NR32), 0xBF);
+        assert_eq!(apu.read_register(0xFF15), 0xFF);
+        assert_eq!(apu.read_register(0xFF27), 0xFF);
+        assert_eq!(apu.read_register(NR52) & 0xF0, 0xF0);
+    }
+
+    #[test]
+    fn test_power_off() {
+        let mut apu = Apu::new();
+
+        // registers are not writable when powered off
+        apu.write_register(NR50, 0x77);
+        assert_eq!(apu.read_register(NR50), 0x00);
+        assert_eq!(apu.read_register(NR52), 0x70);
+
+        apu.write_register(NR52, 0x80);
+        apu.write_register(NR50, 0x77);
+        apu.write_register(0xFF30, 0xA5);
+        assert_eq!(apu.read_register(NR50), 0x77);
+
+        // power off clears the registers but not the wave RAM
+        apu.write_register(NR52, 0x00);
+        assert_eq!(apu.read_register(NR50), 0x00);
+        assert_eq!(apu.read_register(0xFF30), 0xA5);
+    }
+
+    #[test]
+    fn test_trigger_and_length() {
+        let mut apu = Apu::new();
+        apu.write_register(NR52, 0x80);
+
+        // channel is not enabled when its DAC is off
+        apu.write_register(NR12, 0x00);
+        apu.write_register(NR14, 0x80);
+        assert_eq!(apu.read_register(NR52) & 0x01, 0x00);
+
+        // length of 62 -> 2 length clocks
+        apu.write_register(NR12, 0xF0);
+        apu.write_register(NR11, 62);
+        apu.write_register(NR14, 0xC0);
+        assert_eq!(apu.read_register(NR52) & 0x01, 0x01);
+
+        run_frame_sequencer(&mut apu, 1);
+        assert_eq!(apu.read_register(NR52) & 0x01, 0x01);
+        run_frame_sequencer(&mut apu, 2);
+        assert_eq!(apu.read_register(NR52) & 0x01, 0x00);
+    }
+
+    #[test]
+    fn test_sweep_overflow() {
+        let mut apu = Apu::new();
+        apu.write_register(NR52, 0x80);
+
+        // frequency increases until it overflows
+        apu.write_register(NR10, 0x11);
+        apu.write_register(NR12, 0xF0);
+        apu.write_register(NR13, 0x00);
+        apu.write_register(NR14, 0x84);
+        assert_eq!(apu.read_register(NR52) & 0x01, 0x01);
+
+        run_frame_sequencer(&mut apu, 8);
+        assert_eq!(apu.read_register(NR52) & 0x01, 0x00);
+    }
+
+    #[test]
+    fn test_noise_lfsr() {
+        let mut channel = NoiseChannel::new();
+        channel.write_envelope(0xF0);
+        channel.write_control(0x80, false);
+        assert_eq!(channel.lfsr, 0x7FFF);
+
+        // first shift: bits 0 and 1 are set, the xor result is 0
+        channel.step(channel.timer);
+        assert_eq!(channel.lfsr, 0x3FFF);
+        assert_eq!(channel.output(), 0);
+    }
+
+    #[test]
+    fn test_wave_output() {
+        let mut apu = Apu::new();
+        apu.write_register(NR52, 0x80);
+        apu.write_register(0xFF30, 0xA5);
+        apu.write_register(NR30, 0x80);
+        apu.write_register(NR32, 0x20);
+        apu.write_register(NR34, 0x80);
+
+        assert_eq!(apu.channel_3.output(), 0x0A);
+        apu.channel_3.step(apu.channel_3.timer);
+        assert_eq!(apu.channel_3.output(), 0x05);
+
+        // 50 % volume
+        apu.write_register(NR32, 0x40);
+        assert_eq!(apu.channel_3.output(), 0x02);
+    }
+
+    #[test]
+    fn test_sample_rate() {
+        let mut apu = Apu::new();
+        apu.set_sample_rate(32768);
+
+        // one second of emulation
+        for _ in 0..ONE_SECOND_IN_CYCLES / 16 {
+            apu.run(16, 0x00);
+        }
+
+        assert_eq!(apu.take_samples().len(), 32768 * 2);
+        assert!(apu.take_samples().is_empty());
+    }
+}
@@ -1,4 +1,151 @@
 use crate::soc::peripheral::BOOT_ROM_SIZE;
+use crate::savestate::{SaveState, StateWriter, StateReader};
+use crate::error::EmulatorError;
+
+/// Replacement of the dmg boot rom written for this emulator and distributed under its
+/// license. It scrolls the logo of the cartridge header down the screen, plays the startup
+/// sound, then hands over to the game with the registers left by the original boot rom.
+pub const BUILTIN_BOOT_ROM: [u8; BOOT_ROM_SIZE as usize] = [
+    // set up the stack and clear the vram
+    0x31, 0xFE, 0xFF,   // 0x0000 ld sp,0xFFFE
+    0xAF,               // 0x0003 xor a
+    0x21, 0x00, 0x80,   // 0x0004 ld hl,0x8000
+    0x22,               // 0x0007 clear_vram: ld (hl+),a
+    0xCB, 0x6C,         // 0x0008 bit 5,h
+    0x28, 0xFB,         // 0x000A jr z,clear_vram
+
+    // power on the apu, channel 1 plays the startup sound on both outputs
+    0x3E, 0x80,         // 0x000C ld a,0x80
+    0xE0, 0x26,         // 0x000E ldh (NR52),a
+    0xE0, 0x11,         // 0x0010 ldh (NR11),a
+    0x3E, 0xF3,         // 0x0012 ld a,0xF3
+    0xE0, 0x12,         // 0x0014 ldh (NR12),a
+    0xE0, 0x25,         // 0x0016 ldh (NR51),a
+    0x3E, 0x77,         // 0x0018 ld a,0x77
+    0xE0, 0x24,         // 0x001A ldh (NR50),a
+    0x3E, 0xFC,         // 0x001C ld a,0xFC
+    0xE0, 0x47,         // 0x001E ldh (BGP),a
+
+    // scale the logo of the cartridge header up by two, in tiles 1 to 24
+    0x11, 0x04, 0x01,   // 0x0020 ld de,0x0104
+    0x21, 0x10, 0x80,   // 0x0023 ld hl,0x8010
+    0x1A,               // 0x0026 logo_byte: ld a,(de)
+    0xCD, 0x9D, 0x00,   // 0x0027 call expand_nibble
+    0x1A,               // 0x002A ld a,(de)
+    0xCB, 0x37,         // 0x002B swap a
+    0xCD, 0x9D, 0x00,   // 0x002D call expand_nibble
+    0x13,               // 0x0030 inc de
+    0x7B,               // 0x0031 ld a,e
+    0xFE, 0x34,         // 0x0032 cp 0x34
+    0x20, 0xF0,         // 0x0034 jr nz,logo_byte
+
+    // two rows of 12 tiles in the middle of the background
+    0x3E, 0x01,         // 0x0036 ld a,0x01
+    0x21, 0x04, 0x99,   // 0x0038 ld hl,0x9904
+    0x0E, 0x0C,         // 0x003B map_row: ld c,12
+    0x22,               // 0x003D map_tile: ld (hl+),a
+    0x3C,               // 0x003E inc a
+    0x0D,               // 0x003F dec c
+    0x20, 0xFB,         // 0x0040 jr nz,map_tile
+    0x2E, 0x24,         // 0x0042 ld l,0x24
+    0xFE, 0x19,         // 0x0044 cp 25
+    0x20, 0xF3,         // 0x0046 jr nz,map_row
+
+    // turn the lcd on, the logo scrolls down from the top of the screen
+    0x3E, 0x64,         // 0x0048 ld a,0x64
+    0xE0, 0x42,         // 0x004A ldh (SCY),a
+    0x3E, 0x91,         // 0x004C ld a,0x91
+    0xE0, 0x40,         // 0x004E ldh (LCDC),a
+    0xCD, 0xB6, 0x00,   // 0x0050 scroll: call wait_frame
+    0xF0, 0x42,         // 0x0053 ldh a,(SCY)
+    0x3D,               // 0x0055 dec a
+    0xE0, 0x42,         // 0x0056 ldh (SCY),a
+    0x20, 0xF6,         // 0x0058 jr nz,scroll
+
+    // startup sound: two notes on channel 1, then a short pause
+    0x3E, 0x83,         // 0x005A ld a,0x83
+    0xE0, 0x13,         // 0x005C ldh (NR13),a
+    0x3E, 0x87,         // 0x005E ld a,0x87
+    0xE0, 0x14,         // 0x0060 ldh (NR14),a
+    0x06, 0x06,         // 0x0062 ld b,6
+    0xCD, 0xAF, 0x00,   // 0x0064 call wait_frames
+    0x3E, 0xC1,         // 0x0067 ld a,0xC1
+    0xE0, 0x13,         // 0x0069 ldh (NR13),a
+    0x3E, 0x87,         // 0x006B ld a,0x87
+    0xE0, 0x14,         // 0x006D ldh (NR14),a
+    0x06, 0x3C,         // 0x006F ld b,60
+    0xCD, 0xAF, 0x00,   // 0x0071 call wait_frames
+
+    // sum the header like the original boot rom, the flags of the last addition and
+    // the registers are left as the game expects them
+    0x21, 0x34, 0x01,   // 0x0074 ld hl,0x0134
+    0x06, 0x19,         // 0x0077 ld b,0x19
+    0x78,               // 0x0079 ld a,b
+    0x86,               // 0x007A checksum: add (hl)
+    0x23,               // 0x007B inc hl
+    0x05,               // 0x007C dec b
+    0x20, 0xFB,         // 0x007D jr nz,checksum
+    0x86,               // 0x007F add (hl)
+    0x0E, 0x13,         // 0x0080 ld c,0x13
+    0x11, 0xD8, 0x00,   // 0x0082 ld de,0x00D8
+    0xF5,               // 0x0085 push af
+
+    // hand over on line 153 with the divider of the original boot rom: it is reset
+    // on line 57 and reads 0xAB after the 96 lines which separate them
+    0xF0, 0x44,         // 0x0086 wait_line_57: ldh a,(LY)
+    0xFE, 0x39,         // 0x0088 cp 57
+    0x20, 0xFA,         // 0x008A jr nz,wait_line_57
+    0xE0, 0x04,         // 0x008C ldh (DIV),a
+    0xF0, 0x44,         // 0x008E wait_line_152: ldh a,(LY)
+    0xFE, 0x98,         // 0x0090 cp 152
+    0x20, 0xFA,         // 0x0092 jr nz,wait_line_152
+    0xF0, 0x44,         // 0x0094 wait_line_153: ldh a,(LY)
+    0xFE, 0x98,         // 0x0096 cp 152
+    0x28, 0xFA,         // 0x0098 jr z,wait_line_153
+    0xF1,               // 0x009A pop af
+    0x18, 0x5F,         // 0x009B jr done
+
+    // expand the high nibble of a to 8 pixels, written in two rows
+    0x06, 0x04,         // 0x009D expand_nibble: ld b,4
+    0x17,               // 0x009F expand_bit: rla
+    0xF5,               // 0x00A0 push af
+    0xCB, 0x11,         // 0x00A1 rl c
+    0xF1,               // 0x00A3 pop af
+    0xCB, 0x11,         // 0x00A4 rl c
+    0x05,               // 0x00A6 dec b
+    0x20, 0xF6,         // 0x00A7 jr nz,expand_bit
+    0x79,               // 0x00A9 ld a,c
+    0x22,               // 0x00AA ld (hl+),a
+    0x23,               // 0x00AB inc hl
+    0x22,               // 0x00AC ld (hl+),a
+    0x23,               // 0x00AD inc hl
+    0xC9,               // 0x00AE ret
+
+    // wait for b frames
+    0xCD, 0xB6, 0x00,   // 0x00AF wait_frames: call wait_frame
+    0x05,               // 0x00B2 dec b
+    0x20, 0xFA,         // 0x00B3 jr nz,wait_frames
+    0xC9,               // 0x00B5 ret
+
+    // wait for the beginning of the next vertical blanking period
+    0xF0, 0x44,         // 0x00B6 wait_frame: ldh a,(LY)
+    0xFE, 0x90,         // 0x00B8 cp 144
+    0x28, 0xFA,         // 0x00BA jr z,wait_frame
+    0xF0, 0x44,         // 0x00BC wait_vblank: ldh a,(LY)
+    0xFE, 0x90,         // 0x00BE cp 144
+    0x20, 0xFA,         // 0x00C0 jr nz,wait_vblank
+    0xC9,               // 0x00C2 ret
+
+    // unused
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+
+    // unmap the boot rom, the next instruction is the entry point of the cartridge
+    0x3E, 0x01,         // 0x00FC done: ld a,0x01
+    0xE0, 0x50,         // 0x00FE ldh (BOOT),a
+];
 
 pub struct BootRom {
     rom: [u8; BOOT_ROM_SIZE as usize],
@@ -17,12 +164,16 @@ impl BootRom {
         self.rom[address as usize]
     }
 
-    pub fn load(&mut self, boot_rom: &[u8]){
-        let mut rom_data = [0x00; BOOT_ROM_SIZE as usize];
-        rom_data.copy_from_slice(boot_rom);
-        self.rom = rom_data;
+    pub fn load(&mut self, boot_rom: &[u8]) -> Result<(), EmulatorError> {
+        if boot_rom.len() != BOOT_ROM_SIZE as usize {
+            return Err(EmulatorError::InvalidBootRomSize(boot_rom.len()));
+        }
+
+        self.rom.copy_from_slice(boot_rom);
         // enable memory once load is complete
         self.enabled = true;
+
+        Ok(())
     }
 
     pub fn set_state(&mut self, state: bool) {
@@ -32,4 +183,17 @@ impl BootRom {
     pub fn get_state(&self) -> bool {
         self.enabled
     }
+}
+
+// the boot rom content is not part of the state, only its mapping
+impl SaveState for BootRom {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bool(self.enabled);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> std::io::Result<()> {
+        self.enabled = reader.read_bool()?;
+
+        Ok(())
+    }
 }
\ No newline at end of file
@@ -1,8 +1,10 @@
 use crate::soc::peripheral::{VRAM_SIZE, OAM_SIZE};
 use crate::soc::peripheral::nvic::{Nvic, InterruptSources};
+use crate::savestate::{SaveState, StateWriter, StateReader, invalid_state};
 
 const HORIZONTAL_BLANK_CYCLES: u16 = 204;
 const VERTICAL_BLANK_CYCLES: u16 = 4560;
+const LAST_VERTICAL_BLANK_LINE: u16 = 9;
 const OAM_SCAN_CYCLES: u16 = 80;
 const DRAW_PIXEL_CYCLES: u16 = 172;
 const ONE_LINE_CYCLES: u16 = HORIZONTAL_BLANK_CYCLES + OAM_SCAN_CYCLES + DRAW_PIXEL_CYCLES;
@@ -56,6 +58,35 @@ impl Palette {
             color_3: PixelColor::BLACK,
         }
     }
+
+    fn to_byte(self) -> u8 {
+        color_to_index(self.color_0)
+            | (color_to_index(self.color_1) << 2)
+            | (color_to_index(self.color_2) << 4)
+            | (color_to_index(self.color_3) << 6)
+    }
+}
+
+impl PixelColor {
+    /// Color of a shade of the frame buffer, `None` when the value isn't one of the four shades.
+    pub fn from_shade(shade: u8) -> Option<PixelColor> {
+        match shade {
+            255 => Some(PixelColor::WHITE),
+            192 => Some(PixelColor::LIGHT_GRAY),
+            96 => Some(PixelColor::DARK_GRAY),
+            0 => Some(PixelColor::BLACK),
+            _ => None,
+        }
+    }
+}
+
+pub fn color_to_index(color: PixelColor) -> u8 {
+    match color {
+        PixelColor::WHITE => 0,
+        PixelColor::LIGHT_GRAY => 1,
+        PixelColor::DARK_GRAY => 2,
+        PixelColor::BLACK => 3,
+    }
 }
 
 macro_rules! set_palette {
@@ -158,6 +189,8 @@ pub struct Gpu {
     vblank_line: u16,
     window_flag: bool,
     window_line_counter: u8,
+    // set when the frame is complete, until the emulator reads it
+    vblank_reached: bool,
 
     // ****** OUTPUT FRAME BUFFER *******
     pub frame_buffer: [u8; SCREEN_WIDTH * SCREEN_HEIGHT],
@@ -203,6 +236,7 @@ impl Gpu {
             vblank_line: 0,
             window_flag: false,
             window_line_counter: 0,
+            vblank_reached: false,
 
             frame_buffer: [0; SCREEN_WIDTH * SCREEN_HEIGHT],
         }
@@ -224,6 +258,22 @@ impl Gpu {
         self.oam[address] = data;
     }
 
+    // true once after the gpu has entered the vertical blanking period
+    pub fn take_vblank(&mut self) -> bool {
+        std::mem::take(&mut self.vblank_reached)
+    }
+
+    // the boot rom hands over to the game during the vertical blanking period, `line` is the
+    // value read in LY and `vblank_line` the number of lines already spent in the period
+    pub fn skip_to_vblank(&mut self, line: u8, vblank_line: u16) {
+        self.mode = GpuMode::VerticalBlank;
+        self.new_mode_flag = false;
+        self.cycles = vblank_line * ONE_LINE_CYCLES;
+        self.vblank_line = vblank_line;
+        self.current_line = line;
+        self.line_compare_state = self.current_line == self.compare_line;
+    }
+
     pub fn run(&mut self, cycles: u8, nvic: &mut Nvic) {
         if self.lcd_display_enabled {
             // update GPU cycles counter
@@ -251,6 +301,9 @@ impl Gpu {
                             // go to next gpu mode
                             self.mode = GpuMode::OAMScan;
                         } else {
+                            // the first line of the vertical blanking period is line 144
+                            self.current_line += 1;
+                            self.compare_line(nvic);
                             // reset new mode flag
                             self.new_mode_flag = true;
                             // go to next gpu mode
@@ -263,6 +316,7 @@ impl Gpu {
                     if self.new_mode_flag {
                         self.new_mode_flag = false;
                         nvic.set_interrupt(InterruptSources::VBLANK);
+                        self.vblank_reached = true;
 
                         if self.vblank_interrupt_enabled {
                             nvic.set_interrupt(InterruptSources::STAT);
@@ -276,6 +330,12 @@ impl Gpu {
                         if self.window_flag { self.window_line_counter += 1 }
 
                         self.compare_line(nvic);
+
+                        // LY already reads 0 during most of line 153
+                        if self.vblank_line == LAST_VERTICAL_BLANK_LINE {
+                            self.current_line = 0;
+                            self.compare_line(nvic);
+                        }
                     }
 
                     // we reached the end of the mode
@@ -683,6 +743,18 @@ impl Gpu {
         self.window_x_offset
     }
 
+    pub fn get_background_palette(&self) -> u8 {
+        self.background_palette.to_byte()
+    }
+
+    pub fn get_object_palette_0(&self) -> u8 {
+        self.object_palette_0.to_byte()
+    }
+
+    pub fn get_object_palette_1(&self) -> u8 {
+        self.object_palette_1.to_byte()
+    }
+
     pub fn set_background_palette(&mut self, data: u8) {
         set_palette!(self.background_palette.color_0, data, 0);
         set_palette!(self.background_palette.color_1, data, 1);
@@ -705,6 +777,73 @@ impl Gpu {
     }
 }
 
+impl SaveState for Gpu {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bytes(&self.vram);
+        writer.write_bytes(&self.oam);
+        // lcd registers
+        writer.write_u8(self.control_to_byte());
+        writer.write_u8(self.status_to_byte());
+        writer.write_u8(match self.mode {
+            GpuMode::HorizontalBlank => 0,
+            GpuMode::VerticalBlank => 1,
+            GpuMode::OAMScan => 2,
+            GpuMode::DrawPixel => 3,
+        });
+        writer.write_u8(self.viewport_y_offset);
+        writer.write_u8(self.viewport_x_offset);
+        writer.write_u8(self.current_line);
+        writer.write_u8(self.compare_line);
+        writer.write_u8(self.background_palette.to_byte());
+        writer.write_u8(self.object_palette_0.to_byte());
+        writer.write_u8(self.object_palette_1.to_byte());
+        writer.write_u8(self.window_x_offset);
+        writer.write_u8(self.window_y_offset);
+        // internal parameters
+        writer.write_u16(self.cycles);
+        writer.write_bool(self.new_mode_flag);
+        writer.write_u16(self.vblank_line);
+        writer.write_bool(self.window_flag);
+        writer.write_u8(self.window_line_counter);
+        writer.write_bytes(&self.frame_buffer);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> std::io::Result<()> {
+        reader.read_bytes_into(&mut self.vram)?;
+        reader.read_bytes_into(&mut self.oam)?;
+        // lcd registers
+        self.control_from_byte(reader.read_u8()?);
+        let status = reader.read_u8()?;
+        self.status_from_byte(status);
+        self.line_compare_state = ((status >> 2) & 0x01) != 0;
+        self.mode = match reader.read_u8()? {
+            0 => GpuMode::HorizontalBlank,
+            1 => GpuMode::VerticalBlank,
+            2 => GpuMode::OAMScan,
+            3 => GpuMode::DrawPixel,
+            _ => return Err(invalid_state("unknown gpu mode")),
+        };
+        self.viewport_y_offset = reader.read_u8()?;
+        self.viewport_x_offset = reader.read_u8()?;
+        self.current_line = reader.read_u8()?;
+        self.compare_line = reader.read_u8()?;
+        self.set_background_palette(reader.read_u8()?);
+        self.set_object_palette_0(reader.read_u8()?);
+        self.set_object_palette_1(reader.read_u8()?);
+        self.window_x_offset = reader.read_u8()?;
+        self.window_y_offset = reader.read_u8()?;
+        // internal parameters
+        self.cycles = reader.read_u16()?;
+        self.new_mode_flag = reader.read_bool()?;
+        self.vblank_line = reader.read_u16()?;
+        self.window_flag = reader.read_bool()?;
+        self.window_line_counter = reader.read_u8()?;
+        reader.read_bytes_into(&mut self.frame_buffer)?;
+
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod gpu_tests {
     use super::*;
@@ -1059,6 +1198,7 @@ mod gpu_tests {
         assert_eq!(gpu.background_palette.color_2, PixelColor::LIGHT_GRAY);
         assert_eq!(gpu.background_palette.color_1, PixelColor::WHITE);
         assert_eq!(gpu.background_palette.color_0, PixelColor::BLACK);
+        assert_eq!(gpu.get_background_palette(), 0b10010011);
     }
 
     #[test]
@@ -1071,6 +1211,7 @@ mod gpu_tests {
         assert_eq!(gpu.object_palette_0.color_2, PixelColor::LIGHT_GRAY);
         assert_eq!(gpu.object_palette_0.color_1, PixelColor::WHITE);
         assert_eq!(gpu.object_palette_0.color_0, PixelColor::BLACK);
+        assert_eq!(gpu.get_object_palette_0(), 0b10010011);
 
         gpu.set_object_palette_1(0b11010010);
 
@@ -1078,5 +1219,6 @@ mod gpu_tests {
         assert_eq!(gpu.object_palette_1.color_2, PixelColor::LIGHT_GRAY);
         assert_eq!(gpu.object_palette_1.color_1, PixelColor::WHITE);
         assert_eq!(gpu.object_palette_1.color_0, PixelColor::DARK_GRAY);
+        assert_eq!(gpu.get_object_palette_1(), 0b11010010);
     }
 }
\ No newline at end of file
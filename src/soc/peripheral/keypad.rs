@@ -1,3 +1,7 @@
+use crate::soc::peripheral::nvic::{Nvic, InterruptSources};
+use crate::savestate::{SaveState, StateWriter, StateReader};
+
+#[derive(Copy, Clone, PartialEq, Debug)]
 pub enum GameBoyKey {
     START,
     SELECT,
@@ -22,6 +26,8 @@ pub struct Keypad {
     up: bool,
     left: bool,
     right: bool,
+    // P10-P13 input lines seen at the last run
+    previous_lines: u8,
 }
 
 impl Keypad {
@@ -39,7 +45,38 @@ impl Keypad {
             up: false,
             left: false,
             right: false,
+            previous_lines: 0x0F,
+        }
+    }
+
+    pub fn run(&mut self, nvic: &mut Nvic) {
+        // the joypad interrupt is requested when a selected input line goes from high to low
+        let lines = self.get_lines();
+        if self.previous_lines & !lines != 0 {
+            nvic.set_interrupt(InterruptSources::JOYPAD);
+        }
+        self.previous_lines = lines;
+    }
+
+    // a line is low when one of the selected buttons wired on it is pressed
+    fn get_lines(&self) -> u8 {
+        let mut pressed = 0x00;
+
+        if self.action_buttons {
+            pressed |= (self.start as u8) << 3
+                | (self.select as u8) << 2
+                | (self.b as u8) << 1
+                | (self.a as u8);
         }
+
+        if self.direction_buttons {
+            pressed |= (self.down as u8) << 3
+                | (self.up as u8) << 2
+                | (self.left as u8) << 1
+                | (self.right as u8);
+        }
+
+        !pressed & 0x0F
     }
 
     pub fn control(&mut self, data: u8) {
@@ -48,26 +85,10 @@ impl Keypad {
     }
 
     pub fn get(&self) -> u8 {
-        match (self.action_buttons, self.direction_buttons) {
-            (true, false) => {
-                (!self.action_buttons as u8) << 5
-                | (!self.direction_buttons as u8) << 4
-                | (!self.start as u8) << 3
-                | (!self.select as u8) << 2
-                | (!self.b as u8) << 1
-                | (!self.a as u8) << 0
-            },
-            (false, true) => {
-                (!self.action_buttons as u8) << 5
-                | (!self.direction_buttons as u8) << 4
-                | (!self.down as u8) << 3
-                | (!self.up as u8) << 2
-                | (!self.left as u8) << 1
-                | (!self.right as u8) << 0  
-            },
-            (false, false) => 0x00, // nothing to return
-            (true, true) => panic!("Cannot read action and direction buttons at the same time"),
-        }
+        // when both button groups are selected, their lines are combined
+        (!self.action_buttons as u8) << 5
+        | (!self.direction_buttons as u8) << 4
+        | self.get_lines()
     }
 
     pub fn set(&mut self, key: GameBoyKey, value: bool) {
@@ -84,6 +105,38 @@ impl Keypad {
     }
 }
 
+impl SaveState for Keypad {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bool(self.action_buttons);
+        writer.write_bool(self.direction_buttons);
+        writer.write_bool(self.start);
+        writer.write_bool(self.select);
+        writer.write_bool(self.b);
+        writer.write_bool(self.a);
+        writer.write_bool(self.down);
+        writer.write_bool(self.up);
+        writer.write_bool(self.left);
+        writer.write_bool(self.right);
+        writer.write_u8(self.previous_lines);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> std::io::Result<()> {
+        self.action_buttons = reader.read_bool()?;
+        self.direction_buttons = reader.read_bool()?;
+        self.start = reader.read_bool()?;
+        self.select = reader.read_bool()?;
+        self.b = reader.read_bool()?;
+        self.a = reader.read_bool()?;
+        self.down = reader.read_bool()?;
+        self.up = reader.read_bool()?;
+        self.left = reader.read_bool()?;
+        self.right = reader.read_bool()?;
+        self.previous_lines = reader.read_u8()?;
+
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod keypad_tests {
     use super::*;
@@ -113,5 +166,44 @@ mod keypad_tests {
         keypad.set(GameBoyKey::LEFT, true);
         keypad.set(GameBoyKey::RIGHT, false);
         assert_eq!(keypad.get(), 0x25);
+
+        // both groups selected
+        keypad.control(0x00);
+        keypad.set(GameBoyKey::A, true);
+        assert_eq!(keypad.get(), 0x04);
+
+        // no group selected
+        keypad.control(0x30);
+        assert_eq!(keypad.get(), 0x3F);
+    }
+
+    #[test]
+    fn test_joypad_interrupt() {
+        let mut keypad = Keypad::new();
+        let mut nvic = Nvic::new();
+
+        // buttons of an unselected group do not request the interrupt
+        keypad.control(0x20);
+        keypad.set(GameBoyKey::A, true);
+        keypad.run(&mut nvic);
+        assert_eq!(nvic.interrupt_flag, 0x00);
+
+        // a pressed button requests the interrupt once
+        keypad.set(GameBoyKey::UP, true);
+        keypad.run(&mut nvic);
+        assert_eq!(nvic.interrupt_flag, 1 << InterruptSources::JOYPAD as u8);
+        nvic.set_it_flag(0x00);
+        keypad.run(&mut nvic);
+        assert_eq!(nvic.interrupt_flag, 0x00);
+
+        // releasing a button does not request the interrupt
+        keypad.set(GameBoyKey::UP, false);
+        keypad.run(&mut nvic);
+        assert_eq!(nvic.interrupt_flag, 0x00);
+
+        // selecting a group with a pressed button drives its line low
+        keypad.control(0x10);
+        keypad.run(&mut nvic);
+        assert_eq!(nvic.interrupt_flag, 1 << InterruptSources::JOYPAD as u8);
     }
 }
\ No newline at end of file
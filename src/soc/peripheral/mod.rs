@@ -1,16 +1,26 @@
 pub mod gpu;
 pub mod nvic;
+pub mod apu;
+pub mod serial;
 mod timer;
 pub mod keypad;
 mod bootrom;
 
 use gpu::Gpu;
+use apu::Apu;
+use serial::{Serial, SerialDevice};
 use nvic::{Nvic, InterruptSources};
 use timer::Timer;
 use bootrom::BootRom;
+pub use bootrom::BUILTIN_BOOT_ROM;
 use keypad::Keypad;
 
-use crate::cartridge::Cartridge;
+use crate::cartridge::{Cartridge, CartridgeHeader};
+use crate::savestate::{SaveState, StateWriter, StateReader};
+use crate::error::EmulatorError;
+use crate::soc::model::GameBoyModel;
+use std::cell::Cell;
+use std::path::PathBuf;
 
 pub const BOOT_ROM_BEGIN: u16 = 0x0000;
 pub const BOOT_ROM_END: u16 = 0x00FF;
@@ -54,9 +64,28 @@ pub const ZERO_PAGE_SIZE: u16 = ZERO_PAGE_END - ZERO_PAGE_BEGIN + 1;
 
 pub const INTERRUPT_ENABLE_REGISTER: u16 = 0xFFFF;
 
+pub const JOYPAD_REGISTER: u16 = 0xFF00;
+pub const DIVIDER_REGISTER: u16 = 0xFF04;
+
+// io registers left by the boot rom, the apu is powered on before its registers are written
+const POST_BOOT_IO_REGISTERS: [(u16, u8); 38] = [
+    (0xFF00, 0xCF), (0xFF01, 0x00), (0xFF02, 0x7E),
+    (0xFF05, 0x00), (0xFF06, 0x00), (0xFF07, 0xF8), (0xFF0F, 0xE1),
+    (0xFF26, 0xF1),
+    (0xFF10, 0x80), (0xFF11, 0xBF), (0xFF12, 0xF3), (0xFF13, 0xFF), (0xFF14, 0x3F),
+    (0xFF16, 0x3F), (0xFF17, 0x00), (0xFF18, 0xFF), (0xFF19, 0x3F),
+    (0xFF1A, 0x7F), (0xFF1B, 0xFF), (0xFF1C, 0x9F), (0xFF1D, 0xFF), (0xFF1E, 0x3F),
+    (0xFF20, 0xFF), (0xFF21, 0x00), (0xFF22, 0x00), (0xFF23, 0x3F),
+    (0xFF24, 0x77), (0xFF25, 0xF3),
+    (0xFF40, 0x91), (0xFF41, 0x85), (0xFF42, 0x00), (0xFF43, 0x00), (0xFF45, 0x00),
+    (0xFF47, 0xFC), (0xFF48, 0xFF), (0xFF49, 0xFF), (0xFF4A, 0x00), (0xFF4B, 0x00),
+];
+
 pub const VBLANK_VECTOR: u16 = 0x40;
 pub const LCDSTAT_VECTOR: u16 = 0x48;
 pub const TIMER_VECTOR: u16 = 0x50;
+pub const SERIAL_VECTOR: u16 = 0x58;
+pub const JOYPAD_VECTOR: u16 = 0x60;
 
 pub trait IoAccess {
     fn read(&self, address: u16) -> u8;
@@ -81,12 +110,16 @@ pub struct Peripheral {
     zero_page: [u8; ZERO_PAGE_SIZE as usize],
     pub gpu: Gpu,
     pub nvic: Nvic,
+    pub apu: Apu,
+    serial: Serial,
     timer: Timer,
     pub keypad: Keypad,
     // dma
     dma_cycles: u8,
     dma_start_adress: u16,
     dma_enabled: bool,
+    // last access the bus could not handle
+    fault: Cell<Option<EmulatorError>>,
 }
 
 impl Peripheral {
@@ -98,11 +131,14 @@ impl Peripheral {
             zero_page: [0xFF; ZERO_PAGE_SIZE as usize],
             gpu: Gpu::new(),
             nvic: Nvic::new(),
+            apu: Apu::new(),
+            serial: Serial::new(),
             timer: Timer::new(),
             keypad: Keypad::new(),
             dma_cycles: 0,
             dma_start_adress: 0xFFFF,
             dma_enabled: false,
+            fault: Cell::new(None),
         }
     }
 
@@ -110,6 +146,12 @@ impl Peripheral {
         // run the timer
         self.timer.run(runned_cycles, &mut self.nvic);
 
+        // run the serial port
+        self.serial.run(runned_cycles, &mut self.nvic);
+
+        // check the joypad input lines
+        self.keypad.run(&mut self.nvic);
+
         // run the DMA
         if self.dma_enabled {
             // copy data
@@ -132,92 +174,122 @@ impl Peripheral {
         // run the GPU 
         self.gpu.run(runned_cycles, &mut self.nvic);
 
+        // run the APU, its frame sequencer is clocked by the timer divider
+        self.apu.run(runned_cycles, self.timer.get_divider());
+
         // run the cartridge
         self.cartridge.run(runned_cycles);
     }
 
-    pub fn load_bootrom(&mut self, boot_rom: &[u8]){
-        self.boot_rom.load(boot_rom);
+    pub fn load_bootrom(&mut self, boot_rom: &[u8]) -> Result<(), EmulatorError> {
+        self.boot_rom.load(boot_rom)
+    }
+
+    // start without boot rom, with the io registers it leaves on the model
+    pub fn skip_boot(&mut self, model: GameBoyModel) {
+        self.boot_rom.set_state(false);
+
+        for &(address, data) in POST_BOOT_IO_REGISTERS.iter() {
+            self.write(address, data);
+        }
+        self.nvic.set_it_enable(0x00);
+        self.apu.skip_boot();
+
+        // writing DIV resets it and LY is read only, both are set directly
+        match model {
+            GameBoyModel::Dmg0 => {
+                self.timer.divider = 0x18;
+                self.gpu.skip_to_vblank(145, 1);
+            }
+            GameBoyModel::Dmg | GameBoyModel::Mgb => {
+                self.timer.divider = 0xAB;
+                // the last line of the period, LY already reads 0
+                self.gpu.skip_to_vblank(0, 9);
+            }
+        }
+    }
+
+    pub fn take_fault(&mut self) -> Option<EmulatorError> {
+        self.fault.take()
+    }
+
+    // read the bus on behalf of the host, unknown registers are not reported as faults
+    pub fn peek(&self, address: u16) -> u8 {
+        let data = self.read(address);
+        self.fault.set(None);
+        data
+    }
+
+    pub fn set_serial_device(&mut self, device: Box<dyn SerialDevice>) {
+        self.serial.set_device(device);
+    }
+
+    pub fn get_cartridge_header(&self) -> &CartridgeHeader {
+        self.cartridge.get_header()
+    }
+
+    pub fn take_rumble(&mut self) -> Option<bool> {
+        self.cartridge.take_rumble()
+    }
+
+    pub fn advance_rtc(&mut self, seconds: i64) {
+        self.cartridge.advance_rtc(seconds);
+    }
+
+    pub fn set_tilt(&mut self, x: f32, y: f32) {
+        self.cartridge.set_tilt(x, y);
+    }
+
+    pub fn attach_save_file(&mut self, save_path: PathBuf) -> std::io::Result<bool> {
+        self.cartridge.attach_save_file(save_path)
+    }
+
+    pub fn flush_save_file(&mut self) -> std::io::Result<()> {
+        self.cartridge.flush_save_file()
     }
 
     fn read_io_register(&self, address: usize) -> u8 {
         match address {
             0xFF00 => self.keypad.get(),
-            0xFF01 => 0, // TODO: serial
-            0xFF02 => 0, // TODO: serial
+            0xFF01 => self.serial.get_data(),
+            0xFF02 => self.serial.control_to_byte(),
             0xFF04 => self.timer.get_divider(),
             0xFF05 => self.timer.get_value(),
             0xFF06 => self.timer.get_modulo(),
+            0xFF07 => self.timer.settings_to_byte(),
             0xFF0F => self.nvic.get_it_flag(),
-            0xFF10 => 0xFF, // Channel 1 Sweep register 
-            0xFF11 => 0xFF, /* Channel 1 Sound Length and Wave */ 
-            0xFF12 => 0xFF, /* Channel 1 Sound Control */ 
-            0xFF13 => 0xFF, /* Channel 1 Frequency lo */ 
-            0xFF14 => 0xFF, /* Channel 1 Control */ 
-            0xFF16 => 0xFF, /* Channel 2 Sound Control */ 
-            0xFF17 => 0xFF, /* Channel 2 Sound Control */ 
-            0xFF18 => 0xFF, /* Channel 2 Sound Control */ 
-            0xFF19 => 0xFF, /* Channel 2 Frequency hi data*/ 
-            0xFF1A => 0xFF, /* Channel 3 Sound on/off */ 
-            0xFF1B => 0xFF, /* Channel 3 Sound on/off */ 
-            0xFF1C => 0xFF, /* Channel 3 Sound on/off */ 
-            0xFF1D => 0xFF, /* Channel 3 Sound on/off */ 
-            0xFF1E => 0xFF, /* Channel 3 Sound on/off */ 
-            0xFF20 => 0xFF, /* Channel 4 Volumn */ 
-            0xFF21 => 0xFF, /* Channel 4 Volumn */ 
-            0xFF22 => 0xFF, /* Channel 4 Volumn */ 
-            0xFF23 => 0xFF, /* Channel 4 Counter/consecutive */ 
-            0xFF24 => 0xFF, /* Sound  Volume */ 
-            0xFF25 => 0xFF, /* Sound output terminal selection */ 
-            0xFF26 => 0xFF, /* Sound on/off */ 
-            0xff30..=0xff3f => 0xFF, //Wave Pattern RAM
+            0xFF10..=0xFF3F => self.apu.read_register(address as u16),
             0xFF40 => self.gpu.control_to_byte(),
             0xFF41 => self.gpu.status_to_byte(),
             0xFF42 => self.gpu.get_scy(),
             0xFF43 => self.gpu.get_scx(),
             0xFF44 => self.gpu.get_current_line(),
             0xFF45 => self.gpu.get_compare_line(),
+            0xFF47 => self.gpu.get_background_palette(),
+            0xFF48 => self.gpu.get_object_palette_0(),
+            0xFF49 => self.gpu.get_object_palette_1(),
             0xFF4A => self.gpu.get_window_y(),
             0xFF4B => self.gpu.get_window_x(),
             0xFF4D => 0xFF, // CGB SPEED SWITCH register, not supported
-            0xFF48 => 0xFF, // pokemon tries to read this registers
-            0xFF49 => 0xFF, // pokemon tries to read this registers
-            _ => panic!("Reading from an unknown I/O register {:x}", address),
+            _ => {
+                // unmapped registers read as 0xFF
+                self.fault.set(Some(EmulatorError::UnknownIoRead(address as u16)));
+                0xFF
+            }
         }
     }
 
     fn write_io_register(&mut self, address: usize, data: u8) {
         match address {
             0xFF00 => self.keypad.control(data),
-            0xFF01 => { /* Serial Transfer */ }
-            0xFF02 => { /* Serial Transfer Control */ }
+            0xFF01 => self.serial.set_data(data),
+            0xFF02 => self.serial.control_from_byte(data),
             0xFF04 => self.timer.set_divider(),
             0xFF05 => self.timer.set_value(data),
             0xFF06 => self.timer.set_modulo(data),
             0xFF07 => self.timer.settings_from_byte(data),
             0xFF0F => self.nvic.set_it_flag(data),
-            0xFF10 => { /* Channel 1 Sweep register */ }
-            0xFF11 => { /* Channel 1 Sound Length and Wave */ }
-            0xFF12 => { /* Channel 1 Sound Control */ }
-            0xFF13 => { /* Channel 1 Frequency lo */ }
-            0xFF14 => { /* Channel 1 Control */ }
-            0xFF16 => { /* Channel 2 Sound Control */ }
-            0xFF17 => { /* Channel 2 Sound Control */ }
-            0xFF18 => { /* Channel 2 Sound Control */ }
-            0xFF19 => { /* Channel 2 Frequency hi data*/ }
-            0xFF1A => { /* Channel 3 Sound on/off */ }
-            0xFF1B => { /* Channel 3 Sound on/off */ }
-            0xFF1C => { /* Channel 3 Sound on/off */ }
-            0xFF1D => { /* Channel 3 Sound on/off */ }
-            0xFF1E => { /* Channel 3 Sound on/off */ }
-            0xFF20 => { /* Channel 4 Volumn */ }
-            0xFF21 => { /* Channel 4 Volumn */ }
-            0xFF22 => { /* Channel 4 Volumn */ }
-            0xFF23 => { /* Channel 4 Counter/consecutive */ }
-            0xFF24 => { /* Sound  Volume */ }
-            0xFF25 => { /* Sound output terminal selection */ }
-            0xFF26 => { /* Sound on/off */ }
-            0xff30..=0xff3f => { /* Wave Pattern RAM */ }
+            0xFF10..=0xFF3F => self.apu.write_register(address as u16, data),
             0xFF40 => self.gpu.control_from_byte(data),
             0xFF41 => self.gpu.status_from_byte(data),
             0xFF42 => self.gpu.set_scy(data),
@@ -236,10 +308,10 @@ impl Peripheral {
             0xFF7f => {
                 // Writing to here does nothing
             }
-            _ => panic!(
-                "Writting '0b{:b}' to an unknown I/O register {:x}",
-                data, address
-            ),
+            _ => {
+                // writes to unmapped registers are ignored
+                self.fault.set(Some(EmulatorError::UnknownIoWrite(address as u16, data)));
+            }
         }
     }
 }
@@ -312,6 +384,44 @@ impl Interrupt for Peripheral {
     }
 }
 
+impl SaveState for Peripheral {
+    fn save_state(&self, writer: &mut StateWriter) {
+        self.boot_rom.save_state(writer);
+        self.cartridge.save_state(writer);
+        writer.write_bytes(&self.working_ram);
+        writer.write_bytes(&self.zero_page);
+        self.gpu.save_state(writer);
+        self.nvic.save_state(writer);
+        self.apu.save_state(writer);
+        self.serial.save_state(writer);
+        self.timer.save_state(writer);
+        self.keypad.save_state(writer);
+        // dma
+        writer.write_u8(self.dma_cycles);
+        writer.write_u16(self.dma_start_adress);
+        writer.write_bool(self.dma_enabled);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> std::io::Result<()> {
+        self.boot_rom.load_state(reader)?;
+        self.cartridge.load_state(reader)?;
+        reader.read_bytes_into(&mut self.working_ram)?;
+        reader.read_bytes_into(&mut self.zero_page)?;
+        self.gpu.load_state(reader)?;
+        self.nvic.load_state(reader)?;
+        self.apu.load_state(reader)?;
+        self.serial.load_state(reader)?;
+        self.timer.load_state(reader)?;
+        self.keypad.load_state(reader)?;
+        // dma
+        self.dma_cycles = reader.read_u8()?;
+        self.dma_start_adress = reader.read_u16()?;
+        self.dma_enabled = reader.read_bool()?;
+
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod peripheral_tests {
     use super::*;
@@ -323,7 +433,7 @@ mod peripheral_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Peripheral::new(Cartridge::new(&rom).unwrap());
         peripheral.write(0x0001 + 0xC000, 0xAA);
         peripheral.write(0x0002 + 0xC000, 0x55);
         peripheral.write(0x0010 + 0xC000, 0xAA);
@@ -338,7 +448,7 @@ mod peripheral_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Peripheral::new(Cartridge::new(&rom).unwrap());
         peripheral.write(0x0001 + VRAM_BEGIN, 0xAA);
         peripheral.write(0x0002 + VRAM_BEGIN, 0x55);
         peripheral.write(0x0010 + VRAM_BEGIN, 0xAA);
@@ -353,7 +463,7 @@ mod peripheral_tests {
         rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
         rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
-        let mut peripheral = Peripheral::new(Cartridge::new(&rom));
+        let mut peripheral = Peripheral::new(Cartridge::new(&rom).unwrap());
         let address = 0xC000;
         // init data
         peripheral.write(address, 0xAA);
@@ -373,4 +483,52 @@ mod peripheral_tests {
         assert_eq!(peripheral.gpu.read_oam(0x7F), 0xAA);
         assert_eq!(peripheral.gpu.read_oam(0x9F), 0x55);
     }
+
+    #[test]
+    fn test_serial_transfer() {
+        let mut rom = [0xFF; 0x8000];
+        rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
+        rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
+        rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
+        let mut peripheral = Peripheral::new(Cartridge::new(&rom).unwrap());
+        peripheral.set_serial_device(Box::new(serial::Loopback));
+
+        // start a transfer with the internal clock
+        peripheral.write(0xFF01, 0x3C);
+        peripheral.write(0xFF02, 0x81);
+        assert_eq!(peripheral.read(0xFF02), 0xFF);
+
+        // run peripheral for 8 bits at 8192 Hz
+        for _ in 0..8 * 512 / 4 {
+            peripheral.run(4);
+        }
+
+        assert_eq!(peripheral.read(0xFF01), 0x3C);
+        assert_eq!(peripheral.read(0xFF02), 0x7F);
+        assert_eq!(peripheral.nvic.get_it_flag() & (1 << InterruptSources::SERIAL as u8), 1 << InterruptSources::SERIAL as u8);
+    }
+
+    #[test]
+    fn test_read_write_io_registers() {
+        let mut rom = [0xFF; 0x8000];
+        rom[CARTRIDGE_TYPE_OFFSET as usize] = 0x00;
+        rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
+        rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = 0x00;
+        let mut peripheral = Peripheral::new(Cartridge::new(&rom).unwrap());
+
+        // the timer control and the palettes read back without fault
+        peripheral.write(0xFF07, 0x06);
+        peripheral.write(0xFF47, 0xE4);
+        peripheral.write(0xFF48, 0xD2);
+        peripheral.write(0xFF49, 0x1B);
+        assert_eq!(peripheral.read(0xFF07), 0xFE);
+        assert_eq!(peripheral.read(0xFF47), 0xE4);
+        assert_eq!(peripheral.read(0xFF48), 0xD2);
+        assert_eq!(peripheral.read(0xFF49), 0x1B);
+        assert_eq!(peripheral.take_fault(), None);
+
+        // unmapped registers are still reported
+        assert_eq!(peripheral.read(0xFF03), 0xFF);
+        assert_eq!(peripheral.take_fault(), Some(EmulatorError::UnknownIoRead(0xFF03)));
+    }
 }
\ No newline at end of file
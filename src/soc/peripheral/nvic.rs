@@ -1,3 +1,5 @@
+use crate::savestate::{SaveState, StateWriter, StateReader};
+
 #[derive(Copy, Clone, PartialEq, Debug)]
 pub enum InterruptSources {
     VBLANK,
@@ -29,6 +31,8 @@ impl Nvic {
         self.interrupt_master_enable = enable;
     }
 
+    // the cpu enables the interrupts through the IE register, the tests go straight to the source
+    #[cfg(test)]
     pub fn enable_interrupt(&mut self, source: InterruptSources, enable: bool) {
         if enable {
             self.interrupt_enable |= (1 as u8) << (source as u8);
@@ -103,6 +107,22 @@ impl Nvic {
     }
 }
 
+impl SaveState for Nvic {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bool(self.interrupt_master_enable);
+        writer.write_u8(self.interrupt_enable);
+        writer.write_u8(self.interrupt_flag);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> std::io::Result<()> {
+        self.interrupt_master_enable = reader.read_bool()?;
+        self.interrupt_enable = reader.read_u8()?;
+        self.interrupt_flag = reader.read_u8()?;
+
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod nvic_tests {
     use super::*;
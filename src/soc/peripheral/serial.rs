@@ -0,0 +1,262 @@
+use crate::soc::peripheral::nvic::{Nvic, InterruptSources};
+use crate::savestate::{SaveState, StateWriter, StateReader};
+use std::sync::{Arc, Mutex};
+
+// the internal clock runs at 8192 Hz, one bit is shifted every 512 cycles
+const SERIAL_CYCLES_PER_BIT: u16 = 512;
+const SERIAL_BITS_PER_TRANSFER: u8 = 8;
+
+// value shifted in when nothing drives the serial line
+pub const SERIAL_DISCONNECTED_BYTE: u8 = 0xFF;
+
+const SC_TRANSFER_START_BIT: u8 = 7;
+const SC_CLOCK_SELECT_BIT: u8 = 0;
+const SC_UNUSED_BITS: u8 = 0x7E;
+
+// the other end of the link cable, it is sent along with the emulator to other threads
+pub trait SerialDevice: Send {
+    // the gameboy drives the clock: the device receives the byte sent
+    // and returns the byte it sends back during the same transfer
+    fn transfer(&mut self, data: u8) -> u8;
+
+    // the device drives the clock: called while the gameboy waits with a byte ready to be sent,
+    // returns the byte sent by the device once it has started a transfer
+    fn poll_external(&mut self, data: u8) -> Option<u8>;
+
+    // the gameboy has cleared the start bit while it was waiting for the device clock,
+    // the byte offered by the last poll is not available anymore
+    fn cancel_external(&mut self) {}
+}
+
+// no cable plugged in
+pub struct Disconnected;
+
+impl SerialDevice for Disconnected {
+    fn transfer(&mut self, _data: u8) -> u8 {
+        SERIAL_DISCONNECTED_BYTE
+    }
+
+    fn poll_external(&mut self, _data: u8) -> Option<u8> {
+        None
+    }
+}
+
+// the serial output is wired to the serial input
+pub struct Loopback;
+
+impl SerialDevice for Loopback {
+    fn transfer(&mut self, data: u8) -> u8 {
+        data
+    }
+
+    fn poll_external(&mut self, _data: u8) -> Option<u8> {
+        None
+    }
+}
+
+// record every byte sent by the gameboy, used by test roms to report their results
+pub struct SerialCapture {
+    bytes: Arc<Mutex<Vec<u8>>>,
+}
+
+impl SerialCapture {
+    pub fn new(bytes: Arc<Mutex<Vec<u8>>>) -> SerialCapture {
+        SerialCapture {
+            bytes,
+        }
+    }
+}
+
+impl SerialDevice for SerialCapture {
+    fn transfer(&mut self, data: u8) -> u8 {
+        self.bytes.lock().unwrap().push(data);
+        SERIAL_DISCONNECTED_BYTE
+    }
+
+    fn poll_external(&mut self, _data: u8) -> Option<u8> {
+        None
+    }
+}
+
+pub struct Serial {
+    // SB / SC registers
+    data: u8,
+    transfer_start: bool,
+    internal_clock: bool,
+    // shift register state
+    shifting: bool,
+    incoming_data: u8,
+    remaining_bits: u8,
+    cycles: u16,
+    device: Box<dyn SerialDevice>,
+}
+
+impl Serial {
+    pub fn new() -> Serial {
+        Serial {
+            data: 0x00,
+            transfer_start: false,
+            internal_clock: false,
+            shifting: false,
+            incoming_data: SERIAL_DISCONNECTED_BYTE,
+            remaining_bits: 0,
+            cycles: 0,
+            device: Box::new(Disconnected),
+        }
+    }
+
+    pub fn set_device(&mut self, device: Box<dyn SerialDevice>) {
+        self.device = device;
+    }
+
+    pub fn run(&mut self, cycles: u8, nvic: &mut Nvic) {
+        if self.transfer_start && !self.shifting {
+            // exchange the bytes with the other end when the transfer begins
+            let incoming_data = if self.internal_clock {
+                Some(self.device.transfer(self.data))
+            } else {
+                self.device.poll_external(self.data)
+            };
+
+            if let Some(incoming_data) = incoming_data {
+                self.incoming_data = incoming_data;
+                self.remaining_bits = SERIAL_BITS_PER_TRANSFER;
+                self.cycles = 0;
+                self.shifting = true;
+            }
+        }
+
+        if self.shifting {
+            // both ends shift their bits at the 8192 Hz rate of the master clock
+            self.cycles += cycles as u16;
+            while self.cycles >= SERIAL_CYCLES_PER_BIT && self.remaining_bits > 0 {
+                self.cycles -= SERIAL_CYCLES_PER_BIT;
+                self.data = (self.data << 1) | (self.incoming_data >> 7);
+                self.incoming_data <<= 1;
+                self.remaining_bits -= 1;
+            }
+
+            if self.remaining_bits == 0 {
+                self.shifting = false;
+                self.transfer_start = false;
+                nvic.set_interrupt(InterruptSources::SERIAL);
+            }
+        }
+    }
+
+    pub fn get_data(&self) -> u8 {
+        self.data
+    }
+
+    pub fn set_data(&mut self, data: u8) {
+        self.data = data;
+    }
+
+    pub fn control_to_byte(&self) -> u8 {
+        SC_UNUSED_BITS
+            | (self.transfer_start as u8) << SC_TRANSFER_START_BIT
+            | (self.internal_clock as u8) << SC_CLOCK_SELECT_BIT
+    }
+
+    pub fn control_from_byte(&mut self, data: u8) {
+        self.transfer_start = (data >> SC_TRANSFER_START_BIT) & 0x01 != 0;
+        self.internal_clock = (data >> SC_CLOCK_SELECT_BIT) & 0x01 != 0;
+
+        // clearing the start bit aborts the current transfer
+        if !self.transfer_start {
+            self.shifting = false;
+            self.device.cancel_external();
+        }
+    }
+}
+
+impl SaveState for Serial {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(self.data);
+        writer.write_bool(self.transfer_start);
+        writer.write_bool(self.internal_clock);
+        writer.write_bool(self.shifting);
+        writer.write_u8(self.incoming_data);
+        writer.write_u8(self.remaining_bits);
+        writer.write_u16(self.cycles);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> std::io::Result<()> {
+        self.data = reader.read_u8()?;
+        self.transfer_start = reader.read_bool()?;
+        self.internal_clock = reader.read_bool()?;
+        self.shifting = reader.read_bool()?;
+        self.incoming_data = reader.read_u8()?;
+        self.remaining_bits = reader.read_u8()?;
+        self.cycles = reader.read_u16()?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod serial_tests {
+    use super::*;
+
+    fn run_cycles(serial: &mut Serial, nvic: &mut Nvic, cycles: usize) {
+        for _ in 0..cycles / 4 {
+            serial.run(4, nvic);
+        }
+    }
+
+    #[test]
+    fn test_internal_clock_transfer() {
+        let mut serial = Serial::new();
+        let mut nvic = Nvic::new();
+        let bytes = Arc::new(Mutex::new(Vec::new()));
+        serial.set_device(Box::new(SerialCapture::new(Arc::clone(&bytes))));
+
+        serial.set_data(0x42);
+        serial.control_from_byte(0x81);
+        assert_eq!(serial.control_to_byte(), 0xFF);
+
+        // the transfer lasts 8 bits at 8192 Hz
+        run_cycles(&mut serial, &mut nvic, 8 * 512 - 4);
+        assert_eq!(serial.control_to_byte(), 0xFF);
+        assert_eq!(nvic.interrupt_flag, 0x00);
+        run_cycles(&mut serial, &mut nvic, 4);
+        assert_eq!(serial.control_to_byte(), 0x7F);
+        assert_eq!(nvic.interrupt_flag, 1 << InterruptSources::SERIAL as u8);
+
+        assert_eq!(*bytes.lock().unwrap(), vec![0x42]);
+        assert_eq!(serial.get_data(), SERIAL_DISCONNECTED_BYTE);
+    }
+
+    #[test]
+    fn test_loopback() {
+        let mut serial = Serial::new();
+        let mut nvic = Nvic::new();
+        serial.set_device(Box::new(Loopback));
+
+        serial.set_data(0xA5);
+        serial.control_from_byte(0x81);
+        // bits are shifted in msb first
+        run_cycles(&mut serial, &mut nvic, 4 * 512);
+        assert_eq!(serial.get_data(), 0x5A);
+        run_cycles(&mut serial, &mut nvic, 4 * 512);
+        assert_eq!(serial.get_data(), 0xA5);
+    }
+
+    #[test]
+    fn test_external_clock() {
+        let mut serial = Serial::new();
+        let mut nvic = Nvic::new();
+
+        // nobody drives the clock, the transfer never ends
+        serial.set_data(0x42);
+        serial.control_from_byte(0x80);
+        run_cycles(&mut serial, &mut nvic, 16 * 512);
+        assert_eq!(serial.control_to_byte(), 0xFE);
+        assert_eq!(serial.get_data(), 0x42);
+        assert_eq!(nvic.interrupt_flag, 0x00);
+
+        // the transfer can be aborted
+        serial.control_from_byte(0x00);
+        assert_eq!(serial.control_to_byte(), 0x7E);
+    }
+}
@@ -1,5 +1,6 @@
 use crate::soc::peripheral::nvic::{Nvic, InterruptSources};
 use crate::soc::CLOCK_TICK_PER_MACHINE_CYCLE;
+use crate::savestate::{SaveState, StateWriter, StateReader, invalid_state};
 
 pub enum Frequency {
     F4096,
@@ -20,6 +21,25 @@ impl Frequency {
             Frequency::F65536 => 64,
         }
     }
+
+    fn to_byte(&self) -> u8 {
+        match self {
+            Frequency::F4096 => 0,
+            Frequency::F262144 => 1,
+            Frequency::F65536 => 2,
+            Frequency::F16384 => 3,
+        }
+    }
+
+    fn from_byte(data: u8) -> Option<Frequency> {
+        match data {
+            0 => Some(Frequency::F4096),
+            1 => Some(Frequency::F262144),
+            2 => Some(Frequency::F65536),
+            3 => Some(Frequency::F16384),
+            _ => None,
+        }
+    }
 }
 
 pub struct Timer {
@@ -112,8 +132,10 @@ impl Timer {
         self.main_timer_cycles / CLOCK_TICK_PER_MACHINE_CYCLE as usize > 0
     }
 
+    // writing the register resets the whole internal counter
     pub fn set_divider(&mut self) {
         self.divider = 0;
+        self.divider_timer_cycles = 0;
     }
 
     pub fn get_divider(&self) -> u8 {
@@ -144,10 +166,43 @@ impl Timer {
         self.main_timer_frequency = match data & 0x03 {
             0x00 => Frequency::F4096,
             0x01 => Frequency::F262144,
-            0x10 => Frequency::F65536,
+            0x02 => Frequency::F65536,
             _ => Frequency::F16384,
         };
     }
+
+    pub fn settings_to_byte(&self) -> u8 {
+        // unused bits read as 1
+        0xF8 | ((self.enabled as u8) << 2) | self.main_timer_frequency.to_byte()
+    }
+}
+
+impl SaveState for Timer {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u64(self.main_timer_cycles as u64);
+        writer.write_u64(self.divider_timer_cycles as u64);
+        writer.write_bool(self.tima_overflow);
+        writer.write_u8(self.divider);
+        writer.write_u8(self.value);
+        writer.write_u8(self.modulo);
+        writer.write_u8(self.main_timer_frequency.to_byte());
+        writer.write_u8(self.divider_timer_frequency.to_byte());
+        writer.write_bool(self.enabled);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> std::io::Result<()> {
+        self.main_timer_cycles = reader.read_u64()? as usize;
+        self.divider_timer_cycles = reader.read_u64()? as usize;
+        self.tima_overflow = reader.read_bool()?;
+        self.divider = reader.read_u8()?;
+        self.value = reader.read_u8()?;
+        self.modulo = reader.read_u8()?;
+        self.main_timer_frequency = Frequency::from_byte(reader.read_u8()?).ok_or_else(|| invalid_state("unknown timer frequency"))?;
+        self.divider_timer_frequency = Frequency::from_byte(reader.read_u8()?).ok_or_else(|| invalid_state("unknown timer frequency"))?;
+        self.enabled = reader.read_bool()?;
+
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -198,4 +253,14 @@ mod timer_tests {
 
         assert_eq!(timer.value, 0xF5);
     }
+
+    #[test]
+    fn test_timer_settings() {
+        let mut timer = Timer::new();
+
+        for data in 0x00..=0x07 {
+            timer.settings_from_byte(data);
+            assert_eq!(timer.settings_to_byte(), 0xF8 | data);
+        }
+    }
 }
\ No newline at end of file
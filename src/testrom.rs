@@ -0,0 +1,181 @@
+use crate::emulator::{Emulator, SerialCapture, ONE_FRAME_IN_CYCLES};
+use std::sync::{Arc, Mutex};
+
+// mooneye tests run LD B,B once done, the registers hold the result
+const LD_B_B_OPCODE: u8 = 0x40;
+const MOONEYE_PASSED_REGISTERS: [u8; 6] = [3, 5, 8, 13, 21, 34];
+const MOONEYE_FAILED_REGISTER: u8 = 0x42;
+
+// blargg tests write their status in the cartridge ram once the signature is valid
+const BLARGG_STATUS_ADDRESS: u16 = 0xA000;
+const BLARGG_SIGNATURE_ADDRESS: u16 = 0xA001;
+const BLARGG_SIGNATURE: [u8; 3] = [0xDE, 0xB0, 0x61];
+const BLARGG_TEXT_ADDRESS: u16 = 0xA004;
+const BLARGG_RUNNING_STATUS: u8 = 0x80;
+const BLARGG_TEXT_MAX_LENGTH: u16 = 0x1000;
+
+/// Outcome of a test rom.
+#[derive(Clone, PartialEq, Debug)]
+pub enum TestRomResult {
+    Passed,
+    /// The test has reported a failure, with the text it has printed.
+    Failed(String),
+    /// The test has not completed in time, with the text printed so far.
+    Timeout(String),
+}
+
+/// Run a blargg or mooneye test rom until it reports its result, or for `max_frames` frames.
+///
+/// The result is read from the serial output ("Passed" / "Failed") or the 0xA000 signature
+/// protocol of blargg tests, and from the registers set before the LD B,B instruction of
+/// mooneye tests. The serial device of the emulator is replaced to capture the output.
+pub fn run_test_rom(emulator: &mut Emulator, max_frames: usize) -> TestRomResult {
+    let serial_output = Arc::new(Mutex::new(Vec::new()));
+    emulator.set_serial_device(Box::new(SerialCapture::new(Arc::clone(&serial_output))));
+
+    for _ in 0..max_frames {
+        let mut cycles = 0;
+        while cycles < ONE_FRAME_IN_CYCLES {
+            if let Some(result) = get_mooneye_result(emulator) {
+                return result;
+            }
+
+            cycles += emulator.step_instruction().cycles;
+
+            if let Some(error) = emulator.get_fault() {
+                return TestRomResult::Failed(error.to_string());
+            }
+        }
+
+        let text = get_text(&serial_output.lock().unwrap());
+        if text.contains("Passed") {
+            return TestRomResult::Passed;
+        }
+        if text.contains("Failed") {
+            return TestRomResult::Failed(text);
+        }
+
+        if let Some(result) = get_blargg_memory_result(emulator) {
+            return result;
+        }
+
+        if emulator.is_locked_up() {
+            return TestRomResult::Failed(String::from("cpu locked up"));
+        }
+    }
+
+    let text = get_text(&serial_output.lock().unwrap());
+    TestRomResult::Timeout(text)
+}
+
+fn get_mooneye_result(emulator: &Emulator) -> Option<TestRomResult> {
+    let cpu = &emulator.soc.cpu;
+    if emulator.read_memory(cpu.pc) != LD_B_B_OPCODE {
+        return None;
+    }
+
+    let registers = [cpu.registers.b, cpu.registers.c, cpu.registers.d, cpu.registers.e, cpu.registers.h, cpu.registers.l];
+    if registers == MOONEYE_PASSED_REGISTERS {
+        Some(TestRomResult::Passed)
+    } else if registers.iter().all(|&register| register == MOONEYE_FAILED_REGISTER) {
+        Some(TestRomResult::Failed(String::from("mooneye test failed")))
+    } else {
+        // a LD B,B which is not the end of a test
+        None
+    }
+}
+
+fn get_blargg_memory_result(emulator: &Emulator) -> Option<TestRomResult> {
+    let signature: Vec<u8> = (0..3).map(|offset| emulator.read_memory(BLARGG_SIGNATURE_ADDRESS + offset)).collect();
+    if signature != BLARGG_SIGNATURE {
+        return None;
+    }
+
+    match emulator.read_memory(BLARGG_STATUS_ADDRESS) {
+        BLARGG_RUNNING_STATUS => None,
+        0x00 => Some(TestRomResult::Passed),
+        status => {
+            // the text printed by the test is a null terminated string
+            let text: Vec<u8> = (BLARGG_TEXT_ADDRESS..BLARGG_TEXT_ADDRESS + BLARGG_TEXT_MAX_LENGTH)
+                .map(|address| emulator.read_memory(address))
+                .take_while(|&data| data != 0x00)
+                .collect();
+            Some(TestRomResult::Failed(format!("status {:#04x}: {}", status, get_text(&text))))
+        }
+    }
+}
+
+fn get_text(bytes: &[u8]) -> String {
+    String::from_utf8_lossy(bytes).into_owned()
+}
+
+#[cfg(test)]
+mod testrom_tests {
+    use super::*;
+    use crate::error::FaultPolicy;
+    use crate::cartridge::{CARTRIDGE_TYPE_OFFSET, CARTRIDGE_RAM_SIZE_OFFSET, CARTRIDGE_ROM_SIZE_OFFSET};
+
+    fn build_emulator(program: &[u8], cartridge_type: u8, ram_size: u8) -> Emulator {
+        let mut boot_rom = [0x00; 256];
+        boot_rom[0..program.len()].copy_from_slice(program);
+        let mut rom = [0xFF; 0x8000];
+        rom[CARTRIDGE_TYPE_OFFSET as usize] = cartridge_type;
+        rom[CARTRIDGE_ROM_SIZE_OFFSET as usize] = 0x00;
+        rom[CARTRIDGE_RAM_SIZE_OFFSET as usize] = ram_size;
+
+        let mut emulator = Emulator::new(&boot_rom, &rom).unwrap();
+        emulator.set_fault_policy(FaultPolicy::Halt);
+        emulator
+    }
+
+    // send each byte of the text over the serial port, then loop
+    fn serial_program(text: &str) -> Vec<u8> {
+        let mut program = Vec::new();
+        for &data in text.as_bytes() {
+            // ld a,data / ldh (SB),a / ld a,0x81 / ldh (SC),a / ldh a,(SC) / bit 7,a / jr nz,-6
+            program.extend_from_slice(&[0x3E, data, 0xE0, 0x01, 0x3E, 0x81, 0xE0, 0x02, 0xF0, 0x02, 0xCB, 0x7F, 0x20, 0xFA]);
+        }
+        program.extend_from_slice(&[0x18, 0xFE]);
+        program
+    }
+
+    #[test]
+    fn test_blargg_serial() {
+        let mut emulator = build_emulator(&serial_program("Passed"), 0x00, 0x00);
+        assert_eq!(run_test_rom(&mut emulator, 10), TestRomResult::Passed);
+
+        let mut emulator = build_emulator(&serial_program("Failed"), 0x00, 0x00);
+        assert_eq!(run_test_rom(&mut emulator, 10), TestRomResult::Failed(String::from("Failed")));
+
+        let mut emulator = build_emulator(&serial_program("01:ok"), 0x00, 0x00);
+        assert_eq!(run_test_rom(&mut emulator, 10), TestRomResult::Timeout(String::from("01:ok")));
+    }
+
+    #[test]
+    fn test_blargg_memory() {
+        // enable the ram / write the signature / write the status / jr -2
+        let program = [
+            0x3E, 0x0A, 0xEA, 0x00, 0x00,
+            0x3E, 0xDE, 0xEA, 0x01, 0xA0,
+            0x3E, 0xB0, 0xEA, 0x02, 0xA0,
+            0x3E, 0x61, 0xEA, 0x03, 0xA0,
+            0x3E, 0x41, 0xEA, 0x04, 0xA0,
+            0xAF, 0xEA, 0x05, 0xA0,
+            0x3E, 0x01, 0xEA, 0x00, 0xA0,
+            0x18, 0xFE,
+        ];
+        let mut emulator = build_emulator(&program, 0x03, 0x02);
+        assert_eq!(run_test_rom(&mut emulator, 10), TestRomResult::Failed(String::from("status 0x01: A")));
+    }
+
+    #[test]
+    fn test_mooneye() {
+        // ld b,3 / ld c,5 / ld d,8 / ld e,13 / ld h,21 / ld l,34 / ld b,b
+        let mut emulator = build_emulator(&[0x06, 3, 0x0E, 5, 0x16, 8, 0x1E, 13, 0x26, 21, 0x2E, 34, 0x40], 0x00, 0x00);
+        assert_eq!(run_test_rom(&mut emulator, 10), TestRomResult::Passed);
+
+        // ld a,0x42 / ld b,a / ld c,a / ld d,a / ld e,a / ld h,a / ld l,a / ld b,b
+        let mut emulator = build_emulator(&[0x3E, 0x42, 0x47, 0x4F, 0x57, 0x5F, 0x67, 0x6F, 0x40], 0x00, 0x00);
+        assert_eq!(run_test_rom(&mut emulator, 10), TestRomResult::Failed(String::from("mooneye test failed")));
+    }
+}
@@ -0,0 +1,125 @@
+// runs every test rom found in the test_roms directory (or in the directory given by the
+// QOBOY_TEST_ROMS environment variable) and fails on any unexpected result:
+//
+// test_roms/
+//   dmg_boot.bin    boot rom used to start the test roms, optional: without it the roms
+//                   start in the state left by the boot rom of the dmg
+//   blargg/...      blargg test roms, e.g. cpu_instrs/cpu_instrs.gb
+//   mooneye/...     mooneye test roms, e.g. acceptance/div_timing.gb
+//   dmg-acid2.gb    roms with a png file next to them are screenshot tests, the last
+//   dmg-acid2.png   frame is compared with the png file and a diff image is written on mismatch
+//
+// roms listed in known_failures.txt are expected to fail, they are only reported when they pass
+
+use qoboy::{Emulator, EmulatorError, FaultPolicy, GameBoyModel};
+use qoboy::testrom::{run_test_rom, TestRomResult};
+use qoboy::headless::{run_headless, HeadlessStatus, InputScript};
+use qoboy::screenshot::{compare_screenshot, ReferencePalette};
+use std::path::{Path, PathBuf};
+
+// blargg's cpu_instrs takes about one minute to complete
+const MAX_FRAMES: usize = 60 * 120;
+// screenshot tests are expected to draw their final frame within a few seconds
+const SCREENSHOT_FRAMES: usize = 60 * 5;
+
+fn get_test_roms_dir() -> PathBuf {
+    match std::env::var_os("QOBOY_TEST_ROMS") {
+        Some(dir) => PathBuf::from(dir),
+        None => Path::new(env!("CARGO_MANIFEST_DIR")).join("test_roms"),
+    }
+}
+
+fn find_roms(dir: &Path, roms: &mut Vec<PathBuf>) {
+    let mut entries: Vec<PathBuf> = std::fs::read_dir(dir).unwrap().map(|entry| entry.unwrap().path()).collect();
+    entries.sort();
+
+    for path in entries {
+        if path.is_dir() {
+            find_roms(&path, roms);
+        } else if path.extension().map_or(false, |extension| extension == "gb") {
+            roms.push(path);
+        }
+    }
+}
+
+fn get_known_failures() -> Vec<String> {
+    include_str!("known_failures.txt")
+        .lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(String::from)
+        .collect()
+}
+
+fn create_emulator(boot_rom: Option<&[u8]>, rom: &[u8]) -> Result<Emulator, EmulatorError> {
+    match boot_rom {
+        Some(boot_rom) => Emulator::new(boot_rom, rom),
+        None => Emulator::new_without_boot_rom(rom, GameBoyModel::Dmg),
+    }
+}
+
+// run the rom then compare its last frame with the reference image
+fn run_screenshot_test(emulator: &mut Emulator, reference_path: &Path) -> TestRomResult {
+    if let HeadlessStatus::Fault(error) = run_headless(emulator, SCREENSHOT_FRAMES, &InputScript::new()) {
+        return TestRomResult::Failed(error.to_string());
+    }
+
+    let diff_path = reference_path.with_extension("diff.png");
+    match compare_screenshot(emulator.get_frame(), reference_path, &ReferencePalette::GRAYSCALE, &diff_path) {
+        Ok(0) => TestRomResult::Passed,
+        Ok(different_pixels) => TestRomResult::Failed(format!("{} different pixels, see {}", different_pixels, diff_path.display())),
+        Err(message) => TestRomResult::Failed(message.to_string()),
+    }
+}
+
+#[test]
+fn test_roms() {
+    let roms_dir = get_test_roms_dir();
+    if !roms_dir.is_dir() {
+        println!("no test roms found in {}, skipped", roms_dir.display());
+        return;
+    }
+    let boot_rom = std::fs::read(roms_dir.join("dmg_boot.bin")).ok();
+
+    let mut roms = Vec::new();
+    find_roms(&roms_dir, &mut roms);
+    let known_failures = get_known_failures();
+
+    let mut unexpected_results = Vec::new();
+    for rom_path in roms {
+        let name = rom_path.strip_prefix(&roms_dir).unwrap().display().to_string();
+        let expect_failure = known_failures.iter().any(|pattern| name.contains(pattern.as_str()));
+
+        let rom = std::fs::read(&rom_path).unwrap();
+        let result = match create_emulator(boot_rom.as_deref(), &rom) {
+            Ok(mut emulator) => {
+                emulator.set_fault_policy(FaultPolicy::Halt);
+
+                let reference_path = rom_path.with_extension("png");
+                if reference_path.exists() {
+                    run_screenshot_test(&mut emulator, &reference_path)
+                } else {
+                    run_test_rom(&mut emulator, MAX_FRAMES)
+                }
+            }
+            Err(error) => TestRomResult::Failed(error.to_string()),
+        };
+
+        match result {
+            TestRomResult::Passed => {
+                println!("PASS {}", name);
+                if expect_failure {
+                    println!("     {} is listed in known_failures.txt but passes now", name);
+                }
+            }
+            TestRomResult::Failed(text) | TestRomResult::Timeout(text) => {
+                println!("FAIL {}: {}", name, text.trim());
+                if !expect_failure {
+                    unexpected_results.push(name);
+                }
+            }
+        }
+    }
+
+    assert!(unexpected_results.is_empty(), "unexpected failures: {:?}", unexpected_results);
+}